@@ -0,0 +1,9 @@
+-- A full-text index over document names and their rendered folder paths.
+-- It is maintained by the application, since the paths are derived from the
+-- `links` graph. The `doc_id` column is not indexed, just stored.
+CREATE VIRTUAL TABLE doc_search USING fts5(
+  doc_id UNINDEXED,
+  name,
+  paths,
+  tokenize = 'unicode61 remove_diacritics 2'
+);
@@ -0,0 +1 @@
+ALTER TABLE docs ADD COLUMN md5_checksum TEXT;
@@ -0,0 +1,2 @@
+DROP TABLE drives;
+-- SQLite cannot remove the docs.drive_id column, so leave it in place.
@@ -0,0 +1,8 @@
+CREATE TABLE drives (
+  account_id INTEGER NOT NULL,
+  id TEXT NOT NULL,
+  name TEXT NOT NULL,
+  PRIMARY KEY (account_id, id),
+  FOREIGN KEY (account_id) REFERENCES accounts(id)
+);
+ALTER TABLE docs ADD COLUMN drive_id TEXT;
@@ -0,0 +1,12 @@
+CREATE TABLE permissions (
+  doc_id TEXT NOT NULL,
+  id TEXT NOT NULL,
+  grantee_type TEXT NOT NULL,
+  role TEXT NOT NULL,
+  email_address TEXT,
+  domain TEXT,
+  display_name TEXT,
+  allow_file_discovery BOOLEAN NOT NULL,
+  PRIMARY KEY (doc_id, id),
+  FOREIGN KEY (doc_id) REFERENCES docs(id)
+);
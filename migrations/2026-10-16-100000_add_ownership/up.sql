@@ -0,0 +1,5 @@
+ALTER TABLE docs ADD COLUMN owner_email TEXT;
+ALTER TABLE docs ADD COLUMN owner_name TEXT;
+ALTER TABLE account_associations ADD COLUMN owned_by_me BOOLEAN NOT NULL DEFAULT 0;
+ALTER TABLE account_associations ADD COLUMN sharing_user_email TEXT;
+ALTER TABLE account_associations ADD COLUMN sharing_user_name TEXT;
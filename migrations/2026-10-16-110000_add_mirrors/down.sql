@@ -0,0 +1,2 @@
+DROP TABLE mirror_entries;
+DROP TABLE mirrors;
@@ -0,0 +1,22 @@
+CREATE TABLE mirrors (
+  id INTEGER PRIMARY KEY NOT NULL,
+  account_id INTEGER NOT NULL,
+  folder_id TEXT NOT NULL,
+  local_dir TEXT NOT NULL,
+  export_format TEXT,
+  UNIQUE (account_id, folder_id, local_dir),
+  FOREIGN KEY (account_id) REFERENCES accounts(id)
+);
+
+-- The doc_id is deliberately not a foreign key: the state of a mirrored file
+-- needs to outlive its row in `docs` so that we can tell that it went away.
+CREATE TABLE mirror_entries (
+  mirror_id INTEGER NOT NULL,
+  path TEXT NOT NULL,
+  doc_id TEXT NOT NULL,
+  remote_modified_time DATETIME NOT NULL,
+  local_modified_time BIGINT NOT NULL,
+  local_size BIGINT NOT NULL,
+  PRIMARY KEY (mirror_id, path),
+  FOREIGN KEY (mirror_id) REFERENCES mirrors(id)
+);
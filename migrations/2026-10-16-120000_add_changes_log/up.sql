@@ -0,0 +1,16 @@
+-- A history of the changes that synchronization has applied to the database.
+-- Rows are only ever added. The doc_id is deliberately not a foreign key, and
+-- the document's name is copied, so that entries outlive removed documents.
+CREATE TABLE changes_log (
+  id INTEGER PRIMARY KEY NOT NULL,
+  account_id INTEGER NOT NULL,
+  doc_id TEXT NOT NULL,
+  doc_name TEXT NOT NULL,
+  kind TEXT NOT NULL,
+  old_value TEXT,
+  new_value TEXT,
+  timestamp DATETIME NOT NULL,
+  FOREIGN KEY (account_id) REFERENCES accounts(id)
+);
+
+CREATE INDEX changes_log_timestamp ON changes_log (timestamp);
@@ -5,14 +5,41 @@
 
 use chrono::{DateTime, Utc};
 use serde_json;
+#[cfg(test)]
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::fs;
 use std::path::PathBuf;
 use yup_oauth2::ApplicationSecret;
 
-use errors::{AdaptExternalResult, Result};
-use google_apis::{self, CallBuilderExt, Drive};
+use errors::Result;
+use google_apis::Drive;
 use token_storage::SerdeMemoryStorage;
 
+#[cfg(test)]
+thread_local! {
+    /// A directory to keep account files in instead of the usual one, so
+    /// that each test can have its own set of accounts.
+    pub static TEST_ACCOUNTS_DIR: RefCell<Option<PathBuf>> = const { RefCell::new(None) };
+}
+
+/// Get the directory that holds the account files, creating it if
+/// *create* is true.
+fn accounts_dir(create: bool) -> Result<PathBuf> {
+    #[cfg(test)]
+    {
+        if let Some(dir) = TEST_ACCOUNTS_DIR.with(|d| d.borrow().clone()) {
+            return Ok(dir);
+        }
+    }
+
+    Ok(if create {
+        app_dirs::app_dir(app_dirs::AppDataType::UserData, &::APP_INFO, "accounts")?
+    } else {
+        app_dirs::get_app_dir(app_dirs::AppDataType::UserData, &::APP_INFO, "accounts")?
+    })
+}
+
 /// Information about one logged-in Google Drive account.
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct AccountData {
@@ -25,6 +52,12 @@ pub struct AccountData {
     /// A token used to ask the API about recent changes.
     pub change_page_token: Option<String>,
 
+    /// Tokens used to ask the API about recent changes in the shared drives
+    /// that this account is a member of, keyed by drive ID. Each shared
+    /// drive has its own change feed.
+    #[serde(default)]
+    pub drive_change_page_tokens: HashMap<String, String>,
+
     /// The identifier of this account's root folder.
     pub root_folder_id: String,
 
@@ -53,8 +86,7 @@ impl Account {
     pub fn load<S: AsRef<str>>(email: S) -> Result<Account> {
         // Note that PathBuf.set_extension() will destroy, e.g., ".com" at the
         // end of an email address.
-        let mut path =
-            app_dirs::get_app_dir(app_dirs::AppDataType::UserData, &::APP_INFO, "accounts")?;
+        let mut path = accounts_dir(false)?;
         let mut email_ext = email.as_ref().to_owned();
         email_ext.push_str(".json");
         path.push(&email_ext);
@@ -97,7 +129,7 @@ impl Account {
     /// make the API call to get the email address associated with the account
     /// when setting it up, because otherwise it will fail when trying to
     /// write JSON to an as-yet-unknown path.
-    fn with_drive_hub_nosave<T, F>(
+    pub fn with_drive_hub_nosave<T, F>(
         &mut self,
         secret: &ApplicationSecret,
         mut callback: F,
@@ -133,53 +165,25 @@ impl Account {
         Ok(result)
     }
 
-    /// Ask Google for the email address associated with this account.
-    pub fn fetch_email_address(&mut self, secret: &ApplicationSecret) -> Result<String> {
-        let about = self.with_drive_hub_nosave(secret, |hub| google_apis::get_about(hub))?;
-        let user = about
-            .user
-            .ok_or_else(|| format_err!("server response did not include user information"))?;
-        let email = user
-            .email_address
-            .ok_or_else(|| format_err!("server response did not include email address"))?;
-
-        // Kind of ugly: set the save path for our JSON file now that we know
-        // what the associated email is. Then we can save the data. Note that
-        // PathBuf.set_extension() will destroy, e.g., ".com" at the end of an
-        // email address.
-
-        let mut path = app_dirs::app_dir(app_dirs::AppDataType::UserData, &::APP_INFO, "accounts")?;
-        let mut email_ext = email.clone();
+    /// Record the email address associated with this account.
+    ///
+    /// Kind of ugly: this sets the save path for our JSON file now that we
+    /// know what the associated email is. Then we can save the data. Note
+    /// that PathBuf.set_extension() will destroy, e.g., ".com" at the end of
+    /// an email address.
+    pub fn set_email(&mut self, email: &str) -> Result<()> {
+        let mut path = accounts_dir(true)?;
+        let mut email_ext = email.to_owned();
         email_ext.push_str(".json");
         path.push(&email_ext);
         self.path = path;
-        self.save_to_json()?;
-
-        Ok(email)
-    }
-
-    /// Acquire a new token for checking for recent document changes in this account.
-    pub fn acquire_change_page_token(&mut self, secret: &ApplicationSecret) -> Result<()> {
-        let token = self.with_drive_hub(secret, |hub| {
-            let (_resp, info) = hub
-                .changes()
-                .get_start_page_token()
-                .default_scope()
-                .doit()
-                .adapt()?;
-            info.start_page_token
-                .ok_or_else(|| format_err!("server response did not include token"))
-        })?;
-
-        self.data.change_page_token = Some(token);
-        self.save_to_json()?;
-        Ok(())
+        self.save_to_json()
     }
 }
 
 /// Get information about all of the accounts.
 pub fn get_accounts() -> Result<impl Iterator<Item = Result<(String, Account)>>> {
-    let path = app_dirs::app_dir(app_dirs::AppDataType::UserData, &::APP_INFO, "accounts")?;
+    let path = accounts_dir(true)?;
 
     // Surely there's a better way to implement this ...
     Ok(fs::read_dir(path)?.filter_map(|maybe_entry| {
@@ -3,20 +3,29 @@
 
 //! The main application state.
 
-use chrono::{DateTime, Duration, Utc};
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
 use diesel::prelude::*;
+use diesel::sql_types::Text;
 use diesel::sqlite::SqliteConnection;
+use google_drive3::{Change, Drive as ApiDrive, File, ReadSeek};
 use petgraph::prelude::*;
 use std::collections::HashMap;
+use std::io::Write;
+use std::path::PathBuf;
 use structopt::StructOpt;
 use tcprint::ColorPrintState;
 use yup_oauth2::ApplicationSecret;
 
 use accounts::{self, Account};
+use backend::{DriveBackend, FixtureBackend, HubBackend};
 use colors::Colors;
+use config::Config;
 use database::{self, Doc};
 use errors::Result;
+use fuzzy;
 use google_apis;
+use output;
+use query;
 use schema;
 
 arg_enum! {
@@ -29,6 +38,39 @@ arg_enum! {
     }
 }
 
+arg_enum! {
+    /// An enum for specifying how lists of documents should be printed
+    #[derive(Clone, Debug, Eq, PartialEq)]
+    pub enum OutputFormat {
+        Text,
+        Json,
+        Jsonl,
+        Csv,
+        Tsv,
+    }
+}
+
+arg_enum! {
+    /// An enum for specifying how document names given on the command line
+    /// should be matched
+    #[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+    #[serde(rename_all = "lowercase")]
+    pub enum MatchMode {
+        Strict,
+        Fuzzy,
+    }
+}
+
+arg_enum! {
+    /// An enum for specifying how to account for documents that live in
+    /// more than one folder
+    #[derive(Clone, Debug, Eq, PartialEq)]
+    pub enum MultiParentPolicy {
+        Once,
+        Apportion,
+    }
+}
+
 /// Global options for the application.
 #[derive(Debug, StructOpt)]
 pub struct ApplicationOptions {
@@ -40,6 +82,70 @@ pub struct ApplicationOptions {
         raw(possible_values = r#"&["auto", "no", "yes"]"#)
     )]
     pub sync: SyncOption,
+
+    #[structopt(
+        long = "format",
+        help = "How to print lists of documents",
+        parse(try_from_str),
+        default_value = "text",
+        raw(possible_values = r#"&["text", "json", "jsonl", "csv", "tsv"]"#)
+    )]
+    pub format: OutputFormat,
+
+    #[structopt(
+        long = "match",
+        help = "How to match document names: \"strict\" requires substrings and refuses to \
+                guess; \"fuzzy\" allows abbreviations and picks clear winners. Commands \
+                that change documents always match strictly. Overrides the \"matching\" \
+                configuration setting",
+        parse(try_from_str),
+        env = "DRORG_MATCH",
+        raw(possible_values = r#"&["strict", "fuzzy"]"#)
+    )]
+    pub match_mode: Option<MatchMode>,
+
+    #[structopt(
+        long = "fixture",
+        help = "Answer Drive API calls from a JSON fixture file instead of Google's servers",
+        parse(from_os_str),
+        raw(hidden = "true")
+    )]
+    pub fixture: Option<PathBuf>,
+
+    #[structopt(
+        long = "db",
+        help = "Use this database file instead of the default one",
+        parse(from_os_str),
+        raw(hidden = "true")
+    )]
+    pub db: Option<PathBuf>,
+}
+
+/// The file fields that we request from the API and store in the database.
+///
+/// The permission subfields should match `google_apis::PERMISSION_FIELDS`.
+const FILE_FIELDS: &str = "driveId,id,md5Checksum,mimeType,modifiedTime,name,ownedByMe,\
+                           owners(displayName,emailAddress),parents,\
+                           permissions(allowFileDiscovery,displayName,domain,emailAddress,id,\
+                           role,type),sharingUser(displayName,emailAddress),size,starred,trashed";
+
+/// The maximum number of paths that `LinkageTable::find_parent_paths` will
+/// report for a single document. Folders that live in several places at
+/// once multiply the number of routes to the documents inside them, so in
+/// pathological cases there can be exponentially many.
+const MAX_PARENT_PATHS: usize = 64;
+
+/// The maximum number of partial paths that `find_parent_paths` will
+/// consider before giving up, which bounds the work spent on tangles of
+/// folders that never lead anywhere.
+const MAX_PARENT_PATH_STEPS: usize = 10_000;
+
+/// Get the ID of a shared drive returned by the API.
+fn drive_id_of(drive: &ApiDrive) -> Result<&str> {
+    drive
+        .id
+        .as_deref()
+        .ok_or_else(|| format_err!("no ID provided with shared drive object"))
 }
 
 /// The runtime state of the application.
@@ -50,94 +156,172 @@ pub struct Application {
     /// The secret we use to identify this client to Google.
     pub secret: ApplicationSecret,
 
+    /// The user's configuration settings.
+    pub config: Config,
+
     /// Our connection to the database of document information.
     pub conn: SqliteConnection,
 
     /// The state object for colorized terminal output.
     pub ps: ColorPrintState<Colors>,
+
+    /// A fixed set of Drive data to use instead of the Google servers, if
+    /// one was specified.
+    pub fixture: Option<FixtureBackend>,
 }
 
 impl Application {
     /// Initialize the application.
     pub fn initialize(options: ApplicationOptions) -> Result<Application> {
-        let secret = google_apis::get_app_secret()?;
-        let conn = database::get_db_connection()?;
+        let fixture = match options.fixture.as_ref() {
+            Some(path) => Some(FixtureBackend::load(path)?),
+            None => None,
+        };
+
+        // We don't need a real secret if we're not going to talk to Google.
+        let secret = if fixture.is_some() {
+            ApplicationSecret::default()
+        } else {
+            google_apis::get_app_secret()?
+        };
+
+        let config = Config::load()?;
+        let conn = database::get_db_connection(options.db.as_deref())?;
         let ps = ColorPrintState::default();
 
         Ok(Application {
             options,
             secret,
+            config,
             conn,
             ps,
+            fixture,
         })
     }
 
+    /// Get how document names should be matched: as given on the command
+    /// line, or else as configured, or else fuzzily.
+    pub fn match_mode(&self) -> MatchMode {
+        self.options
+            .match_mode
+            .as_ref()
+            .or(self.config.matching.as_ref())
+            .cloned()
+            .unwrap_or(MatchMode::Fuzzy)
+    }
+
+    /// Perform a Drive operation using this account, saving the account
+    /// data afterwards.
+    ///
+    /// If a fixture was specified on the command line, the callback is handed
+    /// the fixture backend; otherwise it gets a backend that talks to the
+    /// Google servers.
+    pub fn with_backend<T, F>(&self, account: &mut Account, mut callback: F) -> Result<T>
+    where
+        for<'a> F: FnMut(&'a dyn DriveBackend) -> Result<T>,
+    {
+        if let Some(fixture) = self.fixture.as_ref() {
+            let result = callback(fixture)?;
+            account.save_to_json()?;
+            Ok(result)
+        } else {
+            account.with_drive_hub(&self.secret, |hub| callback(&HubBackend::new(hub)))
+        }
+    }
+
+    /// Like `with_backend`, but without saving the account data.
+    ///
+    /// This is needed when setting up a new account, when it does not yet
+    /// know what filename it should save itself under.
+    pub fn with_backend_nosave<T, F>(&self, account: &mut Account, mut callback: F) -> Result<T>
+    where
+        for<'a> F: FnMut(&'a dyn DriveBackend) -> Result<T>,
+    {
+        if let Some(fixture) = self.fixture.as_ref() {
+            callback(fixture)
+        } else {
+            account.with_drive_hub_nosave(&self.secret, |hub| callback(&HubBackend::new(hub)))
+        }
+    }
+
+    /// Ask the backend for the email address associated with an account, and
+    /// record it in the account data.
+    pub fn fetch_email_address(&self, account: &mut Account) -> Result<String> {
+        let about = self.with_backend_nosave(account, |backend| backend.about())?;
+        let user = about
+            .user
+            .ok_or_else(|| format_err!("server response did not include user information"))?;
+        let email = user
+            .email_address
+            .ok_or_else(|| format_err!("server response did not include email address"))?;
+
+        account.set_email(&email)?;
+        Ok(email)
+    }
+
+    /// Acquire a new token for checking for recent document changes in an
+    /// account.
+    ///
+    /// This is the token for the account's main change feed. The tokens for
+    /// its shared drives are acquired in `import_documents`.
+    pub fn acquire_change_page_token(&self, account: &mut Account) -> Result<()> {
+        let token = self.with_backend(account, |backend| backend.get_start_page_token(None))?;
+        account.data.change_page_token = Some(token);
+        account.save_to_json()?;
+        Ok(())
+    }
+
     /// Fill the database with records for all of the documents associated
-    /// with an account.
+    /// with an account, including those in its shared drives.
     pub fn import_documents(&mut self, account: &mut Account) -> Result<()> {
         let the_account_id = account.data.db_id; // borrowck fun
 
-        let root_id: String = account.with_drive_hub(&self.secret, |hub| {
+        let (root_id, drive_tokens) = self.with_backend(account, |backend| {
             // This redundant codepath feels kind of ugly, but so far it seems
             // like the least-bad way to make sure we get info about the root
             // document.
             let root_id = {
-                let file = google_apis::get_file(hub, "root", |call| {
-                    call.param(
-                        "fields",
-                        "id,mimeType,modifiedTime,name,parents,\
-                         size,starred,trashed",
-                    )
-                })?;
-                let new_doc = database::NewDoc::from_api_object(&file)?;
-                diesel::replace_into(schema::docs::table)
-                    .values(&new_doc)
-                    .execute(&self.conn)?;
-
-                let new_assn = database::NewAccountAssociation::new(new_doc.id, the_account_id);
-                diesel::replace_into(schema::account_associations::table)
-                    .values(&new_assn)
-                    .execute(&self.conn)?;
-
-                new_doc.id.to_owned()
+                let file = backend.get_file("root", FILE_FIELDS)?;
+                self.store_file(the_account_id, &file)?
             };
 
-            for maybe_file in google_apis::list_files(hub, |call| {
-                call.spaces("drive").param(
-                    "fields",
-                    "files(id,mimeType,modifiedTime,name,parents,\
-                     size,starred,trashed),nextPageToken",
-                )
-            }) {
-                let file = maybe_file?;
-                let new_doc = database::NewDoc::from_api_object(&file)?;
-                diesel::replace_into(schema::docs::table)
-                    .values(&new_doc)
-                    .execute(&self.conn)?;
+            let fields = format!("files({}),nextPageToken", FILE_FIELDS);
 
-                let new_assn = database::NewAccountAssociation::new(new_doc.id, the_account_id);
-                diesel::replace_into(schema::account_associations::table)
-                    .values(&new_assn)
-                    .execute(&self.conn)?;
+            for maybe_file in backend.list_files(None, &fields) {
+                let file = maybe_file?;
+                let file_id = self.store_file(the_account_id, &file)?;
 
                 // Note that we make no effort to delete any parent-child
                 // links in the database that don't correspond to items
                 // returned here:
 
-                if let Some(parents) = file.parents.as_ref() {
-                    for pid in parents {
-                        let new_link = database::NewLink::new(the_account_id, pid, new_doc.id);
-                        diesel::replace_into(schema::links::table)
-                            .values(&new_link)
-                            .execute(&self.conn)?;
-                    }
-                }
+                self.add_links(the_account_id, &file_id, file.parents.as_ref())?;
+            }
+
+            let drives = backend.list_drives()?;
+            self.store_drives(the_account_id, &drives)?;
+            let mut drive_tokens = HashMap::new();
+
+            for drive in &drives {
+                let drive_id = drive_id_of(drive)?;
+                let token = self.import_drive(backend, the_account_id, drive_id)?;
+                drive_tokens.insert(drive_id.to_owned(), token);
             }
 
-            Ok(root_id)
+            Ok((root_id, drive_tokens))
         })?;
 
+        let account_doc_ids = {
+            use schema::account_associations::dsl::*;
+            account_associations
+                .filter(account_id.eq(the_account_id))
+                .select(doc_id)
+                .load::<String>(&self.conn)?
+        };
+        self.reindex_docs(account_doc_ids)?;
+
         account.data.root_folder_id = root_id;
+        account.data.drive_change_page_tokens = drive_tokens;
         account.data.last_sync = Some(Utc::now());
         account.save_to_json()?;
         Ok(())
@@ -156,108 +340,650 @@ impl Application {
             .take()
             .ok_or_else(|| format_err!("no change-paging token for {}", email))?;
 
-        let token = account.with_drive_hub(&self.secret, |hub| {
-            let mut lister = google_apis::list_changes(hub, &token, |call| {
-                call.spaces("drive")
-                    .supports_team_drives(true)
-                    .include_team_drive_items(true)
-                    .include_removed(true)
-                    .include_corpus_removals(true)
-                    .param(
-                        "fields",
-                        "changes(file(id,mimeType,modifiedTime,name,parents,\
-                         size,starred,trashed),fileId,removed),newStartPageToken,\
-                         nextPageToken",
-                    )
-            });
-
-            for maybe_change in lister.iter() {
-                use schema::docs::dsl::*;
+        let fields = format!(
+            "changes(file({}),fileId,removed,time),newStartPageToken,nextPageToken",
+            FILE_FIELDS
+        );
 
-                let change = maybe_change?;
+        let mut changed_ids = Vec::new();
 
-                let file_id = match (&change.file_id).as_ref() {
-                    Some(fid) => fid,
+        let token = self.with_backend(account, |backend| {
+            backend.list_changes(None, &token, &fields, &mut |change| {
+                if let Some(fid) = change.file_id.as_ref() {
+                    changed_ids.push(fid.clone());
+                }
 
-                    // I've observed change entries that are filled with Nones
-                    // for every item we request. I don't know what that
-                    // means, but it seems to work OK if we just ignore them.
-                    None => continue,
-                };
+                self.apply_change(the_account_id, &change)
+            })
+        })?;
 
-                let removed = change.removed.unwrap_or(false);
+        account.data.change_page_token = Some(token);
 
-                if removed {
-                    // TODO: just save a flag, or something? NOTE: Just
-                    // putting a file in the trash doesn't trigger this
-                    // action. The user needs to either "Delete forever" the
-                    // document from their Trash; or I think this can happen
-                    // if they lose access to the document.
+        // Now the shared drives. Each has its own change feed; drives that
+        // the account has newly joined are imported from scratch, and those
+        // that it has left are forgotten.
 
-                    {
-                        use schema::links::dsl::*;
-                        diesel::delete(
-                            links.filter(account_id.eq(the_account_id).and(parent_id.eq(file_id))),
-                        )
-                        .execute(&self.conn)?;
-                        diesel::delete(
-                            links.filter(account_id.eq(the_account_id).and(child_id.eq(file_id))),
-                        )
-                        .execute(&self.conn)?;
-                    }
+        let old_drive_tokens = std::mem::take(&mut account.data.drive_change_page_tokens);
 
-                    {
-                        use schema::account_associations::dsl::*;
-                        diesel::delete(account_associations.filter(doc_id.eq(file_id)))
-                            .execute(&self.conn)?;
-                    }
+        let drive_tokens = self.with_backend(account, |backend| {
+            let drives = backend.list_drives()?;
+            self.store_drives(the_account_id, &drives)?;
+            let mut drive_tokens = HashMap::new();
 
-                    diesel::delete(docs.filter(id.eq(file_id))).execute(&self.conn)?;
-                } else {
-                    let file = &change.file.as_ref().ok_or_else(|| {
-                        format_err!(
-                            "server reported file change but did not provide its information"
-                        )
-                    })?;
-                    let new_doc = database::NewDoc::from_api_object(file)?;
-                    diesel::replace_into(schema::docs::table)
-                        .values(&new_doc)
-                        .execute(&self.conn)?;
-
-                    let new_assn = database::NewAccountAssociation::new(new_doc.id, the_account_id);
-                    diesel::replace_into(schema::account_associations::table)
-                        .values(&new_assn)
-                        .execute(&self.conn)?;
-
-                    // Refresh the parentage information.
+            for drive in &drives {
+                let drive_id = drive_id_of(drive)?;
 
-                    {
-                        use schema::links::dsl::*;
-                        diesel::delete(
-                            links.filter(account_id.eq(the_account_id).and(child_id.eq(file_id))),
-                        )
-                        .execute(&self.conn)?;
+                let token = match old_drive_tokens.get(drive_id) {
+                    Some(token) => {
+                        backend.list_changes(Some(drive_id), token, &fields, &mut |change| {
+                            if let Some(fid) = change.file_id.as_ref() {
+                                changed_ids.push(fid.clone());
+                            }
+
+                            self.apply_change(the_account_id, &change)
+                        })?
                     }
 
-                    if let Some(parents) = file.parents.as_ref() {
-                        for pid in parents {
-                            let new_link = database::NewLink::new(the_account_id, pid, file_id);
-                            diesel::replace_into(schema::links::table)
-                                .values(&new_link)
-                                .execute(&self.conn)?;
-                        }
+                    None => {
+                        changed_ids.push(drive_id.to_owned());
+                        self.import_drive(backend, the_account_id, drive_id)?
                     }
-                }
+                };
+
+                drive_tokens.insert(drive_id.to_owned(), token);
             }
 
-            Ok(lister.into_change_page_token())
+            Ok(drive_tokens)
         })?;
 
-        account.data.change_page_token = Some(token);
+        for old_drive_id in old_drive_tokens.keys() {
+            if !drive_tokens.contains_key(old_drive_id) {
+                changed_ids.extend(self.forget_drive(the_account_id, old_drive_id)?);
+            }
+        }
+
+        self.reindex_subtrees(the_account_id, &changed_ids)?;
+
+        account.data.drive_change_page_tokens = drive_tokens;
         account.save_to_json()?;
         Ok(())
     }
 
+    /// Replace the database's list of the shared drives that an account is a
+    /// member of.
+    fn store_drives(&self, the_account_id: i32, the_drives: &[ApiDrive]) -> Result<()> {
+        use schema::drives::dsl::*;
+
+        diesel::delete(drives.filter(account_id.eq(the_account_id))).execute(&self.conn)?;
+
+        for drive in the_drives {
+            let new_drive = database::NewSharedDrive::from_api_object(the_account_id, drive)?;
+            diesel::replace_into(drives)
+                .values(&new_drive)
+                .execute(&self.conn)?;
+        }
+
+        Ok(())
+    }
+
+    /// Fill the database with records for all of the documents in a shared
+    /// drive, including its root folder.
+    ///
+    /// Returns a token for checking for subsequent changes to the drive. As
+    /// with the main change feed, the token is acquired before the listing.
+    fn import_drive(
+        &self,
+        backend: &dyn DriveBackend,
+        the_account_id: i32,
+        drive_id: &str,
+    ) -> Result<String> {
+        let token = backend.get_start_page_token(Some(drive_id))?;
+
+        let root = backend.get_file(drive_id, FILE_FIELDS)?;
+        self.store_file(the_account_id, &root)?;
+
+        let fields = format!("files({}),nextPageToken", FILE_FIELDS);
+
+        for maybe_file in backend.list_files(Some(drive_id), &fields) {
+            let file = maybe_file?;
+            let file_id = self.store_file(the_account_id, &file)?;
+            self.add_links(the_account_id, &file_id, file.parents.as_ref())?;
+        }
+
+        Ok(token)
+    }
+
+    /// Remove the documents in a shared drive from an account, as if the
+    /// change feed had reported each of them as removed. Documents that
+    /// other accounts can still see are kept.
+    ///
+    /// Returns the IDs of the removed documents.
+    fn forget_drive(&self, the_account_id: i32, the_drive_id: &str) -> Result<Vec<String>> {
+        let doc_ids = {
+            use schema::account_associations as aa;
+            use schema::docs::dsl::*;
+            docs.filter(drive_id.eq(the_drive_id))
+                .filter(
+                    id.eq_any(
+                        aa::table
+                            .filter(aa::account_id.eq(the_account_id))
+                            .select(aa::doc_id),
+                    ),
+                )
+                .select(id)
+                .load::<String>(&self.conn)?
+        };
+
+        for doc_id in &doc_ids {
+            let change = Change {
+                file_id: Some(doc_id.clone()),
+                removed: Some(true),
+                ..Default::default()
+            };
+            self.apply_change(the_account_id, &change)?;
+        }
+
+        Ok(doc_ids)
+    }
+
+    /// Store the information about a file returned by the API in the
+    /// database, associating it with the specified account.
+    ///
+    /// Returns the ID of the file. Parent-child links are not touched. The
+    /// cached permissions are replaced with the ones that the API told us
+    /// about, which it only does for files that the user can share, and
+    /// never for files in shared drives.
+    fn store_file(&self, the_account_id: i32, file: &File) -> Result<String> {
+        let new_doc = database::NewDoc::from_api_object(file)?;
+        diesel::replace_into(schema::docs::table)
+            .values(&new_doc)
+            .execute(&self.conn)?;
+
+        let new_assn =
+            database::NewAccountAssociation::from_api_object(new_doc.id, the_account_id, file);
+        diesel::replace_into(schema::account_associations::table)
+            .values(&new_assn)
+            .execute(&self.conn)?;
+
+        // If the permissions are missing, whatever we knew about them is
+        // out of date: the user may no longer be able to share the file.
+        self.store_permissions(new_doc.id, file.permissions.as_deref().unwrap_or(&[]))?;
+
+        Ok(new_doc.id.to_owned())
+    }
+
+    /// Replace the cached permissions of a document.
+    pub fn store_permissions(
+        &self,
+        the_doc_id: &str,
+        perms: &[google_drive3::Permission],
+    ) -> Result<()> {
+        use schema::permissions::dsl::*;
+
+        diesel::delete(permissions.filter(doc_id.eq(the_doc_id))).execute(&self.conn)?;
+
+        for perm in perms {
+            let new_perm = database::NewPermission::from_api_object(the_doc_id, perm)?;
+            diesel::replace_into(permissions)
+                .values(&new_perm)
+                .execute(&self.conn)?;
+        }
+
+        Ok(())
+    }
+
+    /// Fetch the permissions of documents in shared drives, if we don't know
+    /// them already.
+    ///
+    /// The API doesn't report the permissions of documents in shared drives
+    /// when listing their contents, so they have to be asked for one
+    /// document at a time. The results stay in the cache until the document
+    /// changes. Trashed documents are skipped unless *include_trashed* is
+    /// true. Returns the number of documents whose permissions couldn't be
+    /// fetched through any account.
+    pub fn fetch_drive_permissions(&mut self, include_trashed: bool) -> Result<usize> {
+        let missing = {
+            use schema::docs::dsl::*;
+            let no_perms = diesel::dsl::not(diesel::dsl::exists(
+                schema::permissions::table.filter(schema::permissions::doc_id.eq(id)),
+            ));
+            let mut q = docs
+                .filter(drive_id.is_not_null())
+                .filter(no_perms)
+                .into_boxed();
+
+            if !include_trashed {
+                q = q.filter(trashed.eq(false));
+            }
+
+            q.load::<Doc>(&self.conn)?
+        };
+
+        let mut loaded = HashMap::new();
+        let mut n_failed = 0;
+
+        for doc in &missing {
+            let mut fetched = false;
+
+            for acct in doc.accounts(self)? {
+                if !loaded.contains_key(&acct.email) {
+                    loaded.insert(acct.email.clone(), Account::load(&acct.email)?);
+                }
+
+                let account = loaded.get_mut(&acct.email).unwrap();
+
+                if let Ok(perms) =
+                    self.with_backend(account, |backend| backend.list_permissions(&doc.id))
+                {
+                    self.store_permissions(&doc.id, &perms)?;
+                    fetched = true;
+                    break;
+                }
+            }
+
+            if !fetched {
+                n_failed += 1;
+            }
+        }
+
+        Ok(n_failed)
+    }
+
+    /// Record that a file lives in each of the specified parents.
+    fn add_links(
+        &self,
+        the_account_id: i32,
+        file_id: &str,
+        parents: Option<&Vec<String>>,
+    ) -> Result<()> {
+        if let Some(parents) = parents {
+            for pid in parents {
+                let new_link = database::NewLink::new(the_account_id, pid, file_id);
+                diesel::replace_into(schema::links::table)
+                    .values(&new_link)
+                    .execute(&self.conn)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Replace all of the parentage information about a file.
+    fn rebuild_links(
+        &self,
+        the_account_id: i32,
+        file_id: &str,
+        parents: Option<&Vec<String>>,
+    ) -> Result<()> {
+        {
+            use schema::links::dsl::*;
+            diesel::delete(links.filter(account_id.eq(the_account_id).and(child_id.eq(file_id))))
+                .execute(&self.conn)?;
+        }
+
+        self.add_links(the_account_id, file_id, parents)
+    }
+
+    /// Apply one entry from the change feed to the database.
+    ///
+    /// What happened is recorded in the change log, by comparing the
+    /// document's records before and after.
+    fn apply_change(&self, the_account_id: i32, change: &Change) -> Result<()> {
+        use schema::docs::dsl::*;
+
+        let file_id = match change.file_id.as_ref() {
+            Some(fid) => fid,
+
+            // I've observed change entries that are filled with Nones for
+            // every item we request. I don't know what that means, but it
+            // seems to work OK if we just ignore them.
+            None => return Ok(()),
+        };
+
+        let before = self.doc_state(the_account_id, file_id)?;
+        let removed = change.removed.unwrap_or(false);
+
+        if removed {
+            // TODO: just save a flag, or something? NOTE: Just putting a file
+            // in the trash doesn't trigger this action. The user needs to
+            // either "Delete forever" the document from their Trash; or I
+            // think this can happen if they lose access to the document.
+
+            {
+                use schema::links::dsl::*;
+                diesel::delete(
+                    links.filter(account_id.eq(the_account_id).and(parent_id.eq(file_id))),
+                )
+                .execute(&self.conn)?;
+                diesel::delete(
+                    links.filter(account_id.eq(the_account_id).and(child_id.eq(file_id))),
+                )
+                .execute(&self.conn)?;
+            }
+
+            // Other accounts may still have access to the document, in which
+            // case its record has to stay.
+
+            let still_known = {
+                use schema::account_associations::dsl::*;
+                diesel::delete(
+                    account_associations
+                        .filter(doc_id.eq(file_id).and(account_id.eq(the_account_id))),
+                )
+                .execute(&self.conn)?;
+
+                diesel::select(diesel::dsl::exists(
+                    account_associations.filter(doc_id.eq(file_id)),
+                ))
+                .get_result::<bool>(&self.conn)?
+            };
+
+            if !still_known {
+                {
+                    use schema::permissions::dsl::*;
+                    diesel::delete(permissions.filter(doc_id.eq(file_id))).execute(&self.conn)?;
+                }
+
+                diesel::delete(docs.filter(id.eq(file_id))).execute(&self.conn)?;
+            }
+        } else {
+            let file = change.file.as_ref().ok_or_else(|| {
+                format_err!("server reported file change but did not provide its information")
+            })?;
+            self.store_file(the_account_id, file)?;
+
+            // Refresh the parentage information.
+            self.rebuild_links(the_account_id, file_id, file.parents.as_ref())?;
+        }
+
+        let after = if removed {
+            None
+        } else {
+            self.doc_state(the_account_id, file_id)?
+        };
+
+        // Changes that we synthesize ourselves don't have times.
+        let when = change
+            .time
+            .as_ref()
+            .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+            .map(|t| t.naive_utc())
+            .unwrap_or_else(|| Utc::now().naive_utc());
+
+        self.log_change(the_account_id, file_id, when, before, after)
+    }
+
+    /// Get a document's record and the sorted IDs of its parents, if it is
+    /// associated with the specified account.
+    fn doc_state(
+        &self,
+        the_account_id: i32,
+        the_doc_id: &str,
+    ) -> Result<Option<(Doc, Vec<String>)>> {
+        let associated = {
+            use schema::account_associations::dsl::*;
+            diesel::select(diesel::dsl::exists(
+                account_associations
+                    .filter(doc_id.eq(the_doc_id).and(account_id.eq(the_account_id))),
+            ))
+            .get_result::<bool>(&self.conn)?
+        };
+
+        if !associated {
+            return Ok(None);
+        }
+
+        let doc = {
+            use schema::docs::dsl::*;
+            docs.filter(id.eq(the_doc_id))
+                .first::<Doc>(&self.conn)
+                .optional()?
+        };
+
+        Ok(match doc {
+            Some(doc) => {
+                let mut parents = self.parent_ids(the_account_id, the_doc_id)?;
+                parents.sort();
+                Some((doc, parents))
+            }
+
+            None => None,
+        })
+    }
+
+    /// Record the differences between two states of a document in the
+    /// change log.
+    ///
+    /// This is called when applying changes from the change feed, and also
+    /// when we modify documents ourselves, since those changes are already
+    /// in the database by the time that the feed reports them.
+    ///
+    /// A document counts as modified if its content checksum or size
+    /// changed. Native documents have neither, so for them we go by the
+    /// modification time -- but only if nothing else changed, since renaming
+    /// a document also bumps that.
+    fn log_change(
+        &self,
+        the_account_id: i32,
+        the_doc_id: &str,
+        when: NaiveDateTime,
+        before: Option<(Doc, Vec<String>)>,
+        after: Option<(Doc, Vec<String>)>,
+    ) -> Result<()> {
+        let mut entries = Vec::new();
+
+        match (&before, &after) {
+            (None, None) => return Ok(()),
+            (None, Some(_)) => entries.push(("added", None, None)),
+            (Some(_), None) => entries.push(("removed", None, None)),
+
+            (Some((old, old_parents)), Some((new, new_parents))) => {
+                if old.name != new.name {
+                    entries.push(("renamed", Some(old.name.clone()), Some(new.name.clone())));
+                }
+
+                if old_parents != new_parents {
+                    entries.push((
+                        "moved",
+                        Some(old_parents.join(",")),
+                        Some(new_parents.join(",")),
+                    ));
+                }
+
+                if old.trashed != new.trashed {
+                    entries.push((
+                        "trashed",
+                        Some(old.trashed.to_string()),
+                        Some(new.trashed.to_string()),
+                    ));
+                }
+
+                let content_changed = old.md5_checksum != new.md5_checksum || old.size != new.size;
+
+                if content_changed || (entries.is_empty() && old.modified_time != new.modified_time)
+                {
+                    entries.push((
+                        "modified",
+                        Some(old.utc_mod_time().to_rfc3339()),
+                        Some(new.utc_mod_time().to_rfc3339()),
+                    ));
+                }
+            }
+        }
+
+        let the_doc_name = match (&after, &before) {
+            (Some((doc, _)), _) | (None, Some((doc, _))) => doc.name.clone(),
+            (None, None) => unreachable!(),
+        };
+
+        use schema::changes_log::dsl::*;
+
+        for (the_kind, old, new) in entries {
+            diesel::insert_into(changes_log)
+                .values(&database::NewChangeLogEntry {
+                    account_id: the_account_id,
+                    doc_id: the_doc_id,
+                    doc_name: &the_doc_name,
+                    kind: the_kind,
+                    old_value: old,
+                    new_value: new,
+                    timestamp: when,
+                })
+                .execute(&self.conn)?;
+        }
+
+        Ok(())
+    }
+
+    /// Modify a document on the server using the specified account, and
+    /// update the database to match the server's response.
+    ///
+    /// See `DriveBackend::update_file` for the meaning of the arguments.
+    /// Returns the updated document. Only the parent-child links of the
+    /// specified account are refreshed.
+    pub fn update_doc(
+        &mut self,
+        acct: &database::Account,
+        doc_id: &str,
+        patch: File,
+        add_parents: &[&str],
+        remove_parents: &[&str],
+    ) -> Result<Doc> {
+        let mut account = Account::load(&acct.email)?;
+        let before = self.doc_state(acct.id, doc_id)?;
+
+        let file = self.with_backend(&mut account, |backend| {
+            backend.update_file(
+                doc_id,
+                patch.clone(),
+                add_parents,
+                remove_parents,
+                FILE_FIELDS,
+            )
+        })?;
+
+        self.store_file(acct.id, &file)?;
+        self.rebuild_links(acct.id, doc_id, file.parents.as_ref())?;
+        self.reindex_subtrees(acct.id, &[doc_id.to_owned()])?;
+
+        let after = self.doc_state(acct.id, doc_id)?;
+        self.log_change(acct.id, doc_id, Utc::now().naive_utc(), before, after)?;
+
+        use schema::docs::dsl::*;
+        Ok(docs.filter(id.eq(doc_id)).first(&self.conn)?)
+    }
+
+    /// Create a new content-less document on the server using the specified
+    /// account, and record it in the database.
+    ///
+    /// Returns the new document.
+    pub fn create_doc(&mut self, acct: &database::Account, metadata: File) -> Result<Doc> {
+        let mut account = Account::load(&acct.email)?;
+
+        let file = self.with_backend(&mut account, |backend| {
+            backend.create_file(metadata.clone(), FILE_FIELDS)
+        })?;
+
+        self.record_new_file(acct.id, &file)
+    }
+
+    /// Upload a new document to the server using the specified account, and
+    /// record it in the database.
+    ///
+    /// See `DriveBackend::upload_file` for the meaning of the arguments.
+    /// Returns the new document.
+    pub fn upload_doc(
+        &mut self,
+        acct: &database::Account,
+        metadata: File,
+        content: &mut dyn ReadSeek,
+        content_mime_type: &str,
+    ) -> Result<Doc> {
+        let mut account = Account::load(&acct.email)?;
+
+        let file = self.with_backend(&mut account, |backend| {
+            backend.upload_file(
+                metadata.clone(),
+                &mut *content,
+                content_mime_type,
+                FILE_FIELDS,
+            )
+        })?;
+
+        self.record_new_file(acct.id, &file)
+    }
+
+    /// Replace the content of a document on the server using the specified
+    /// account, and update the database to match.
+    ///
+    /// Returns the updated document.
+    pub fn update_doc_content(
+        &mut self,
+        acct: &database::Account,
+        doc_id: &str,
+        content: &mut dyn ReadSeek,
+        content_mime_type: &str,
+    ) -> Result<Doc> {
+        let mut account = Account::load(&acct.email)?;
+        let before = self.doc_state(acct.id, doc_id)?;
+
+        let file = self.with_backend(&mut account, |backend| {
+            backend.update_content(doc_id, &mut *content, content_mime_type, FILE_FIELDS)
+        })?;
+
+        self.store_file(acct.id, &file)?;
+        self.reindex_docs(vec![doc_id.to_owned()])?;
+
+        let after = self.doc_state(acct.id, doc_id)?;
+        self.log_change(acct.id, doc_id, Utc::now().naive_utc(), before, after)?;
+
+        use schema::docs::dsl::*;
+        Ok(docs.filter(id.eq(doc_id)).first(&self.conn)?)
+    }
+
+    /// Record a file that was just created on the server, just as
+    /// `import_documents` would have.
+    fn record_new_file(&self, the_account_id: i32, file: &File) -> Result<Doc> {
+        let doc_id = self.store_file(the_account_id, file)?;
+        self.add_links(the_account_id, &doc_id, file.parents.as_ref())?;
+        self.reindex_docs(vec![doc_id.clone()])?;
+
+        let after = self.doc_state(the_account_id, &doc_id)?;
+        self.log_change(the_account_id, &doc_id, Utc::now().naive_utc(), None, after)?;
+
+        use schema::docs::dsl::*;
+        Ok(docs.filter(id.eq(&doc_id)).first(&self.conn)?)
+    }
+
+    /// Fetch the content of a document, writing it to *dest*.
+    ///
+    /// If *export_mime_type* is specified, the document is exported in that
+    /// format; otherwise its binary content is downloaded. Returns the number
+    /// of bytes written.
+    pub fn download_doc(
+        &self,
+        account: &mut Account,
+        doc_id: &str,
+        export_mime_type: Option<&str>,
+        dest: &mut dyn Write,
+    ) -> Result<u64> {
+        self.with_backend(account, |backend| match export_mime_type {
+            Some(mime) => backend.export_file(doc_id, mime, &mut *dest),
+            None => backend.download_file(doc_id, &mut *dest),
+        })
+    }
+
+    /// Get the IDs of the folders that contain a document, according to the
+    /// specified account.
+    pub fn parent_ids(&self, acct_id: i32, doc_id: &str) -> Result<Vec<String>> {
+        use schema::links::dsl::*;
+
+        Ok(links
+            .filter(account_id.eq(acct_id).and(child_id.eq(doc_id)))
+            .select(parent_id)
+            .load(&self.conn)?)
+    }
+
     /// Maybe synchronize the database with the cloud, depending on the
     /// `--sync` option.
     ///
@@ -287,7 +1013,8 @@ impl Application {
             };
 
             if should_sync {
-                if !printed_sync_notice {
+                // Don't pollute machine-readable output.
+                if !printed_sync_notice && self.options.format == OutputFormat::Text {
                     tcreport!(self.ps, info: "synchronizing accounts ...");
                     printed_sync_notice = true;
                 }
@@ -339,35 +1066,47 @@ impl Application {
         Ok(())
     }
 
+    /// Record a list of documents as the most recently printed listing, so
+    /// that they can be referred to as `%1`, `%2`, etc.
+    pub fn record_listing(&self, docs: &[Doc]) -> Result<()> {
+        use database::{NewListItem, CLI_LAST_PRINT_ID};
+        use schema::listitems::dsl::*;
+
+        diesel::delete(listitems.filter(listing_id.eq(CLI_LAST_PRINT_ID))).execute(&self.conn)?;
+
+        let rows: Vec<_> = docs
+            .iter()
+            .enumerate()
+            .map(|(i, doc)| NewListItem::new(CLI_LAST_PRINT_ID, i as i32, &doc.id))
+            .collect();
+
+        diesel::insert_into(listitems)
+            .values(&rows)
+            .execute(&self.conn)?;
+        Ok(())
+    }
+
     /// Print out a list of documents.
     ///
     /// Many TODOs!
     pub fn print_doc_list(&mut self, docs: Vec<Doc>) -> Result<()> {
-        // If nothing, return -- without clearing the previous cli-last-print
-        // listing, if it exists.
+        // Get it all into the database first -- unless there's nothing, in
+        // which case we leave the previous cli-last-print listing alone, if
+        // it exists.
 
-        if docs.is_empty() {
-            return Ok(());
+        if !docs.is_empty() {
+            self.record_listing(&docs)?;
         }
 
-        // Get it all into the database first.
+        // Machine-readable output is printed even if there's nothing, so
+        // that consumers get an empty array or a header row.
 
-        {
-            use database::{NewListItem, CLI_LAST_PRINT_ID};
-            use schema::listitems::dsl::*;
-
-            diesel::delete(listitems.filter(listing_id.eq(CLI_LAST_PRINT_ID)))
-                .execute(&self.conn)?;
-
-            let rows: Vec<_> = docs
-                .iter()
-                .enumerate()
-                .map(|(i, doc)| NewListItem::new(CLI_LAST_PRINT_ID, i as i32, &doc.id))
-                .collect();
+        if self.options.format != OutputFormat::Text {
+            return self.print_doc_records(&docs);
+        }
 
-            diesel::insert_into(listitems)
-                .values(&rows)
-                .execute(&self.conn)?;
+        if docs.is_empty() {
+            return Ok(());
         }
 
         // Now print it out.
@@ -415,6 +1154,282 @@ impl Application {
     }
 }
 
+impl Application {
+    /// Describe the folder paths that lead to a document, in each of the
+    /// accounts that it belongs to.
+    ///
+    /// The return value is a list of (email, path) tuples, where the paths
+    /// are rendered as folder names joined by " > ". The *linkages* map
+    /// caches the linkage tables of the accounts, which are expensive to
+    /// load; it should be reused for repeated calls.
+    pub fn parent_path_reprs(
+        &mut self,
+        doc: &Doc,
+        linkages: &mut HashMap<i32, LinkageTable>,
+    ) -> Result<Vec<(String, String)>> {
+        use std::collections::hash_map::Entry;
+
+        let accounts = doc.accounts(self)?;
+        let mut path_reprs = Vec::new();
+
+        for acct in &accounts {
+            if let Entry::Vacant(e) = linkages.entry(acct.id) {
+                let table = self.load_linkage_table(acct.id, true)?;
+                e.insert(table);
+            }
+
+            let link_table = linkages.get(&acct.id).unwrap();
+
+            for p in link_table.find_parent_paths(&doc.id).iter().map(|id_path| {
+                // This is not efficient, and it's panicky, but meh.
+                let names: Vec<_> = id_path
+                    .iter()
+                    .map(|docid| {
+                        use schema::docs::dsl::*;
+                        let elem = docs
+                            .filter(id.eq(&docid))
+                            .first::<database::Doc>(&self.conn)
+                            .unwrap();
+                        elem.name
+                    })
+                    .collect();
+
+                names.join(" > ")
+            }) {
+                path_reprs.push((acct.email.clone(), p));
+            }
+        }
+
+        Ok(path_reprs)
+    }
+
+    /// Print a list of documents in the machine-readable format chosen by
+    /// the `--format` option.
+    ///
+    /// This doesn't record the listing for later `%N` references. When it's
+    /// called by `print_doc_list`, that's already been taken care of; other
+    /// callers print records that aren't meant to be referred to that way.
+    pub fn print_doc_records(&mut self, docs: &[Doc]) -> Result<()> {
+        let mut linkages = HashMap::new();
+        let mut records = Vec::with_capacity(docs.len());
+
+        for doc in docs {
+            records.push(self.doc_record(doc, &mut linkages)?);
+        }
+
+        let stdout = std::io::stdout();
+        output::write_records(&self.options.format, &records, stdout.lock())
+    }
+
+    /// Gather the information about a document that goes into
+    /// machine-readable output.
+    ///
+    /// The *linkages* map is used as in `parent_path_reprs`.
+    pub fn doc_record(
+        &mut self,
+        doc: &Doc,
+        linkages: &mut HashMap<i32, LinkageTable>,
+    ) -> Result<output::DocRecord> {
+        let accounts = doc
+            .accounts(self)?
+            .into_iter()
+            .map(|acct| acct.email)
+            .collect();
+
+        let mut paths: Vec<String> = Vec::new();
+
+        for (_email, p) in self.parent_path_reprs(doc, linkages)? {
+            if !paths.contains(&p) {
+                paths.push(p);
+            }
+        }
+
+        Ok(output::DocRecord::new(doc, accounts, paths))
+    }
+}
+
+impl Application {
+    /// Refresh the full-text search index entries of the specified documents
+    /// in an account, and of everything that they contain, since the rendered
+    /// paths of the latter may have changed too.
+    fn reindex_subtrees(&self, acct_id: i32, doc_ids: &[String]) -> Result<()> {
+        use petgraph::visit::Dfs;
+        use std::collections::HashSet;
+
+        if doc_ids.is_empty() {
+            return Ok(());
+        }
+
+        let table = self.load_linkage_table(acct_id, false)?;
+        let mut to_index = HashSet::new();
+
+        for doc_id in doc_ids {
+            to_index.insert(doc_id.clone());
+
+            if let Some(ix) = table.nodes.get(doc_id) {
+                let mut dfs = Dfs::new(&table.graph, *ix);
+
+                while let Some(nix) = dfs.next(&table.graph) {
+                    to_index.insert(table.graph[nix].clone());
+                }
+            }
+        }
+
+        self.reindex_docs(to_index)
+    }
+
+    /// Rewrite the full-text search index entries of the specified documents.
+    ///
+    /// Each entry contains the document name and all of its rendered parent
+    /// paths, in every account. Documents that are no longer in the database
+    /// are dropped from the index.
+    fn reindex_docs<I: IntoIterator<Item = String>>(&self, doc_ids: I) -> Result<()> {
+        use std::collections::hash_map::Entry;
+
+        let mut linkages = HashMap::new();
+        let mut names: HashMap<String, String> = HashMap::new();
+
+        self.conn.transaction(|| {
+            for the_doc_id in doc_ids {
+                diesel::sql_query("DELETE FROM doc_search WHERE doc_id = ?")
+                    .bind::<Text, _>(&the_doc_id)
+                    .execute(&self.conn)?;
+
+                let maybe_doc = {
+                    use schema::docs::dsl::*;
+                    docs.filter(id.eq(&the_doc_id))
+                        .first::<Doc>(&self.conn)
+                        .optional()?
+                };
+
+                let doc = match maybe_doc {
+                    Some(d) => d,
+                    None => continue,
+                };
+
+                let acct_ids = {
+                    use schema::account_associations::dsl::*;
+                    account_associations
+                        .filter(doc_id.eq(&the_doc_id))
+                        .select(account_id)
+                        .load::<i32>(&self.conn)?
+                };
+
+                let mut paths: Vec<String> = Vec::new();
+
+                for acct_id in acct_ids {
+                    if let Entry::Vacant(e) = linkages.entry(acct_id) {
+                        e.insert(self.load_linkage_table(acct_id, true)?);
+                    }
+
+                    for id_path in linkages[&acct_id].find_parent_paths(&doc.id) {
+                        let mut elems = Vec::with_capacity(id_path.len());
+
+                        for pid in id_path {
+                            if !names.contains_key(&pid) {
+                                use schema::docs::dsl::*;
+                                let n = docs
+                                    .filter(id.eq(&pid))
+                                    .select(name)
+                                    .first::<String>(&self.conn)
+                                    .optional()?
+                                    .unwrap_or_else(|| pid.clone());
+                                names.insert(pid.clone(), n);
+                            }
+
+                            elems.push(names[&pid].clone());
+                        }
+
+                        let p = elems.join(" > ");
+
+                        if !paths.contains(&p) {
+                            paths.push(p);
+                        }
+                    }
+                }
+
+                diesel::sql_query("INSERT INTO doc_search (doc_id, name, paths) VALUES (?, ?, ?)")
+                    .bind::<Text, _>(&doc.id)
+                    .bind::<Text, _>(&doc.name)
+                    .bind::<Text, _>(paths.join("\n"))
+                    .execute(&self.conn)?;
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Make sure that the full-text search index has been populated.
+    ///
+    /// Databases created before the index existed will have an empty one, so
+    /// in that case we fill it in from scratch.
+    pub fn ensure_search_index(&self) -> Result<()> {
+        let probe = diesel::sql_query("SELECT doc_id FROM doc_search LIMIT 1")
+            .load::<database::SearchHit>(&self.conn)?;
+
+        if !probe.is_empty() {
+            return Ok(());
+        }
+
+        self.rebuild_search_index()
+    }
+
+    /// Rebuild the full-text search index from scratch, dropping any entries
+    /// that have gone stale.
+    pub fn rebuild_search_index(&self) -> Result<()> {
+        diesel::sql_query("DELETE FROM doc_search").execute(&self.conn)?;
+
+        let all_ids = {
+            use schema::docs::dsl::*;
+            docs.select(id).load::<String>(&self.conn)?
+        };
+
+        self.reindex_docs(all_ids)
+    }
+
+    /// Search for documents using the full-text index.
+    ///
+    /// Each whitespace-separated word in the search terms must prefix-match a
+    /// word in either the document name or one of its parent paths. The
+    /// results are ranked by relevance, with matches in the name counting
+    /// for more than matches in the paths.
+    pub fn search_docs(&self, terms: &[String]) -> Result<Vec<Doc>> {
+        let words: Vec<_> = terms
+            .iter()
+            .flat_map(|t| t.split_whitespace())
+            .map(|w| format!("\"{}\"*", w.replace('"', "\"\"")))
+            .collect();
+
+        if words.is_empty() {
+            return Err(format_err!("no search terms given"));
+        }
+
+        // The bm25() arguments weight the doc_id, name, and paths columns.
+        let hits = diesel::sql_query(
+            "SELECT doc_id FROM doc_search WHERE doc_search MATCH ? \
+             ORDER BY bm25(doc_search, 0.0, 10.0, 1.0)",
+        )
+        .bind::<Text, _>(words.join(" "))
+        .load::<database::SearchHit>(&self.conn)?;
+
+        let mut results = Vec::with_capacity(hits.len());
+
+        for hit in hits {
+            use schema::docs::dsl::*;
+
+            if let Some(doc) = docs
+                .filter(id.eq(&hit.doc_id))
+                .first::<Doc>(&self.conn)
+                .optional()?
+            {
+                results.push(doc);
+            }
+        }
+
+        Ok(results)
+    }
+}
+
 /// Data about inter-document linkages.
 ///
 /// We have a database table that can store the inter-document linkage
@@ -486,6 +1501,139 @@ impl Application {
 }
 
 impl LinkageTable {
+    /// Get the IDs of the documents immediately contained in a folder.
+    pub fn children(&self, doc_id: &str) -> Vec<String> {
+        let dir = if self.transposed {
+            Direction::Incoming
+        } else {
+            Direction::Outgoing
+        };
+
+        self.neighbor_ids(doc_id, dir)
+    }
+
+    /// Get the IDs of the folders that immediately contain a document.
+    pub fn parents(&self, doc_id: &str) -> Vec<String> {
+        let dir = if self.transposed {
+            Direction::Outgoing
+        } else {
+            Direction::Incoming
+        };
+
+        self.neighbor_ids(doc_id, dir)
+    }
+
+    fn neighbor_ids(&self, doc_id: &str, dir: Direction) -> Vec<String> {
+        match self.nodes.get(doc_id) {
+            Some(ix) => self
+                .graph
+                .neighbors_directed(*ix, dir)
+                .map(|nix| self.graph[nix].clone())
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Return true if a document is contained in a folder, either directly
+    /// or through any number of intermediate folders. A folder counts as
+    /// being inside itself.
+    pub fn is_inside(&self, doc_id: &str, folder_id: &str) -> bool {
+        let (doc_ix, folder_ix) = match (self.nodes.get(doc_id), self.nodes.get(folder_id)) {
+            (Some(d), Some(f)) => (*d, *f),
+            _ => return doc_id == folder_id,
+        };
+
+        if self.transposed {
+            petgraph::algo::has_path_connecting(&self.graph, doc_ix, folder_ix, None)
+        } else {
+            petgraph::algo::has_path_connecting(&self.graph, folder_ix, doc_ix, None)
+        }
+    }
+
+    /// Find the documents contained in a folder, recursively, along with how
+    /// much of each one should be attributed to the folder.
+    ///
+    /// The *self* linkage table must have been loaded with *transpose* set to
+    /// false, so that the graph edges point from parents to children.
+    ///
+    /// With the `Once` policy, every document in the subtree gets a weight
+    /// of 1. With `Apportion`, a document with N parents inherits 1/N of the
+    /// weight of each of them, so that a file that lives both in this folder
+    /// and somewhere else only counts half. Cycles are broken by ignoring the
+    /// links that lead back toward the starting folder, for the purposes of
+    /// both inheritance and counting parents. The return value maps
+    /// document IDs to weights, and includes the folder itself.
+    pub fn subtree_weights(
+        &self,
+        root_id: &str,
+        policy: &MultiParentPolicy,
+    ) -> HashMap<String, f64> {
+        use petgraph::visit::DfsPostOrder;
+
+        assert!(!self.transposed);
+
+        let mut weights = HashMap::new();
+
+        let root_ix = match self.nodes.get(root_id) {
+            Some(ix) => *ix,
+            None => {
+                weights.insert(root_id.to_owned(), 1.);
+                return weights;
+            }
+        };
+
+        // In reverse DFS postorder, every link that isn't part of a cycle
+        // points from an earlier node to a later one.
+
+        let mut order = Vec::new();
+        let mut dfs = DfsPostOrder::new(&self.graph, root_ix);
+
+        while let Some(ix) = dfs.next(&self.graph) {
+            order.push(ix);
+        }
+
+        order.reverse();
+
+        let positions: HashMap<NodeIndex, usize> =
+            order.iter().enumerate().map(|(i, ix)| (*ix, i)).collect();
+        let mut node_weights: HashMap<NodeIndex, f64> = HashMap::new();
+
+        for (i, ix) in order.iter().enumerate() {
+            let weight = if i == 0 {
+                1.
+            } else {
+                match policy {
+                    MultiParentPolicy::Once => 1.,
+
+                    MultiParentPolicy::Apportion => {
+                        // Parents that come later in the ordering are part
+                        // of a cycle, and don't get a share.
+                        let mut n_parents = 0;
+                        let mut inherited = 0.;
+
+                        for p in self.graph.neighbors_directed(*ix, Direction::Incoming) {
+                            match positions.get(&p) {
+                                Some(pi) if *pi < i => {
+                                    n_parents += 1;
+                                    inherited += node_weights[&p];
+                                }
+                                Some(_) => {}
+                                None => n_parents += 1,
+                            }
+                        }
+
+                        inherited / f64::from(n_parents)
+                    }
+                }
+            };
+
+            node_weights.insert(*ix, weight);
+            weights.insert(self.graph[*ix].clone(), weight);
+        }
+
+        weights
+    }
+
     /// Given a document ID, find the set of folders that contain it.
     ///
     /// This is nontrivial because in Google Drive, the folder structure can
@@ -505,80 +1653,60 @@ impl LinkageTable {
     /// understand (e.g. folder JupiterExample for wwt@aas.org).
     ///
     /// The algorithm here is homebrewed because I couldn't find any serious
-    /// discussion of the relevant graph-thory problem. It's basically a
-    /// breadth-first iteration, but it is willing to revisit nodes so long as
-    /// they do not create a cycle within the path being considered.
+    /// discussion of the relevant graph-thory problem. It's a depth-first
+    /// enumeration of the paths leading upward from the document, each of
+    /// which is abandoned if it would revisit one of its own nodes. Each
+    /// partial path carries its own history, so that a folder reachable by
+    /// several routes contributes all of them. Because the number of such
+    /// paths can grow exponentially, the search stops after finding
+    /// `MAX_PARENT_PATHS` of them or considering `MAX_PARENT_PATH_STEPS`
+    /// partial paths.
     pub fn find_parent_paths(&self, start_id: &str) -> Vec<Vec<String>> {
-        use std::collections::HashSet;
-
         assert!(self.transposed);
 
-        let roots: HashSet<NodeIndex> = self.graph.externals(Direction::Outgoing).collect();
-
         let start_ix = match self.nodes.get(start_id) {
             Some(ix) => *ix,
             None => return Vec::new(),
         };
 
-        let mut queue = Vec::new();
-        queue.push(start_ix);
-
-        let mut path_data = HashMap::new();
-        path_data.insert(start_ix, None);
-
+        // Each queue item is a path from the starting document upwards.
+        let mut queue = vec![vec![start_ix]];
         let mut results = Vec::new();
+        let mut steps = 0;
 
-        while !queue.is_empty() {
-            let cur_ix = queue.pop().unwrap();
+        while let Some(path) = queue.pop() {
+            steps += 1;
 
-            if roots.contains(&cur_ix) {
-                // We finished a path!
-                let mut path = Vec::new();
-                let mut ix = cur_ix;
-
-                // Can't do this as a `while let` loop since the bindings shadow
-                #[allow(clippy::while_let_loop)]
-                loop {
-                    if let Some(new_ix) = path_data.get(&ix).unwrap() {
-                        path.push(self.graph.node_weight(ix).unwrap().clone());
-                        ix = *new_ix;
-                    } else {
-                        break;
-                    }
-                }
-
-                results.push(path);
+            if results.len() >= MAX_PARENT_PATHS || steps > MAX_PARENT_PATH_STEPS {
+                break;
             }
 
+            let cur_ix = *path.last().unwrap();
+            let mut is_root = true;
+
             for next_ix in self.graph.neighbors(cur_ix) {
-                // Already enqueued?
-                if queue.contains(&next_ix) {
-                    continue;
-                }
+                is_root = false;
 
                 // Check for loops.
-                let mut ix = cur_ix;
-
-                let found_loop = loop {
-                    if ix == next_ix {
-                        break true;
-                    }
-
-                    if let Some(new_ix) = path_data.get(&ix).unwrap() {
-                        ix = *new_ix;
-                    } else {
-                        break false;
-                    }
-                };
-
-                if found_loop {
+                if path.contains(&next_ix) {
                     continue;
                 }
 
-                // Looks like we should consider this node.
+                let mut longer = path.clone();
+                longer.push(next_ix);
+                queue.push(longer);
+            }
 
-                path_data.insert(next_ix, Some(cur_ix));
-                queue.push(next_ix);
+            if is_root {
+                // We finished a path! Put it in outermost-first order,
+                // leaving off the document itself.
+                results.push(
+                    path[1..]
+                        .iter()
+                        .rev()
+                        .map(|ix| self.graph[*ix].clone())
+                        .collect(),
+                );
             }
         }
 
@@ -591,6 +1719,7 @@ impl LinkageTable {
 pub struct GetDocBuilder<'a> {
     app: &'a mut Application,
     zero_ok: bool,
+    strict: bool,
 }
 
 impl Application {
@@ -602,6 +1731,7 @@ impl Application {
         GetDocBuilder {
             app: self,
             zero_ok: false,
+            strict: false,
         }
     }
 }
@@ -617,11 +1747,22 @@ impl<'a> GetDocBuilder<'a> {
         self
     }
 
+    /// Specify whether names must be matched strictly, regardless of the
+    /// user's matching preference.
+    ///
+    /// Commands that change documents should set this, so that they never
+    /// act on a document that was only guessed at.
+    pub fn strict(mut self, setting: bool) -> Self {
+        self.strict = setting;
+        self
+    }
+
     /// Convert a single specification string into a list of documents,
     /// without applying any validation.
     ///
-    /// If this function returns `Err`, it is because of a genuine problem
-    /// talking to the database or something.
+    /// If this function returns `Err`, it is because the specification could
+    /// not be parsed, or because of a genuine problem talking to the database
+    /// or something.
     fn process_impl(&mut self, spec: &str) -> Result<Vec<Doc>> {
         use schema::docs::dsl::*;
 
@@ -705,15 +1846,230 @@ impl<'a> GetDocBuilder<'a> {
             return Ok(vec![doc]);
         }
 
-        // Partial doc name match?
-        // TODO: ESCAPING
-        let pattern = format!("%{}%", spec);
-        let results = docs
-            .filter(name.like(&pattern))
-            .load::<Doc>(&self.app.conn)?;
+        // Path specifier? If it doesn't resolve to anything, maybe it's a
+        // query that happens to contain a slash, like "mime:text/plain".
+        if spec.contains('/') {
+            let results = self.process_path(spec)?;
+
+            if !results.is_empty() {
+                return Ok(results);
+            }
+        }
+
+        // Otherwise, it's a query. In the simplest case, that's a partial
+        // doc name match.
+        let expr = query::parse(spec)?;
+        let filter = query::compile(&expr, self.app)?;
+        let results = docs.filter(filter).load::<Doc>(&self.app.conn)?;
+
+        if !results.is_empty() {
+            return Ok(results);
+        }
+
+        // If it's just a name and nothing contains it, maybe it's an
+        // abbreviation.
+        if let Some(pattern) = self.fuzzy_pattern(spec) {
+            let results = docs
+                .filter(name.like(fuzzy::like_pattern(&pattern)).escape('\\'))
+                .load::<Doc>(&self.app.conn)?
+                .into_iter()
+                .filter(|d| fuzzy::name_score(&pattern, &d.name).is_some())
+                .collect();
+            return Ok(results);
+        }
+
         Ok(results)
     }
 
+    /// Resolve a slash-separated path specifier.
+    ///
+    /// A path starting with a slash is resolved from the root folder of every
+    /// account, or, if its first component names a shared drive, from the
+    /// root of that drive. A path starting with `.` or `..` is resolved
+    /// relative to the virtual CWD. Any other path is resolved both ways: its
+    /// first component may name an account or shared drive root folder (e.g.
+    /// "My Drive"), or a child of the CWD -- or of the root folders, if there
+    /// is no CWD. Components must match document names exactly. Since
+    /// folders can have multiple parents and contain multiple documents of
+    /// the same name, a path can resolve to any number of documents.
+    fn process_path(&mut self, spec: &str) -> Result<Vec<Doc>> {
+        use std::collections::HashSet;
+
+        let segments: Vec<&str> = spec.split('/').collect();
+        let mut found = HashSet::new();
+
+        let explicitly_relative = segments[0] == "." || segments[0] == "..";
+        let mut all_roots = Vec::new();
+
+        if !explicitly_relative {
+            let mut named_roots = Vec::new();
+            let mut named_drive_roots = Vec::new();
+
+            for maybe_info in accounts::get_accounts()? {
+                let (_email, account) = maybe_info?;
+                let root_name = {
+                    use schema::docs::dsl::*;
+                    docs.filter(id.eq(&account.data.root_folder_id))
+                        .select(name)
+                        .first::<String>(&self.app.conn)
+                        .optional()?
+                };
+                let root = (account.data.db_id, account.data.root_folder_id.clone());
+
+                if segments[0].is_empty() || root_name.as_deref() == Some(segments[0]) {
+                    named_roots.push(root.clone());
+                }
+
+                all_roots.push(root);
+
+                let drive_roots = {
+                    use schema::drives::dsl::*;
+                    drives
+                        .filter(account_id.eq(account.data.db_id))
+                        .select((id, name))
+                        .load::<(String, String)>(&self.app.conn)?
+                };
+
+                for (the_drive_id, drive_name) in drive_roots {
+                    let root = (account.data.db_id, the_drive_id);
+
+                    if drive_name == segments[0] {
+                        named_roots.push(root.clone());
+                    } else if segments[0].is_empty()
+                        && segments.len() > 1
+                        && drive_name == segments[1]
+                    {
+                        named_drive_roots.push(root.clone());
+                    }
+
+                    all_roots.push(root);
+                }
+            }
+
+            found.extend(self.resolve_path(named_roots, &segments[1..])?);
+
+            if !named_drive_roots.is_empty() {
+                found.extend(self.resolve_path(named_drive_roots, &segments[2..])?);
+            }
+        }
+
+        if !segments[0].is_empty() {
+            // An explicitly relative path requires a CWD. An implicitly
+            // relative one is resolved from the account roots if there's
+            // no CWD.
+            let starts = if explicitly_relative {
+                let cwd = self.process_impl(".")?.pop().unwrap();
+                self.positions_of(&cwd)?
+            } else if let Some(cwd) = self.process_impl(".").ok().and_then(|mut v| v.pop()) {
+                self.positions_of(&cwd)?
+            } else {
+                all_roots
+            };
+
+            found.extend(self.resolve_path(starts, &segments)?);
+        }
+
+        let found: Vec<_> = found.into_iter().collect();
+        use schema::docs::dsl::*;
+        Ok(docs.filter(id.eq_any(found)).load::<Doc>(&self.app.conn)?)
+    }
+
+    /// Get the (account ID, document ID) positions of a document, for use as
+    /// a starting point in `resolve_path`.
+    fn positions_of(&mut self, doc: &Doc) -> Result<Vec<(i32, String)>> {
+        Ok(doc
+            .accounts(self.app)?
+            .iter()
+            .map(|acct| (acct.id, doc.id.clone()))
+            .collect())
+    }
+
+    /// Walk a list of path components starting from a set of (account ID,
+    /// document ID) positions, returning the IDs of the documents reached.
+    fn resolve_path(
+        &mut self,
+        starts: Vec<(i32, String)>,
+        segments: &[&str],
+    ) -> Result<Vec<String>> {
+        use std::collections::HashSet;
+
+        let mut tables = HashMap::new();
+        let mut positions: HashSet<(i32, String)> = starts.into_iter().collect();
+
+        for segment in segments {
+            if positions.is_empty() {
+                break;
+            }
+
+            if segment.is_empty() || *segment == "." {
+                continue;
+            }
+
+            for (acct_id, _) in &positions {
+                if !tables.contains_key(acct_id) {
+                    let table = self.app.load_linkage_table(*acct_id, false)?;
+                    tables.insert(*acct_id, table);
+                }
+            }
+
+            if *segment == ".." {
+                positions = positions
+                    .iter()
+                    .flat_map(|(acct_id, doc_id)| {
+                        tables[acct_id]
+                            .parents(doc_id)
+                            .into_iter()
+                            .map(move |pid| (*acct_id, pid))
+                    })
+                    .collect();
+                continue;
+            }
+
+            let candidates: Vec<(i32, String)> = positions
+                .iter()
+                .flat_map(|(acct_id, doc_id)| {
+                    tables[acct_id]
+                        .children(doc_id)
+                        .into_iter()
+                        .map(move |cid| (*acct_id, cid))
+                })
+                .collect();
+
+            let named: HashSet<String> = {
+                use schema::docs::dsl::*;
+                docs.filter(id.eq_any(candidates.iter().map(|(_, cid)| cid)))
+                    .filter(name.eq(segment))
+                    .select(id)
+                    .load::<String>(&self.app.conn)?
+                    .into_iter()
+                    .collect()
+            };
+
+            positions = candidates
+                .into_iter()
+                .filter(|(_, cid)| named.contains(cid))
+                .collect();
+        }
+
+        Ok(positions.into_iter().map(|(_, doc_id)| doc_id).collect())
+    }
+
+    /// If fuzzy matching is enabled and the specification is a plain name,
+    /// get the name.
+    fn fuzzy_pattern(&self, spec: &str) -> Option<String> {
+        if self.strict || self.app.match_mode() != MatchMode::Fuzzy {
+            return None;
+        }
+
+        if spec == "." || spec == ".." || spec.starts_with('%') {
+            return None;
+        }
+
+        query::parse(spec)
+            .ok()
+            .and_then(|e| e.name_pattern().map(|p| p.to_owned()))
+    }
+
     /// Convert a single specification string into a list of documents.
     pub fn process<S: AsRef<str>>(mut self, spec: S) -> Result<Vec<Doc>> {
         let spec = spec.as_ref();
@@ -736,9 +2092,10 @@ impl<'a> GetDocBuilder<'a> {
 
     /// Convert a single specification string into a single document.
     ///
-    /// If not exactly one document matches, an error is raised. In the
-    /// multiple-match case, a listing is printed that is intended to help the
-    /// user narrow down their search.
+    /// If not exactly one document matches, an error is raised -- unless
+    /// names are being matched fuzzily and one of them matches much better
+    /// than the rest. In the multiple-match case, a listing is printed that
+    /// is intended to help the user narrow down their search.
     pub fn process_one<S: AsRef<str>>(mut self, spec: S) -> Result<Doc> {
         let spec = spec.as_ref();
         let mut r = self.process_impl(spec)?;
@@ -754,8 +2111,24 @@ impl<'a> GetDocBuilder<'a> {
             return Ok(r.pop().unwrap());
         }
 
-        // Multiple documents matched. Print a listing, limiting the number of
-        // printed results in case the listing would be super long.
+        // Multiple documents matched. If we're matching fuzzily, rank them,
+        // and go with the best one if it stands out.
+
+        let mut ranked = false;
+
+        if let Some(pattern) = self.fuzzy_pattern(spec) {
+            let mut scored = fuzzy::rank(&pattern, r);
+
+            if fuzzy::has_clear_winner(&pattern, &scored) {
+                return Ok(scored.swap_remove(0).1);
+            }
+
+            r = scored.into_iter().map(|(_score, doc)| doc).collect();
+            ranked = true;
+        }
+
+        // Print a listing, limiting the number of printed results in case the
+        // listing would be super long.
 
         let n = r.len();
         const MAX_TO_PRINT: usize = 20;
@@ -772,6 +2145,10 @@ impl<'a> GetDocBuilder<'a> {
             tcreport!(self.app.ps, error: "{} documents matched the specification \"{}\"\n", n, spec);
         }
 
+        if ranked {
+            tcreport!(self.app.ps, info: "best matches are listed first\n");
+        }
+
         if let Err(e) = self.app.print_doc_list(r) {
             tcreport!(self.app.ps, error: "furthermore, could not access database: {}", e);
         }
@@ -791,3 +2168,580 @@ impl<'a> GetDocBuilder<'a> {
         Ok(docs.load(&self.app.conn)?)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_support::fixture_app;
+
+    /// Build a transposed linkage table from (parent, child) pairs.
+    fn table(links: &[(&str, &str)]) -> LinkageTable {
+        build_table(links, true)
+    }
+
+    fn build_table(links: &[(&str, &str)], transposed: bool) -> LinkageTable {
+        let mut graph = petgraph::Graph::new();
+        let mut nodes = HashMap::new();
+
+        for &(parent, child) in links {
+            let pix = *nodes
+                .entry(parent.to_owned())
+                .or_insert_with(|| graph.add_node(parent.to_owned()));
+            let cix = *nodes
+                .entry(child.to_owned())
+                .or_insert_with(|| graph.add_node(child.to_owned()));
+            if transposed {
+                graph.add_edge(cix, pix, ());
+            } else {
+                graph.add_edge(pix, cix, ());
+            }
+        }
+
+        LinkageTable {
+            account_id: 0,
+            transposed,
+            graph,
+            nodes,
+        }
+    }
+
+    fn sorted_paths(table: &LinkageTable, id: &str) -> Vec<Vec<String>> {
+        let mut paths = table.find_parent_paths(id);
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn parent_paths_multiple_parents() {
+        let t = table(&[("root", "f1"), ("f1", "doc"), ("root", "doc")]);
+        assert_eq!(
+            sorted_paths(&t, "doc"),
+            vec![
+                vec!["root".to_owned()],
+                vec!["root".to_owned(), "f1".to_owned()]
+            ]
+        );
+    }
+
+    #[test]
+    fn parent_paths_converging_routes() {
+        // Both routes pass through "top", which must not suppress either.
+        let t = table(&[
+            ("root", "top"),
+            ("top", "a"),
+            ("top", "b"),
+            ("a", "doc"),
+            ("b", "doc"),
+        ]);
+        assert_eq!(
+            sorted_paths(&t, "doc"),
+            vec![
+                vec!["root".to_owned(), "top".to_owned(), "a".to_owned()],
+                vec!["root".to_owned(), "top".to_owned(), "b".to_owned()],
+            ]
+        );
+    }
+
+    #[test]
+    fn parent_paths_cycle() {
+        let t = table(&[
+            ("root", "f1"),
+            ("f1", "f2"),
+            ("f3", "f2"),
+            ("f2", "f3"),
+            ("f3", "doc"),
+        ]);
+        assert_eq!(
+            sorted_paths(&t, "doc"),
+            vec![vec![
+                "root".to_owned(),
+                "f1".to_owned(),
+                "f2".to_owned(),
+                "f3".to_owned()
+            ]]
+        );
+    }
+
+    #[test]
+    fn parent_paths_unknown_and_orphan() {
+        let t = table(&[("root", "doc")]);
+        assert!(t.find_parent_paths("nonesuch").is_empty());
+        assert_eq!(t.find_parent_paths("root"), vec![Vec::<String>::new()]);
+    }
+
+    #[test]
+    fn is_inside() {
+        let t = table(&[("root", "f1"), ("f1", "f2"), ("f2", "f1"), ("root", "doc")]);
+        assert!(t.is_inside("f2", "f1"));
+        assert!(t.is_inside("f1", "f2"));
+        assert!(t.is_inside("f2", "root"));
+        assert!(t.is_inside("f1", "f1"));
+        assert!(!t.is_inside("root", "f1"));
+        assert!(!t.is_inside("doc", "f1"));
+        assert!(!t.is_inside("nonesuch", "f1"));
+    }
+
+    #[test]
+    fn parent_paths_capped() {
+        // A chain of 30 diamonds has 2^30 routes to the top.
+        let mut links = Vec::new();
+        let mut below = "doc".to_owned();
+
+        for i in 0..30 {
+            let (a, b, top) = (format!("a{}", i), format!("b{}", i), format!("t{}", i));
+            links.push((a.clone(), below.clone()));
+            links.push((b.clone(), below.clone()));
+            links.push((top.clone(), a));
+            links.push((top.clone(), b));
+            below = top;
+        }
+
+        let links: Vec<_> = links
+            .iter()
+            .map(|(p, c)| (p.as_str(), c.as_str()))
+            .collect();
+        let t = table(&links);
+        assert_eq!(t.find_parent_paths("doc").len(), MAX_PARENT_PATHS);
+
+        // A tangle that never reaches a root must still terminate.
+        let names: Vec<_> = (0..12).map(|i| format!("n{}", i)).collect();
+        let mut links = vec![("n0", "doc")];
+
+        for p in &names {
+            for c in &names {
+                if p != c {
+                    links.push((p.as_str(), c.as_str()));
+                }
+            }
+        }
+
+        let t = table(&links);
+        assert!(t.find_parent_paths("doc").is_empty());
+    }
+
+    /// The weights of a subtree, sorted by document ID.
+    fn weights(t: &LinkageTable, root: &str, policy: MultiParentPolicy) -> Vec<(String, f64)> {
+        let mut w: Vec<_> = t.subtree_weights(root, &policy).into_iter().collect();
+        w.sort_by(|a, b| a.0.cmp(&b.0));
+        w
+    }
+
+    fn w(items: &[(&str, f64)]) -> Vec<(String, f64)> {
+        items.iter().map(|(i, x)| ((*i).to_owned(), *x)).collect()
+    }
+
+    #[test]
+    fn subtree_weights_multiple_parents() {
+        let t = build_table(
+            &[
+                ("root", "a"),
+                ("root", "b"),
+                ("a", "doc"),
+                ("b", "doc"),
+                ("a", "sub"),
+                ("sub", "file"),
+            ],
+            false,
+        );
+
+        // Counted fully under each folder ...
+        assert_eq!(
+            weights(&t, "a", MultiParentPolicy::Once),
+            w(&[("a", 1.), ("doc", 1.), ("file", 1.), ("sub", 1.)])
+        );
+        assert_eq!(
+            weights(&t, "b", MultiParentPolicy::Once),
+            w(&[("b", 1.), ("doc", 1.)])
+        );
+
+        // ... or split between them, which adds back up to one above both.
+        assert_eq!(
+            weights(&t, "a", MultiParentPolicy::Apportion),
+            w(&[("a", 1.), ("doc", 0.5), ("file", 1.), ("sub", 1.)])
+        );
+        assert_eq!(
+            weights(&t, "b", MultiParentPolicy::Apportion),
+            w(&[("b", 1.), ("doc", 0.5)])
+        );
+        assert_eq!(
+            weights(&t, "root", MultiParentPolicy::Apportion),
+            w(&[
+                ("a", 1.),
+                ("b", 1.),
+                ("doc", 1.),
+                ("file", 1.),
+                ("root", 1.),
+                ("sub", 1.)
+            ])
+        );
+    }
+
+    #[test]
+    fn subtree_weights_cycle() {
+        let t = build_table(
+            &[
+                ("root", "f1"),
+                ("f1", "f2"),
+                ("f2", "f1"),
+                ("f2", "doc"),
+                ("elsewhere", "doc"),
+            ],
+            false,
+        );
+
+        for policy in &[MultiParentPolicy::Once, MultiParentPolicy::Apportion] {
+            let expected = if *policy == MultiParentPolicy::Once {
+                1.
+            } else {
+                0.5
+            };
+
+            assert_eq!(
+                weights(&t, "root", policy.clone()),
+                w(&[("doc", expected), ("f1", 1.), ("f2", 1.), ("root", 1.)])
+            );
+            // Seen from inside the cycle, f1 also lives in root.
+            assert_eq!(
+                weights(&t, "f2", policy.clone()),
+                w(&[("doc", expected), ("f1", expected), ("f2", 1.)])
+            );
+        }
+
+        assert_eq!(
+            weights(&t, "nonesuch", MultiParentPolicy::Apportion),
+            w(&[("nonesuch", 1.)])
+        );
+    }
+
+    fn doc_name(app: &Application, the_id: &str) -> Option<String> {
+        use schema::docs::dsl::*;
+        docs.filter(id.eq(the_id))
+            .select(name)
+            .first(&app.conn)
+            .optional()
+            .unwrap()
+    }
+
+    fn parents_of(app: &Application, acct: &Account, the_id: &str) -> Vec<String> {
+        let mut parents = app.parent_ids(acct.data.db_id, the_id).unwrap();
+        parents.sort();
+        parents
+    }
+
+    #[test]
+    fn import_fixture() {
+        let dir = tempfile::tempdir().unwrap();
+        let (app, account) = fixture_app(dir.path(), "account.json");
+
+        assert_eq!(doc_name(&app, "ROOT").as_deref(), Some("My Drive"));
+        assert_eq!(doc_name(&app, "D1").as_deref(), Some("Report"));
+        assert_eq!(doc_name(&app, "S1").as_deref(), Some("Their Slides"));
+        assert_eq!(parents_of(&app, &account, "P1"), vec!["F1", "ROOT"]);
+
+        // Shared drives and their contents are imported too.
+        assert_eq!(doc_name(&app, "TD1").as_deref(), Some("Team"));
+        assert_eq!(parents_of(&app, &account, "T1"), vec!["TD1"]);
+
+        let n_perms: i64 = schema::permissions::table
+            .filter(schema::permissions::doc_id.eq("P1"))
+            .count()
+            .get_result(&app.conn)
+            .unwrap();
+        assert_eq!(n_perms, 3);
+    }
+
+    #[test]
+    fn sync_fixture_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut app, mut account) = fixture_app(dir.path(), "account.json");
+
+        app.sync_account("test@example.com", &mut account).unwrap();
+
+        assert_eq!(doc_name(&app, "D1").as_deref(), Some("Final Report"));
+        assert_eq!(doc_name(&app, "D2").as_deref(), Some("Notes"));
+        assert_eq!(parents_of(&app, &account, "D2"), vec!["F1"]);
+        assert_eq!(doc_name(&app, "P2"), None);
+        assert!(parents_of(&app, &account, "P2").is_empty());
+        assert_eq!(doc_name(&app, "T2").as_deref(), Some("Plan"));
+
+        // The change feed has been consumed, so syncing again does nothing.
+        assert!(account.data.change_page_token.is_some());
+        app.sync_account("test@example.com", &mut account).unwrap();
+        assert_eq!(doc_name(&app, "D2").as_deref(), Some("Notes"));
+
+        let renames: Vec<(String, Option<String>)> = {
+            use schema::changes_log::dsl::*;
+            changes_log
+                .filter(kind.eq("renamed"))
+                .select((doc_id, new_value))
+                .load(&app.conn)
+                .unwrap()
+        };
+        assert_eq!(
+            renames,
+            vec![("D1".to_owned(), Some("Final Report".to_owned()))]
+        );
+    }
+
+    #[test]
+    fn forget_drive_keeps_docs_of_other_accounts() {
+        let dir = tempfile::tempdir().unwrap();
+        let (app, account) = fixture_app(dir.path(), "account.json");
+        let acct_id = account.data.db_id;
+        let other_id = acct_id + 1;
+
+        // Pretend that another account can also see one of the documents
+        // in the shared drive.
+        {
+            use schema::account_associations::dsl::*;
+            diesel::insert_into(account_associations)
+                .values((
+                    doc_id.eq("T1"),
+                    account_id.eq(other_id),
+                    owned_by_me.eq(false),
+                ))
+                .execute(&app.conn)
+                .unwrap();
+        }
+
+        let mut forgotten = app.forget_drive(acct_id, "TD1").unwrap();
+        forgotten.sort();
+        assert_eq!(forgotten, vec!["T1", "TD1"]);
+
+        assert_eq!(doc_name(&app, "TD1"), None);
+        assert_eq!(doc_name(&app, "T1").as_deref(), Some("Budget"));
+        assert!(parents_of(&app, &account, "T1").is_empty());
+
+        let remaining: Vec<i32> = {
+            use schema::account_associations::dsl::*;
+            account_associations
+                .filter(doc_id.eq("T1"))
+                .select(account_id)
+                .load(&app.conn)
+                .unwrap()
+        };
+        assert_eq!(remaining, vec![other_id]);
+
+        // Once the last account loses it, it's gone.
+        app.forget_drive(other_id, "TD1").unwrap();
+        assert_eq!(doc_name(&app, "T1"), None);
+    }
+
+    fn n_permissions(app: &Application, the_id: &str) -> i64 {
+        use schema::permissions::dsl::*;
+        permissions
+            .filter(doc_id.eq(the_id))
+            .count()
+            .get_result(&app.conn)
+            .unwrap()
+    }
+
+    #[test]
+    fn shared_query_ignores_case() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut app, _account) = fixture_app(dir.path(), "account.json");
+
+        let perm = google_drive3::Permission {
+            id: Some("444".to_owned()),
+            type_: Some("user".to_owned()),
+            role: Some("reader".to_owned()),
+            email_address: Some("Carol@Example.ORG".to_owned()),
+            ..Default::default()
+        };
+        app.store_permissions("D1", &[perm]).unwrap();
+
+        for spec in &["shared:carol@example.org", "shared:CAROL@example.org"] {
+            let ids: Vec<_> = app
+                .get_docs()
+                .process(spec)
+                .unwrap()
+                .into_iter()
+                .map(|d| d.id)
+                .collect();
+            assert_eq!(ids, vec!["D1"], "{}", spec);
+        }
+    }
+
+    #[test]
+    fn missing_permissions_clear_the_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let (app, account) = fixture_app(dir.path(), "account.json");
+        assert_eq!(n_permissions(&app, "P1"), 3);
+
+        // As if the account could no longer share the file.
+        let change = Change {
+            file_id: Some("P1".to_owned()),
+            removed: Some(false),
+            file: Some(File {
+                id: Some("P1".to_owned()),
+                name: Some("paper.pdf".to_owned()),
+                mime_type: Some("application/pdf".to_owned()),
+                modified_time: Some("2020-04-01T00:00:00Z".to_owned()),
+                parents: Some(vec!["F1".to_owned(), "ROOT".to_owned()]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        app.apply_change(account.data.db_id, &change).unwrap();
+        assert_eq!(n_permissions(&app, "P1"), 0);
+    }
+
+    /// The sorted IDs of the documents that a path specifier resolves to.
+    fn path_ids(app: &mut Application, spec: &str) -> Vec<String> {
+        let mut ids: Vec<_> = app
+            .get_docs()
+            .process_path(spec)
+            .unwrap()
+            .into_iter()
+            .map(|d| d.id)
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn paths_from_the_roots() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut app, _account) = fixture_app(dir.path(), "folders.json");
+
+        assert_eq!(path_ids(&mut app, "/Projects"), vec!["F1"]);
+        assert_eq!(path_ids(&mut app, "/Projects/paper.pdf"), vec!["P1"]);
+        assert_eq!(path_ids(&mut app, "My Drive/Projects"), vec!["F1"]);
+        assert!(path_ids(&mut app, "/My Drive/Projects").is_empty());
+        assert!(path_ids(&mut app, "/Nowhere/paper.pdf").is_empty());
+
+        // Every same-named sibling is a match.
+        assert_eq!(path_ids(&mut app, "/Projects/Report"), vec!["R1", "R2"]);
+
+        // Documents and folders with several parents can be reached through
+        // any of them.
+        assert_eq!(path_ids(&mut app, "/Archive/paper.pdf"), vec!["P1"]);
+        assert_eq!(path_ids(&mut app, "/Projects/Shared/notes.txt"), vec!["N1"]);
+        assert_eq!(path_ids(&mut app, "/Archive/Shared/notes.txt"), vec!["N1"]);
+
+        // Going up from a folder with several parents goes up to all of them.
+        assert_eq!(
+            path_ids(&mut app, "/Archive/Shared/../Report"),
+            vec!["R1", "R2"]
+        );
+        assert_eq!(path_ids(&mut app, "/Projects/Shared/.."), vec!["F1", "F2"]);
+        assert_eq!(
+            path_ids(&mut app, "/Projects/./Shared//notes.txt"),
+            vec!["N1"]
+        );
+
+        // Shared drives are roots too.
+        assert_eq!(path_ids(&mut app, "/Team/Docs"), vec!["TF"]);
+        assert_eq!(path_ids(&mut app, "Team/Docs/Plan"), vec!["TP"]);
+
+        // Without a CWD, other paths start from the roots.
+        assert_eq!(path_ids(&mut app, "Projects/Report"), vec!["R1", "R2"]);
+        assert!(app.get_docs().process_path("./Report").is_err());
+    }
+
+    #[test]
+    fn paths_from_the_cwd() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut app, _account) = fixture_app(dir.path(), "folders.json");
+
+        let archive = app.get_docs().process_one("F2").unwrap();
+        app.set_cwd(&archive).unwrap();
+
+        assert_eq!(path_ids(&mut app, "Shared/notes.txt"), vec!["N1"]);
+        assert_eq!(path_ids(&mut app, "./paper.pdf"), vec!["P1"]);
+        assert_eq!(path_ids(&mut app, "../Projects/Report"), vec!["R1", "R2"]);
+        assert!(path_ids(&mut app, "Projects/Report").is_empty());
+
+        // Absolute paths and root names still work.
+        assert_eq!(path_ids(&mut app, "/Projects/Report"), vec!["R1", "R2"]);
+        assert_eq!(path_ids(&mut app, "My Drive/Archive"), vec!["F2"]);
+        assert_eq!(path_ids(&mut app, "Team/Docs"), vec!["TF"]);
+    }
+
+    fn search_ids(app: &Application, terms: &str) -> Vec<String> {
+        app.search_docs(&[terms.to_owned()])
+            .unwrap()
+            .into_iter()
+            .map(|d| d.id)
+            .collect()
+    }
+
+    fn sorted_search_ids(app: &Application, terms: &str) -> Vec<String> {
+        let mut ids = search_ids(app, terms);
+        ids.sort();
+        ids
+    }
+
+    fn folder_change(the_id: &str, the_name: &str, parents: &[&str]) -> Change {
+        Change {
+            file_id: Some(the_id.to_owned()),
+            removed: Some(false),
+            file: Some(File {
+                id: Some(the_id.to_owned()),
+                name: Some(the_name.to_owned()),
+                mime_type: Some("application/vnd.google-apps.folder".to_owned()),
+                modified_time: Some("2026-10-11T00:00:00Z".to_owned()),
+                parents: Some(parents.iter().map(|p| (*p).to_owned()).collect()),
+                owned_by_me: Some(true),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn search_index_follows_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut app, mut account) = fixture_app(dir.path(), "account.json");
+
+        assert!(search_ids(&app, "final").is_empty());
+        app.sync_account("test@example.com", &mut account).unwrap();
+        assert_eq!(search_ids(&app, "final"), vec!["D1"]);
+        assert_eq!(search_ids(&app, "notes"), vec!["D2"]);
+
+        // Renaming a folder changes the paths of everything inside it.
+        app.fixture
+            .as_mut()
+            .unwrap()
+            .changes
+            .push(folder_change("F1", "Work", &["ROOT"]));
+        app.sync_account("test@example.com", &mut account).unwrap();
+        assert!(search_ids(&app, "projects").is_empty());
+        assert_eq!(
+            sorted_search_ids(&app, "work"),
+            vec!["D1", "D2", "F1", "P1"]
+        );
+
+        // So does moving it.
+        app.fixture.as_mut().unwrap().changes.extend(vec![
+            folder_change("F9", "Clients", &["ROOT"]),
+            folder_change("F1", "Work", &["F9"]),
+        ]);
+        app.sync_account("test@example.com", &mut account).unwrap();
+        assert_eq!(
+            sorted_search_ids(&app, "clients work"),
+            vec!["D1", "D2", "F1", "P1"]
+        );
+        assert_eq!(
+            sorted_search_ids(&app, "clients"),
+            vec!["D1", "D2", "F1", "F9", "P1"]
+        );
+    }
+
+    #[test]
+    fn search_ranks_names_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let (app, _account) = fixture_app(dir.path(), "folders.json");
+
+        // "Back" is only in a loop by way of its path, and comes last even
+        // though it was imported before "loop.txt".
+        assert_eq!(search_ids(&app, "loop")[2], "C2");
+        assert_eq!(sorted_search_ids(&app, "loop"), vec!["C1", "C2", "L1"]);
+
+        assert_eq!(search_ids(&app, "archive")[0], "F2");
+        assert_eq!(
+            sorted_search_ids(&app, "archive"),
+            vec!["F2", "N1", "P1", "S1"]
+        );
+    }
+}
@@ -0,0 +1,872 @@
+// Copyright 2018 Peter Williams <peter@newton.cx>
+// Licensed under the MIT License.
+
+//! Abstraction over the service that answers our Drive API calls.
+//!
+//! Almost everything we do goes through a `google_drive3::DriveHub` talking
+//! to the real Google servers. But it is very handy to be able to run the
+//! synchronization logic against a canned set of documents and changes, so
+//! the core operations are expressed through the `DriveBackend` trait. The
+//! `HubBackend` implementation forwards to the real API, while the
+//! `FixtureBackend` implementation answers from a JSON file on disk.
+
+use chrono::Utc;
+use google_drive3::{
+    About, Change, Comment, Drive as SharedDrive, File, Permission, ReadSeek, Reply, Revision,
+};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use database::FOLDER_MIME_TYPE;
+use errors::Result;
+use google_apis::{self, Drive};
+
+/// The operations that we need to perform against a Google Drive.
+///
+/// Field selection strings have the same syntax as the `fields` parameter of
+/// the web API. Backends that don't talk to the web API are free to ignore
+/// them.
+pub trait DriveBackend {
+    /// Get "about" meta-information about the logged-in Drive account.
+    fn about(&self) -> Result<About>;
+
+    /// Get information about a specific file.
+    ///
+    /// As with the web API, the id "root" refers to the root folder of the
+    /// account.
+    fn get_file(&self, id: &str, fields: &str) -> Result<File>;
+
+    /// Get information about the shared drives that the account is a member
+    /// of.
+    fn list_drives(&self) -> Result<Vec<SharedDrive>>;
+
+    /// Iterate over every file in a corpus.
+    ///
+    /// If *drive_id* is None, the corpus is the account's "drive" space,
+    /// which includes the files in "My Drive" and those that have been shared
+    /// with the user; otherwise, it is the specified shared drive. As with
+    /// the web API, the root folder of the corpus is not included.
+    fn list_files<'s>(
+        &'s self,
+        drive_id: Option<&str>,
+        fields: &str,
+    ) -> Box<dyn Iterator<Item = Result<File>> + 's>;
+
+    /// Get a token that can be used to ask about changes to a corpus that
+    /// happen after this call.
+    ///
+    /// See `list_files` for the meaning of *drive_id*.
+    fn get_start_page_token(&self, drive_id: Option<&str>) -> Result<String>;
+
+    /// Feed every change to a corpus since *page_token* to *handler*,
+    /// returning the token that should be used for the next query.
+    ///
+    /// See `list_files` for the meaning of *drive_id*. We use a callback
+    /// here, rather than returning an iterator, because the new token is only
+    /// known after the iteration is complete.
+    fn list_changes(
+        &self,
+        drive_id: Option<&str>,
+        page_token: &str,
+        fields: &str,
+        handler: &mut dyn FnMut(Change) -> Result<()>,
+    ) -> Result<String>;
+
+    /// Modify the metadata of a file, returning its updated information.
+    ///
+    /// Only the fields that are set in *patch* are changed. The file is
+    /// added to each folder in *add_parents* and removed from each folder in
+    /// *remove_parents*.
+    fn update_file(
+        &self,
+        id: &str,
+        patch: File,
+        add_parents: &[&str],
+        remove_parents: &[&str],
+        fields: &str,
+    ) -> Result<File>;
+
+    /// Create a new file that has no content, such as a folder, returning
+    /// its information.
+    fn create_file(&self, metadata: File, fields: &str) -> Result<File>;
+
+    /// Create a new file with the specified content, returning its
+    /// information.
+    ///
+    /// If the MIME type in *metadata* is a native Google type that differs
+    /// from *content_mime_type*, the content is converted.
+    fn upload_file(
+        &self,
+        metadata: File,
+        content: &mut dyn ReadSeek,
+        content_mime_type: &str,
+        fields: &str,
+    ) -> Result<File>;
+
+    /// Replace the binary content of an existing file, returning its updated
+    /// information.
+    fn update_content(
+        &self,
+        id: &str,
+        content: &mut dyn ReadSeek,
+        content_mime_type: &str,
+        fields: &str,
+    ) -> Result<File>;
+
+    /// Write the binary content of a file to *dest*, returning the number of
+    /// bytes written.
+    fn download_file(&self, id: &str, dest: &mut dyn Write) -> Result<u64>;
+
+    /// Export a native Google document in the specified format, writing the
+    /// result to *dest* and returning the number of bytes written.
+    fn export_file(&self, id: &str, mime_type: &str, dest: &mut dyn Write) -> Result<u64>;
+
+    /// Get all of the permissions that have been granted on a file.
+    fn list_permissions(&self, file_id: &str) -> Result<Vec<Permission>>;
+
+    /// Grant a new permission on a file, returning its information.
+    ///
+    /// If *notify* is true, the grantee is sent an email about it.
+    fn create_permission(
+        &self,
+        file_id: &str,
+        permission: Permission,
+        notify: bool,
+    ) -> Result<Permission>;
+
+    /// Revoke a permission on a file.
+    fn delete_permission(&self, file_id: &str, permission_id: &str) -> Result<()>;
+
+    /// Iterate over the stored revisions of a file, oldest first.
+    fn list_revisions<'s>(
+        &'s self,
+        file_id: &str,
+        fields: &str,
+    ) -> Box<dyn Iterator<Item = Result<Revision>> + 's>;
+
+    /// Write the binary content of a stored revision of a file to *dest*,
+    /// returning the number of bytes written.
+    fn download_revision(
+        &self,
+        file_id: &str,
+        revision_id: &str,
+        dest: &mut dyn Write,
+    ) -> Result<u64>;
+
+    /// Modify the metadata of a stored revision of a file, returning its
+    /// new information.
+    fn update_revision(
+        &self,
+        file_id: &str,
+        revision_id: &str,
+        revision: Revision,
+        fields: &str,
+    ) -> Result<Revision>;
+
+    /// Get all of the comments on a file, with their replies.
+    fn list_comments(&self, file_id: &str) -> Result<Vec<Comment>>;
+
+    /// Reply to a comment on a file, returning the new reply.
+    ///
+    /// If the reply's `action` is "resolve" or "reopen", the comment is
+    /// resolved or reopened accordingly.
+    fn create_reply(&self, file_id: &str, comment_id: &str, reply: Reply) -> Result<Reply>;
+}
+
+/// A backend that talks to the real Google Drive web API.
+pub struct HubBackend<'a> {
+    hub: &'a Drive<'a>,
+}
+
+impl<'a> HubBackend<'a> {
+    /// Wrap a Drive hub.
+    pub fn new(hub: &'a Drive<'a>) -> HubBackend<'a> {
+        HubBackend { hub }
+    }
+}
+
+impl<'a> DriveBackend for HubBackend<'a> {
+    fn about(&self) -> Result<About> {
+        google_apis::get_about(self.hub)
+    }
+
+    fn get_file(&self, id: &str, fields: &str) -> Result<File> {
+        let fields = fields.to_owned();
+        google_apis::get_file(self.hub, id, move |call| {
+            call.supports_all_drives(true).param("fields", &fields)
+        })
+    }
+
+    fn list_drives(&self) -> Result<Vec<SharedDrive>> {
+        google_apis::list_drives(self.hub)
+    }
+
+    fn list_files<'s>(
+        &'s self,
+        drive_id: Option<&str>,
+        fields: &str,
+    ) -> Box<dyn Iterator<Item = Result<File>> + 's> {
+        let fields = fields.to_owned();
+        let drive_id = drive_id.map(|d| d.to_owned());
+
+        Box::new(google_apis::list_files(self.hub, move |call| {
+            let call = call.param("fields", &fields);
+
+            match drive_id.as_ref() {
+                Some(d) => call
+                    .corpora("drive")
+                    .drive_id(d)
+                    .supports_all_drives(true)
+                    .include_items_from_all_drives(true),
+                None => call.spaces("drive"),
+            }
+        }))
+    }
+
+    fn get_start_page_token(&self, drive_id: Option<&str>) -> Result<String> {
+        google_apis::get_start_page_token(self.hub, drive_id)
+    }
+
+    fn list_changes(
+        &self,
+        drive_id: Option<&str>,
+        page_token: &str,
+        fields: &str,
+        handler: &mut dyn FnMut(Change) -> Result<()>,
+    ) -> Result<String> {
+        let fields = fields.to_owned();
+        let drive_id = drive_id.map(|d| d.to_owned());
+
+        // Shared drives have their own change feeds, so the main feed leaves
+        // out their items.
+        let mut lister = google_apis::list_changes(self.hub, page_token, move |call| {
+            let call = call
+                .spaces("drive")
+                .supports_all_drives(true)
+                .include_removed(true)
+                .include_corpus_removals(true)
+                .param("fields", &fields);
+
+            match drive_id.as_ref() {
+                Some(d) => call.drive_id(d).include_items_from_all_drives(true),
+                None => call.include_items_from_all_drives(false),
+            }
+        });
+
+        for maybe_change in lister.iter() {
+            handler(maybe_change?)?;
+        }
+
+        Ok(lister.into_change_page_token())
+    }
+
+    fn update_file(
+        &self,
+        id: &str,
+        patch: File,
+        add_parents: &[&str],
+        remove_parents: &[&str],
+        fields: &str,
+    ) -> Result<File> {
+        let fields = fields.to_owned();
+        let add_parents = add_parents.join(",");
+        let remove_parents = remove_parents.join(",");
+
+        google_apis::update_file(self.hub, id, patch, move |call| {
+            let call = call.supports_all_drives(true).param("fields", &fields);
+
+            let call = if add_parents.is_empty() {
+                call
+            } else {
+                call.add_parents(&add_parents)
+            };
+
+            if remove_parents.is_empty() {
+                call
+            } else {
+                call.remove_parents(&remove_parents)
+            }
+        })
+    }
+
+    fn create_file(&self, metadata: File, fields: &str) -> Result<File> {
+        let fields = fields.to_owned();
+        google_apis::create_file(self.hub, metadata, move |call| {
+            call.supports_all_drives(true).param("fields", &fields)
+        })
+    }
+
+    fn upload_file(
+        &self,
+        metadata: File,
+        content: &mut dyn ReadSeek,
+        content_mime_type: &str,
+        fields: &str,
+    ) -> Result<File> {
+        let fields = fields.to_owned();
+        google_apis::upload_file(
+            self.hub,
+            metadata,
+            content,
+            content_mime_type,
+            move |call| call.supports_all_drives(true).param("fields", &fields),
+        )
+    }
+
+    fn update_content(
+        &self,
+        id: &str,
+        content: &mut dyn ReadSeek,
+        content_mime_type: &str,
+        fields: &str,
+    ) -> Result<File> {
+        let fields = fields.to_owned();
+        google_apis::update_content(
+            self.hub,
+            id,
+            File::default(),
+            content,
+            content_mime_type,
+            move |call| call.supports_all_drives(true).param("fields", &fields),
+        )
+    }
+
+    fn download_file(&self, id: &str, dest: &mut dyn Write) -> Result<u64> {
+        google_apis::download_file(self.hub, id, dest)
+    }
+
+    fn export_file(&self, id: &str, mime_type: &str, dest: &mut dyn Write) -> Result<u64> {
+        google_apis::export_file(self.hub, id, mime_type, dest)
+    }
+
+    fn list_permissions(&self, file_id: &str) -> Result<Vec<Permission>> {
+        google_apis::list_permissions(self.hub, file_id)
+    }
+
+    fn create_permission(
+        &self,
+        file_id: &str,
+        permission: Permission,
+        notify: bool,
+    ) -> Result<Permission> {
+        google_apis::create_permission(self.hub, file_id, permission, move |call| {
+            call.send_notification_email(notify)
+        })
+    }
+
+    fn delete_permission(&self, file_id: &str, permission_id: &str) -> Result<()> {
+        google_apis::delete_permission(self.hub, file_id, permission_id)
+    }
+
+    fn list_revisions<'s>(
+        &'s self,
+        file_id: &str,
+        fields: &str,
+    ) -> Box<dyn Iterator<Item = Result<Revision>> + 's> {
+        let fields = fields.to_owned();
+
+        Box::new(google_apis::list_revisions(
+            self.hub,
+            file_id,
+            move |call| call.param("fields", &fields),
+        ))
+    }
+
+    fn download_revision(
+        &self,
+        file_id: &str,
+        revision_id: &str,
+        dest: &mut dyn Write,
+    ) -> Result<u64> {
+        google_apis::download_revision(self.hub, file_id, revision_id, dest)
+    }
+
+    fn update_revision(
+        &self,
+        file_id: &str,
+        revision_id: &str,
+        revision: Revision,
+        fields: &str,
+    ) -> Result<Revision> {
+        google_apis::update_revision(self.hub, file_id, revision_id, revision, fields)
+    }
+
+    fn list_comments(&self, file_id: &str) -> Result<Vec<Comment>> {
+        google_apis::list_comments(self.hub, file_id)
+    }
+
+    fn create_reply(&self, file_id: &str, comment_id: &str, reply: Reply) -> Result<Reply> {
+        google_apis::create_reply(self.hub, file_id, comment_id, reply)
+    }
+}
+
+/// A backend that answers queries from a fixed set of data.
+///
+/// The data are loaded from a JSON file whose structure mirrors this type. The
+/// `files`, `changes`, `revisions`, and `comments` items use the same JSON
+/// representation as the web API; the permissions of each file are taken from
+/// its `permissions` item. As with the web API, those permissions are left out
+/// of file listings and changes for files in shared drives, and have to be
+/// asked for separately. The change feed is "scripted": page tokens are just
+/// indices into the `changes` list, so that a client that acquires the start
+/// token and then synchronizes will see every change in the list exactly once.
+///
+/// Modifications are applied to the in-memory copy of the data, so that they
+/// are visible for the rest of the process, but they are not saved back to
+/// the fixture file.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct FixtureBackend {
+    /// The "about" information for the account.
+    pub about: About,
+
+    /// The root folder of the account.
+    pub root: File,
+
+    /// All of the other files in the account, including those in shared
+    /// drives.
+    pub files: RefCell<Vec<File>>,
+
+    /// The shared drives that the account is a member of. Files belong to
+    /// them through their `driveId` fields.
+    pub drives: Vec<SharedDrive>,
+
+    /// The scripted list of changes. Changes to shared drives are those with
+    /// a `driveId`, or whose file has one.
+    pub changes: Vec<Change>,
+
+    /// The token handed out by `get_start_page_token`.
+    pub start_page_token: String,
+
+    /// The binary content of files, keyed by file ID.
+    pub contents: RefCell<HashMap<String, String>>,
+
+    /// The exported content of native documents, keyed by file ID and then
+    /// by export MIME type.
+    pub exports: HashMap<String, HashMap<String, String>>,
+
+    /// The stored revisions of files, keyed by file ID.
+    pub revisions: RefCell<HashMap<String, Vec<Revision>>>,
+
+    /// The binary content of stored revisions, keyed by file ID and then by
+    /// revision ID.
+    pub revision_contents: HashMap<String, HashMap<String, String>>,
+
+    /// The comments on files, keyed by file ID.
+    pub comments: RefCell<HashMap<String, Vec<Comment>>>,
+}
+
+impl FixtureBackend {
+    /// Load a fixture from a JSON file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<FixtureBackend> {
+        let file = fs::File::open(path)?;
+        Ok(serde_json::from_reader(file)?)
+    }
+
+    /// Determine which shared drive, if any, a change pertains to.
+    fn change_drive_id(change: &Change) -> Option<&str> {
+        change
+            .drive_id
+            .as_deref()
+            .or_else(|| change.file.as_ref().and_then(|f| f.drive_id.as_deref()))
+    }
+
+    /// Operate on the fixture's record of a file.
+    fn with_file_mut<T, F>(&self, id: &str, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut File) -> Result<T>,
+    {
+        let mut files = self.files.borrow_mut();
+        let file = files
+            .iter_mut()
+            .find(|f| f.id.as_deref() == Some(id))
+            .ok_or_else(|| format_err!("no such file \"{}\" in fixture", id))?;
+        f(file)
+    }
+
+    /// Prepare a file record for a listing or change, leaving out what the
+    /// web API would.
+    fn listed(file: &File) -> File {
+        let mut file = file.clone();
+
+        if file.drive_id.is_some() {
+            file.permissions = None;
+        }
+
+        file
+    }
+
+    /// Parse a page token into an index into the change list.
+    fn change_index(&self, page_token: &str) -> Result<usize> {
+        let index = page_token
+            .parse::<usize>()
+            .map_err(|_| format_err!("invalid fixture page token \"{}\"", page_token))?;
+        Ok(std::cmp::min(index, self.changes.len()))
+    }
+}
+
+impl DriveBackend for FixtureBackend {
+    fn about(&self) -> Result<About> {
+        Ok(self.about.clone())
+    }
+
+    fn get_file(&self, id: &str, _fields: &str) -> Result<File> {
+        if id == "root" || self.root.id.as_deref() == Some(id) {
+            return Ok(self.root.clone());
+        }
+
+        if let Some(file) = self
+            .files
+            .borrow()
+            .iter()
+            .find(|f| f.id.as_deref() == Some(id))
+        {
+            return Ok(file.clone());
+        }
+
+        // The root folder of a shared drive has the same ID as the drive.
+        self.drives
+            .iter()
+            .find(|d| d.id.as_deref() == Some(id))
+            .map(|d| File {
+                id: d.id.clone(),
+                name: d.name.clone(),
+                mime_type: Some(FOLDER_MIME_TYPE.to_owned()),
+                modified_time: d.created_time.clone(),
+                drive_id: d.id.clone(),
+                ..Default::default()
+            })
+            .ok_or_else(|| format_err!("no such file \"{}\" in fixture", id))
+    }
+
+    fn list_drives(&self) -> Result<Vec<SharedDrive>> {
+        Ok(self.drives.clone())
+    }
+
+    fn list_files<'s>(
+        &'s self,
+        drive_id: Option<&str>,
+        _fields: &str,
+    ) -> Box<dyn Iterator<Item = Result<File>> + 's> {
+        let files: Vec<_> = self
+            .files
+            .borrow()
+            .iter()
+            .filter(|f| f.drive_id.as_deref() == drive_id)
+            .map(Self::listed)
+            .collect();
+        Box::new(files.into_iter().map(Ok))
+    }
+
+    fn get_start_page_token(&self, _drive_id: Option<&str>) -> Result<String> {
+        if self.start_page_token.is_empty() {
+            Ok("0".to_owned())
+        } else {
+            Ok(self.start_page_token.clone())
+        }
+    }
+
+    fn list_changes(
+        &self,
+        drive_id: Option<&str>,
+        page_token: &str,
+        _fields: &str,
+        handler: &mut dyn FnMut(Change) -> Result<()>,
+    ) -> Result<String> {
+        let start = self.change_index(page_token)?;
+
+        for change in &self.changes[start..] {
+            if Self::change_drive_id(change) == drive_id {
+                let mut change = change.clone();
+                change.file = change.file.as_ref().map(Self::listed);
+                handler(change)?;
+            }
+        }
+
+        Ok(self.changes.len().to_string())
+    }
+
+    fn update_file(
+        &self,
+        id: &str,
+        patch: File,
+        add_parents: &[&str],
+        remove_parents: &[&str],
+        _fields: &str,
+    ) -> Result<File> {
+        let mut files = self.files.borrow_mut();
+        let file = files
+            .iter_mut()
+            .find(|f| f.id.as_deref() == Some(id))
+            .ok_or_else(|| format_err!("no such file \"{}\" in fixture", id))?;
+
+        if patch.name.is_some() {
+            file.name = patch.name;
+        }
+
+        if patch.starred.is_some() {
+            file.starred = patch.starred;
+        }
+
+        if patch.trashed.is_some() {
+            file.trashed = patch.trashed;
+        }
+
+        let mut parents = file.parents.take().unwrap_or_default();
+        parents.retain(|p| !remove_parents.contains(&p.as_str()));
+
+        for p in add_parents {
+            if !parents.iter().any(|q| q == p) {
+                parents.push((*p).to_owned());
+            }
+        }
+
+        file.parents = Some(parents);
+        file.modified_time = Some(Utc::now().to_rfc3339());
+        Ok(file.clone())
+    }
+
+    fn create_file(&self, metadata: File, _fields: &str) -> Result<File> {
+        // The IDs need to be unique across invocations of the program, since
+        // the database persists but our modifications don't.
+        let now = Utc::now();
+        let mut file = metadata;
+        file.id = Some(format!("fixture-{}", now.timestamp_nanos()));
+        file.modified_time = Some(now.to_rfc3339());
+        let mut files = self.files.borrow_mut();
+        files.push(file.clone());
+        Ok(file)
+    }
+
+    fn upload_file(
+        &self,
+        metadata: File,
+        content: &mut dyn ReadSeek,
+        content_mime_type: &str,
+        fields: &str,
+    ) -> Result<File> {
+        let mut data = Vec::new();
+        content.read_to_end(&mut data)?;
+
+        // Converted documents don't have a size.
+        let mut metadata = metadata;
+
+        if metadata.mime_type.is_none() {
+            metadata.mime_type = Some(content_mime_type.to_owned());
+            metadata.size = Some(data.len().to_string());
+        }
+
+        let file = self.create_file(metadata, fields)?;
+        let id = file.id.clone().unwrap_or_default();
+        self.contents
+            .borrow_mut()
+            .insert(id, String::from_utf8_lossy(&data).into_owned());
+        Ok(file)
+    }
+
+    fn update_content(
+        &self,
+        id: &str,
+        content: &mut dyn ReadSeek,
+        _content_mime_type: &str,
+        _fields: &str,
+    ) -> Result<File> {
+        let mut data = Vec::new();
+        content.read_to_end(&mut data)?;
+
+        let file = self.with_file_mut(id, |file| {
+            file.size = Some(data.len().to_string());
+            file.md5_checksum = None;
+            file.modified_time = Some(Utc::now().to_rfc3339());
+            Ok(file.clone())
+        })?;
+
+        self.contents
+            .borrow_mut()
+            .insert(id.to_owned(), String::from_utf8_lossy(&data).into_owned());
+        Ok(file)
+    }
+
+    fn download_file(&self, id: &str, dest: &mut dyn Write) -> Result<u64> {
+        let content = self
+            .contents
+            .borrow()
+            .get(id)
+            .cloned()
+            .ok_or_else(|| format_err!("no content for file \"{}\" in fixture", id))?;
+        dest.write_all(content.as_bytes())?;
+        Ok(content.len() as u64)
+    }
+
+    fn export_file(&self, id: &str, mime_type: &str, dest: &mut dyn Write) -> Result<u64> {
+        let content = self
+            .exports
+            .get(id)
+            .and_then(|m| m.get(mime_type))
+            .ok_or_else(|| {
+                format_err!("no \"{}\" export of file \"{}\" in fixture", mime_type, id)
+            })?;
+        dest.write_all(content.as_bytes())?;
+        Ok(content.len() as u64)
+    }
+
+    fn list_permissions(&self, file_id: &str) -> Result<Vec<Permission>> {
+        // The root folder of a shared drive is shared with the drive's
+        // members, which we don't model.
+        if self.drives.iter().any(|d| d.id.as_deref() == Some(file_id)) {
+            return Ok(Vec::new());
+        }
+
+        self.with_file_mut(file_id, |file| {
+            Ok(file.permissions.clone().unwrap_or_default())
+        })
+    }
+
+    fn create_permission(
+        &self,
+        file_id: &str,
+        permission: Permission,
+        _notify: bool,
+    ) -> Result<Permission> {
+        self.with_file_mut(file_id, |file| {
+            let mut permission = permission;
+
+            permission.id = Some(match permission.type_.as_deref() {
+                Some("anyone") => "anyoneWithLink".to_owned(),
+                _ => format!("fixture-{}", Utc::now().timestamp_nanos()),
+            });
+
+            file.permissions
+                .get_or_insert_with(Vec::new)
+                .push(permission.clone());
+            Ok(permission)
+        })
+    }
+
+    fn delete_permission(&self, file_id: &str, permission_id: &str) -> Result<()> {
+        self.with_file_mut(file_id, |file| {
+            let permissions = file.permissions.get_or_insert_with(Vec::new);
+            let n = permissions.len();
+            permissions.retain(|p| p.id.as_deref() != Some(permission_id));
+
+            if permissions.len() == n {
+                return Err(format_err!(
+                    "no permission \"{}\" on file \"{}\" in fixture",
+                    permission_id,
+                    file_id
+                ));
+            }
+
+            Ok(())
+        })
+    }
+
+    fn list_revisions<'s>(
+        &'s self,
+        file_id: &str,
+        _fields: &str,
+    ) -> Box<dyn Iterator<Item = Result<Revision>> + 's> {
+        let revisions = self
+            .revisions
+            .borrow()
+            .get(file_id)
+            .cloned()
+            .unwrap_or_default();
+        Box::new(revisions.into_iter().map(Ok))
+    }
+
+    fn download_revision(
+        &self,
+        file_id: &str,
+        revision_id: &str,
+        dest: &mut dyn Write,
+    ) -> Result<u64> {
+        let content = self
+            .revision_contents
+            .get(file_id)
+            .and_then(|m| m.get(revision_id))
+            .ok_or_else(|| {
+                format_err!(
+                    "no content for revision \"{}\" of file \"{}\" in fixture",
+                    revision_id,
+                    file_id
+                )
+            })?;
+        dest.write_all(content.as_bytes())?;
+        Ok(content.len() as u64)
+    }
+
+    fn update_revision(
+        &self,
+        file_id: &str,
+        revision_id: &str,
+        revision: Revision,
+        _fields: &str,
+    ) -> Result<Revision> {
+        let mut revisions = self.revisions.borrow_mut();
+        let rev = revisions
+            .get_mut(file_id)
+            .and_then(|revs| {
+                revs.iter_mut()
+                    .find(|r| r.id.as_deref() == Some(revision_id))
+            })
+            .ok_or_else(|| {
+                format_err!(
+                    "no revision \"{}\" of file \"{}\" in fixture",
+                    revision_id,
+                    file_id
+                )
+            })?;
+
+        if revision.keep_forever.is_some() {
+            rev.keep_forever = revision.keep_forever;
+        }
+
+        if revision.published.is_some() {
+            rev.published = revision.published;
+        }
+
+        Ok(rev.clone())
+    }
+
+    fn list_comments(&self, file_id: &str) -> Result<Vec<Comment>> {
+        Ok(self
+            .comments
+            .borrow()
+            .get(file_id)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    fn create_reply(&self, file_id: &str, comment_id: &str, reply: Reply) -> Result<Reply> {
+        let mut comments = self.comments.borrow_mut();
+        let comment = comments
+            .get_mut(file_id)
+            .and_then(|cs| cs.iter_mut().find(|c| c.id.as_deref() == Some(comment_id)))
+            .ok_or_else(|| {
+                format_err!(
+                    "no comment \"{}\" on file \"{}\" in fixture",
+                    comment_id,
+                    file_id
+                )
+            })?;
+
+        match reply.action.as_deref() {
+            Some("resolve") => comment.resolved = Some(true),
+            Some("reopen") => comment.resolved = Some(false),
+            _ => {}
+        }
+
+        let mut reply = reply;
+        reply.id = Some(format!("fixture-{}", Utc::now().timestamp_nanos()));
+        reply.created_time = Some(Utc::now().to_rfc3339());
+        comment
+            .replies
+            .get_or_insert_with(Vec::new)
+            .push(reply.clone());
+        Ok(reply)
+    }
+}
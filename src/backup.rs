@@ -0,0 +1,956 @@
+// Copyright 2018-2020 Peter Williams <peter@newton.cx>
+// Licensed under the MIT License.
+
+//! One-way backups of entire accounts.
+//!
+//! A backup is either a directory or a zstd-compressed tarball. Its layout
+//! follows the folder hierarchy of the account: the top level holds "My
+//! Drive", any shared drives, and any folders that have been shared with
+//! the account, and native Google documents are exported. A file that lives
+//! in several folders appears in each of them, as hard links to a single
+//! copy of its content.
+//!
+//! Every backup includes a JSON manifest, `manifest.json`, that lists each
+//! file's ID, modification time, paths within the backup, and the MD5
+//! checksum of the content that was saved. When a backup is redone, files
+//! whose modification times and export formats haven't changed since the
+//! previous one are carried over from it rather than fetched again. In a
+//! tarball, the manifest is the first entry.
+
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use accounts;
+use app::Application;
+use content;
+use database::{Doc, SHORTCUT_MIME_TYPE};
+use errors::Result;
+
+/// The name of the manifest within a backup.
+const MANIFEST_NAME: &str = "manifest.json";
+
+/// The record of what went into a backup.
+#[derive(Debug, Deserialize, Serialize)]
+struct Manifest {
+    /// The email address of the account that was backed up.
+    account: String,
+
+    /// When the backup was made.
+    created: DateTime<Utc>,
+
+    /// The paths of the folders in the backup.
+    folders: Vec<String>,
+
+    /// The files in the backup.
+    files: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    /// Get the names of the top-level items in the backup.
+    fn top_level_names(&self) -> HashSet<&str> {
+        self.folders
+            .iter()
+            .chain(self.files.iter().flat_map(|f| f.paths.iter()))
+            .map(|p| p.split('/').next().unwrap_or(p))
+            .collect()
+    }
+}
+
+/// The record of one file in a backup.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct ManifestEntry {
+    /// The document ID.
+    id: String,
+
+    /// The document's name in Drive.
+    name: String,
+
+    /// The document's MIME type in Drive.
+    mime_type: String,
+
+    /// If the document is native, the MIME type that it was exported in.
+    export_mime_type: Option<String>,
+
+    /// The document's modification time.
+    modified_time: DateTime<Utc>,
+
+    /// The size of the saved content.
+    size: u64,
+
+    /// The MD5 checksum of the saved content, in hex.
+    md5_checksum: String,
+
+    /// The paths of the file within the backup. The content is stored at the
+    /// first one, and the others are links to it.
+    paths: Vec<String>,
+}
+
+/// A file to be included in a backup.
+struct Item {
+    doc: Doc,
+
+    /// If the document is native, the MIME type that it is exported in.
+    export_mime_type: Option<String>,
+
+    /// The paths of the file within the backup, in sorted order.
+    paths: Vec<String>,
+}
+
+impl Item {
+    fn manifest_entry(&self, size: u64, md5_checksum: String) -> ManifestEntry {
+        ManifestEntry {
+            id: self.doc.id.clone(),
+            name: self.doc.name.clone(),
+            mime_type: self.doc.mime_type.clone(),
+            export_mime_type: self.export_mime_type.clone(),
+            modified_time: self.doc.utc_mod_time(),
+            size,
+            md5_checksum,
+            paths: self.paths.clone(),
+        }
+    }
+
+    /// Find this file in a previous backup, if it hasn't changed since.
+    fn previous<'m>(&self, old: &'m HashMap<&str, &ManifestEntry>) -> Option<&'m ManifestEntry> {
+        old.get(self.doc.id.as_str()).cloned().filter(|e| {
+            e.modified_time == self.doc.utc_mod_time()
+                && e.export_mime_type == self.export_mime_type
+                && !e.paths.is_empty()
+        })
+    }
+}
+
+/// A writer that keeps track of the size and MD5 checksum of what passes
+/// through it.
+struct HashingWriter<W> {
+    inner: W,
+    context: md5::Context,
+    size: u64,
+}
+
+impl<W: Write> HashingWriter<W> {
+    fn new(inner: W) -> Self {
+        HashingWriter {
+            inner,
+            context: md5::Context::new(),
+            size: 0,
+        }
+    }
+
+    /// Get the total size and the hex checksum.
+    fn finish(mut self) -> Result<(u64, String)> {
+        self.inner.flush()?;
+        Ok((self.size, format!("{:x}", self.context.compute())))
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.context.consume(&buf[..n]);
+        self.size += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Return true if *path* names a tarball rather than a directory.
+fn is_tarball(path: &Path) -> bool {
+    let name = path.to_string_lossy();
+    name.ends_with(".tar.zst") || name.ends_with(".tzst")
+}
+
+/// Make *dest* a hard link to *src*, copying it if that isn't possible
+/// (e.g., on filesystems that don't support links).
+fn link_or_copy(src: &Path, dest: &Path) -> Result<()> {
+    if fs::hard_link(src, dest).is_err() {
+        fs::copy(src, dest)?;
+    }
+
+    Ok(())
+}
+
+/// Read the manifest of an existing tarball, and the paths of the files
+/// that it contains.
+fn scan_tarball(path: &Path) -> Result<(Option<Manifest>, HashSet<String>)> {
+    let mut manifest = None;
+    let mut present = HashSet::new();
+    let mut archive = tar::Archive::new(zstd::Decoder::new(fs::File::open(path)?)?);
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let entry_path = entry.path()?.to_string_lossy().into_owned();
+
+        if entry_path == MANIFEST_NAME {
+            manifest = Some(serde_json::from_reader(&mut entry)?);
+        } else if entry.header().entry_type().is_file() {
+            present.insert(entry_path);
+        }
+    }
+
+    Ok((manifest, present))
+}
+
+/// Make a tar header.
+fn tar_header(kind: tar::EntryType, size: u64, mode: u32, mtime: DateTime<Utc>) -> tar::Header {
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(kind);
+    header.set_size(size);
+    header.set_mode(mode);
+    header.set_mtime(mtime.timestamp().max(0) as u64);
+    header
+}
+
+/// How many files were affected by a backup.
+#[derive(Debug, Default)]
+pub struct BackupSummary {
+    /// The number of files downloaded or exported.
+    pub fetched: usize,
+
+    /// The number of files carried over from the previous backup.
+    pub unchanged: usize,
+
+    /// The number of documents that couldn't be backed up because they
+    /// can't be exported.
+    pub skipped: usize,
+}
+
+/// Back up an account.
+pub struct Backup<'a> {
+    app: &'a mut Application,
+    email: String,
+    account: accounts::Account,
+    export_format: Option<String>,
+    summary: BackupSummary,
+}
+
+impl<'a> Backup<'a> {
+    /// Set up to back up the account with the specified email address.
+    ///
+    /// Native documents are exported in *export_format* if possible, or
+    /// otherwise in a default format.
+    pub fn new(
+        app: &'a mut Application,
+        email: &str,
+        export_format: Option<&str>,
+    ) -> Result<Backup<'a>> {
+        let mut account = None;
+        let mut known = Vec::new();
+
+        for maybe_info in accounts::get_accounts()? {
+            let (this_email, this_account) = maybe_info?;
+
+            if this_email == email {
+                account = Some(this_account);
+            }
+
+            known.push(this_email);
+        }
+
+        let account = account.ok_or_else(|| {
+            known.sort();
+            format_err!(
+                "no logged-in account \"{}\"; known accounts are: {}",
+                email,
+                known.join(", ")
+            )
+        })?;
+
+        Ok(Backup {
+            app,
+            email: email.to_owned(),
+            account,
+            export_format: export_format.map(|f| f.to_owned()),
+            summary: BackupSummary::default(),
+        })
+    }
+
+    /// Figure out the folders and files that make up the backup.
+    fn plan(&mut self) -> Result<(BTreeSet<String>, Vec<Item>)> {
+        let acct_id = self.account.data.db_id;
+        let linkage = self.app.load_linkage_table(acct_id, true)?;
+
+        let docs: HashMap<String, Doc> = {
+            use schema::account_associations::dsl::*;
+            use schema::docs;
+
+            account_associations
+                .inner_join(docs::table)
+                .filter(account_id.eq(acct_id))
+                .filter(docs::trashed.eq(false))
+                .select(docs::all_columns)
+                .load::<Doc>(&self.app.conn)?
+                .into_iter()
+                .map(|d| (d.id.clone(), d))
+                .collect()
+        };
+
+        // Shared drives don't have documents of their own, just names.
+        let drive_names: HashMap<String, String> = {
+            use schema::drives::dsl::*;
+
+            drives
+                .filter(account_id.eq(acct_id))
+                .select((id, name))
+                .load::<(String, String)>(&self.app.conn)?
+                .into_iter()
+                .collect()
+        };
+
+        let needs_about = docs.values().any(|d| d.is_native() && !d.is_folder());
+        let about = if needs_about {
+            let about = self
+                .app
+                .with_backend(&mut self.account, |backend| backend.about())?;
+            Some(about)
+        } else {
+            None
+        };
+
+        // Decide what each document will be called, and how it will be
+        // exported if it's native.
+
+        let mut filenames = HashMap::new();
+        let mut exports = HashMap::new();
+
+        for doc in docs.values() {
+            if doc.is_folder() {
+                filenames.insert(doc.id.as_str(), content::safe_component(&doc.name));
+                continue;
+            }
+
+            if doc.mime_type == SHORTCUT_MIME_TYPE {
+                continue;
+            }
+
+            let export_mime_type = match (doc.is_native(), about.as_ref()) {
+                (true, Some(about)) => {
+                    match content::choose_bulk_export_format(
+                        about,
+                        &doc.mime_type,
+                        self.export_format.as_deref(),
+                    ) {
+                        Ok(m) => Some(m),
+                        Err(e) => {
+                            tcreport!(self.app.ps, warning: "skipping \"{}\": {}", doc.name, e);
+                            self.summary.skipped += 1;
+                            continue;
+                        }
+                    }
+                }
+                _ => None,
+            };
+
+            let name = content::local_filename(&doc.name, export_mime_type.as_deref())
+                .to_string_lossy()
+                .into_owned();
+            filenames.insert(doc.id.as_str(), content::safe_component(&name));
+            exports.insert(doc.id.as_str(), export_mime_type);
+        }
+
+        // Names have to be unique within each folder. Sorting makes the
+        // choice of which duplicate gets a number stable.
+
+        let by_name = |a: &&str, b: &&str| (&filenames[a], a).cmp(&(&filenames[b], b));
+        let mut names = HashMap::new();
+
+        for folder_id in linkage.nodes.keys() {
+            let mut children: Vec<&str> = linkage
+                .children(folder_id)
+                .iter()
+                .filter_map(|c| filenames.get_key_value(c.as_str()).map(|(k, _)| *k))
+                .collect();
+            children.sort_by(by_name);
+
+            let mut taken = HashSet::new();
+
+            for child in children {
+                let name = content::unique_name(filenames[child].clone(), &mut taken);
+                names.insert((folder_id.as_str(), child), name);
+            }
+        }
+
+        // The top level holds the roots of the folder hierarchy: folders
+        // that aren't in any other folder.
+
+        let mut roots: Vec<(String, &str)> = linkage
+            .nodes
+            .keys()
+            .filter(|fid| linkage.parents(fid).is_empty())
+            .filter_map(|fid| {
+                let name = match (docs.get(fid), drive_names.get(fid)) {
+                    (Some(doc), _) if doc.is_folder() => filenames[fid.as_str()].clone(),
+                    (Some(_), _) => return None,
+                    (None, Some(name)) => content::safe_component(name),
+                    // Trashed, or not something we know about.
+                    (None, None) => return None,
+                };
+                Some((name, fid.as_str()))
+            })
+            .collect();
+        roots.sort();
+
+        let mut taken = HashSet::new();
+        let mut top_names = HashMap::new();
+
+        for (name, fid) in roots {
+            top_names.insert(fid, content::unique_name(name, &mut taken));
+        }
+
+        // Now we can assemble the path(s) of every file and folder.
+
+        let paths_of = |doc_id: &str| -> Vec<String> {
+            if let Some(name) = top_names.get(doc_id) {
+                return vec![name.clone()];
+            }
+
+            let mut paths: Vec<String> = linkage
+                .find_parent_paths(doc_id)
+                .into_iter()
+                .filter_map(|ids| {
+                    let mut path = top_names.get(ids.first()?.as_str())?.clone();
+
+                    for pair in ids.windows(2) {
+                        let name = names.get(&(pair[0].as_str(), pair[1].as_str()))?;
+                        path = content::join_path(&path, name);
+                    }
+
+                    let parent = ids.last()?.as_str();
+                    Some(content::join_path(&path, names.get(&(parent, doc_id))?))
+                })
+                .collect();
+
+            paths.sort();
+            paths.dedup();
+            paths
+        };
+
+        let mut folders = BTreeSet::new();
+        let mut items = Vec::new();
+
+        for doc in docs.values() {
+            if doc.is_folder() {
+                folders.extend(paths_of(&doc.id));
+                continue;
+            }
+
+            let export_mime_type = match exports.get(doc.id.as_str()) {
+                Some(e) => e.clone(),
+                None => continue,
+            };
+
+            let paths = paths_of(&doc.id);
+
+            // Files that aren't anywhere in the hierarchy, like ones that
+            // have been shared with the account but not added to it, are
+            // left out.
+            if paths.is_empty() {
+                continue;
+            }
+
+            items.push(Item {
+                doc: doc.clone(),
+                export_mime_type,
+                paths,
+            });
+        }
+
+        // Drive roots are folders too.
+        folders.extend(top_names.values().cloned());
+
+        items.sort_by(|a, b| a.paths.cmp(&b.paths));
+        Ok((folders, items))
+    }
+
+    /// Fetch a file's content, saving it at *dest*. Returns the size and
+    /// checksum of the content.
+    fn fetch(&mut self, item: &Item, dest: &Path) -> Result<(u64, String)> {
+        let action = if item.export_mime_type.is_some() {
+            "export"
+        } else {
+            "download"
+        };
+        tcprintln!(self.app.ps, [green: "{:>9}", action], ("  {}", item.paths[0]));
+
+        let mut w = HashingWriter::new(fs::File::create(dest)?);
+        self.app.download_doc(
+            &mut self.account,
+            &item.doc.id,
+            item.export_mime_type.as_deref(),
+            &mut w,
+        )?;
+        self.summary.fetched += 1;
+        w.finish()
+    }
+
+    fn new_manifest(&self, folders: BTreeSet<String>, files: Vec<ManifestEntry>) -> Manifest {
+        Manifest {
+            account: self.email.clone(),
+            created: Utc::now(),
+            folders: folders.into_iter().collect(),
+            files,
+        }
+    }
+
+    fn check_account(&self, old: &Manifest, out: &Path) -> Result<()> {
+        if old.account != self.email {
+            return Err(format_err!(
+                "\"{}\" is a backup of a different account ({})",
+                out.display(),
+                old.account
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Back up the account into *out*, which is either a directory or a
+    /// `.tar.zst` file. If *out* already holds a backup, it is brought up
+    /// to date.
+    pub fn run(mut self, out: &Path) -> Result<BackupSummary> {
+        let (folders, items) = self.plan()?;
+
+        if is_tarball(out) {
+            self.write_tarball(out, folders, &items)?;
+        } else {
+            self.write_directory(out, folders, &items)?;
+        }
+
+        Ok(self.summary)
+    }
+
+    /// Write the backup as a directory.
+    ///
+    /// The new tree is assembled in a temporary directory, linking to
+    /// unchanged files from the old tree where possible, and then swapped
+    /// into place. Nothing in the old tree is touched until every file has
+    /// been fetched, so a failed run leaves the previous backup usable.
+    fn write_directory(
+        &mut self,
+        dir: &Path,
+        folders: BTreeSet<String>,
+        items: &[Item],
+    ) -> Result<()> {
+        fs::create_dir_all(dir)?;
+        let manifest_path = dir.join(MANIFEST_NAME);
+
+        let old: Option<Manifest> = if manifest_path.exists() {
+            Some(serde_json::from_reader(fs::File::open(&manifest_path)?)?)
+        } else if fs::read_dir(dir)?.next().is_some() {
+            return Err(format_err!(
+                "refusing to back up into \"{}\": it is not empty and is not a previous backup",
+                dir.display()
+            ));
+        } else {
+            None
+        };
+
+        if let Some(ref old) = old {
+            self.check_account(old, dir)?;
+        }
+
+        let old_entries: HashMap<&str, &ManifestEntry> = old
+            .iter()
+            .flat_map(|m| m.files.iter())
+            .map(|e| (e.id.as_str(), e))
+            .collect();
+
+        let staging = tempfile::Builder::new().prefix(".drorg").tempdir_in(dir)?;
+
+        for folder in &folders {
+            fs::create_dir_all(staging.path().join(folder))?;
+        }
+
+        let mut files = Vec::new();
+
+        for item in items {
+            let dest = staging.path().join(&item.paths[0]);
+
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            // The old copy might have gone missing if a previous run was
+            // interrupted.
+            let previous = item.previous(&old_entries).filter(|e| {
+                fs::metadata(dir.join(&e.paths[0]))
+                    .map(|m| m.is_file() && m.len() == e.size)
+                    .unwrap_or(false)
+            });
+
+            // Unchanged files are linked rather than moved, so that the old
+            // tree stays intact until the new one is complete.
+
+            let (size, md5_checksum) = match previous {
+                Some(e) => {
+                    link_or_copy(&dir.join(&e.paths[0]), &dest)?;
+                    self.summary.unchanged += 1;
+                    (e.size, e.md5_checksum.clone())
+                }
+
+                None => self.fetch(item, &dest)?,
+            };
+
+            for path in &item.paths[1..] {
+                link_or_copy(&dest, &staging.path().join(path))?;
+            }
+
+            files.push(item.manifest_entry(size, md5_checksum));
+        }
+
+        // Out with the old ...
+
+        if let Some(ref old) = old {
+            for name in old.top_level_names() {
+                let path = dir.join(name);
+
+                if path.is_dir() {
+                    fs::remove_dir_all(&path)?;
+                } else if path.exists() {
+                    fs::remove_file(&path)?;
+                }
+            }
+        }
+
+        // ... and in with the new.
+
+        for entry in fs::read_dir(staging.path())? {
+            let entry = entry?;
+            fs::rename(entry.path(), dir.join(entry.file_name()))?;
+        }
+
+        let manifest = self.new_manifest(folders, files);
+
+        content::write_atomically(&manifest_path, |w| {
+            serde_json::to_writer_pretty(w, &manifest)?;
+            Ok(())
+        })
+    }
+
+    /// Write the backup as a zstd-compressed tarball.
+    ///
+    /// Tarballs can't be updated in place, so a new one is always written,
+    /// copying unchanged files over from the old one.
+    fn write_tarball(
+        &mut self,
+        path: &Path,
+        folders: BTreeSet<String>,
+        items: &[Item],
+    ) -> Result<()> {
+        // Find out what's in the old tarball, if there is one.
+
+        let (old, present) = if path.exists() {
+            let (old, present) = scan_tarball(path).map_err(|e| {
+                format_err!(
+                    "refusing to overwrite \"{}\": it is not a readable backup ({})",
+                    path.display(),
+                    e
+                )
+            })?;
+
+            let old = old.ok_or_else(|| {
+                format_err!(
+                    "refusing to overwrite \"{}\": it is not a previous backup",
+                    path.display()
+                )
+            })?;
+
+            self.check_account(&old, path)?;
+            (Some(old), present)
+        } else {
+            (None, HashSet::new())
+        };
+
+        let old_entries: HashMap<&str, &ManifestEntry> = old
+            .iter()
+            .flat_map(|m| m.files.iter())
+            .map(|e| (e.id.as_str(), e))
+            .collect();
+
+        // Fetch whatever has changed. We have to do this before writing
+        // anything, since the manifest comes first.
+
+        let destdir = match path.parent() {
+            Some(p) if !p.as_os_str().is_empty() => p.to_owned(),
+            _ => PathBuf::from("."),
+        };
+
+        let scratch = tempfile::Builder::new()
+            .prefix(".drorg")
+            .tempdir_in(&destdir)?;
+        let mut files = Vec::new();
+        let mut reused = HashMap::new();
+        let mut fetched = Vec::new();
+
+        for (i, item) in items.iter().enumerate() {
+            let previous = item
+                .previous(&old_entries)
+                .filter(|e| present.contains(&e.paths[0]));
+
+            let (size, md5_checksum) = match previous {
+                Some(e) => {
+                    reused.insert(e.paths[0].clone(), item);
+                    self.summary.unchanged += 1;
+                    (e.size, e.md5_checksum.clone())
+                }
+
+                None => {
+                    let dest = scratch.path().join(i.to_string());
+                    let info = self.fetch(item, &dest)?;
+                    fetched.push((item, dest));
+                    info
+                }
+            };
+
+            files.push(item.manifest_entry(size, md5_checksum));
+        }
+
+        let manifest = self.new_manifest(folders, files);
+
+        // Now we can write the new tarball.
+
+        let mut temp = tempfile::Builder::new()
+            .prefix(".drorg")
+            .suffix(".tmp")
+            .tempfile_in(&destdir)?;
+
+        {
+            let mut builder = tar::Builder::new(zstd::Encoder::new(&mut temp, 0)?);
+
+            let data = serde_json::to_vec_pretty(&manifest)?;
+            let mut header = tar_header(
+                tar::EntryType::Regular,
+                data.len() as u64,
+                0o644,
+                manifest.created,
+            );
+            builder.append_data(&mut header, MANIFEST_NAME, &data[..])?;
+
+            for folder in &manifest.folders {
+                let mut header = tar_header(tar::EntryType::Directory, 0, 0o755, manifest.created);
+                builder.append_data(&mut header, folder, io::empty())?;
+            }
+
+            if !reused.is_empty() {
+                let mut archive = tar::Archive::new(zstd::Decoder::new(fs::File::open(path)?)?);
+
+                for entry in archive.entries()? {
+                    let mut entry = entry?;
+                    let entry_path = entry.path()?.to_string_lossy().into_owned();
+
+                    if let Some(item) = reused.get(&entry_path) {
+                        let mut header = entry.header().clone();
+                        builder.append_data(&mut header, &item.paths[0], &mut entry)?;
+                    }
+                }
+            }
+
+            for (item, source) in fetched {
+                let file = fs::File::open(&source)?;
+                let mut header = tar_header(
+                    tar::EntryType::Regular,
+                    file.metadata()?.len(),
+                    0o644,
+                    item.doc.utc_mod_time(),
+                );
+                builder.append_data(&mut header, &item.paths[0], file)?;
+            }
+
+            // Links have to come after the files that they point to.
+
+            for item in items {
+                for link in &item.paths[1..] {
+                    let mut header =
+                        tar_header(tar::EntryType::Link, 0, 0o644, item.doc.utc_mod_time());
+                    builder.append_link(&mut header, link, &item.paths[0])?;
+                }
+            }
+
+            builder.into_inner()?.finish()?;
+        }
+
+        temp.persist(path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use test_support::fixture_app;
+
+    const EMAIL: &str = "test@example.com";
+
+    fn backup(app: &mut Application, out: &Path) -> Result<(usize, usize)> {
+        let summary = Backup::new(app, EMAIL, None)?.run(out)?;
+        Ok((summary.fetched, summary.unchanged))
+    }
+
+    fn read_manifest(dir: &Path) -> Manifest {
+        serde_json::from_reader(fs::File::open(dir.join(MANIFEST_NAME)).unwrap()).unwrap()
+    }
+
+    fn change_content(app: &mut Application, the_id: &str, text: &str) {
+        let acct = app
+            .get_docs()
+            .process_one(the_id)
+            .unwrap()
+            .accounts(app)
+            .unwrap()
+            .pop()
+            .unwrap();
+        app.update_doc_content(
+            &acct,
+            the_id,
+            &mut Cursor::new(text.to_owned()),
+            "text/plain",
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut app, _account) = fixture_app(dir.path(), "mirror.json");
+        let out = dir.path().join("backup");
+
+        assert_eq!(backup(&mut app, &out).unwrap(), (4, 0));
+        let projects = out.join("My Drive").join("Projects");
+        assert_eq!(
+            fs::read_to_string(projects.join("a.txt")).unwrap(),
+            "alpha\n"
+        );
+        assert_eq!(
+            fs::read_to_string(projects.join("Notes.pdf")).unwrap(),
+            "%PDF-notes\n"
+        );
+
+        // A file in two folders is stored once and linked into the other.
+        let manifest = read_manifest(&out);
+        let b = manifest.files.iter().find(|e| e.id == "B1").unwrap();
+        assert_eq!(
+            b.paths,
+            vec!["My Drive/Archive/b.txt", "My Drive/Projects/b.txt"]
+        );
+        assert_eq!(b.md5_checksum, "f0cf2a92516045024a0c99147b28f05b");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let meta = fs::metadata(out.join(&b.paths[0])).unwrap();
+            assert_eq!(meta.nlink(), 2);
+            assert_eq!(
+                meta.ino(),
+                fs::metadata(out.join(&b.paths[1])).unwrap().ino()
+            );
+        }
+
+        // Unchanged files are carried over from the previous backup.
+        assert_eq!(backup(&mut app, &out).unwrap(), (0, 4));
+        change_content(&mut app, "A1", "alpha, edited\n");
+        assert_eq!(backup(&mut app, &out).unwrap(), (1, 3));
+        assert_eq!(
+            fs::read_to_string(projects.join("a.txt")).unwrap(),
+            "alpha, edited\n"
+        );
+        assert_eq!(
+            fs::read_to_string(out.join("My Drive/Projects/b.txt")).unwrap(),
+            "beta\n"
+        );
+
+        // ... unless they've gone missing.
+        fs::remove_file(projects.join("c.txt")).unwrap();
+        assert_eq!(backup(&mut app, &out).unwrap(), (1, 3));
+        assert_eq!(
+            fs::read_to_string(projects.join("c.txt")).unwrap(),
+            "gamma\n"
+        );
+    }
+
+    #[test]
+    fn tarball() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut app, _account) = fixture_app(dir.path(), "mirror.json");
+        let out = dir.path().join("backup.tar.zst");
+
+        assert_eq!(backup(&mut app, &out).unwrap(), (4, 0));
+        assert_eq!(backup(&mut app, &out).unwrap(), (0, 4));
+        change_content(&mut app, "A1", "alpha, edited\n");
+        assert_eq!(backup(&mut app, &out).unwrap(), (1, 3));
+
+        let mut archive =
+            tar::Archive::new(zstd::Decoder::new(fs::File::open(&out).unwrap()).unwrap());
+        let mut contents = HashMap::new();
+        let mut links = HashMap::new();
+        let mut first = None;
+
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let entry_path = entry.path().unwrap().to_string_lossy().into_owned();
+            first.get_or_insert_with(|| entry_path.clone());
+
+            match entry.header().entry_type() {
+                tar::EntryType::Regular => {
+                    let mut text = String::new();
+                    io::Read::read_to_string(&mut entry, &mut text).unwrap();
+                    contents.insert(entry_path, text);
+                }
+
+                tar::EntryType::Link => {
+                    let target = entry.link_name().unwrap().unwrap();
+                    links.insert(entry_path, target.to_string_lossy().into_owned());
+                }
+
+                _ => {}
+            }
+        }
+
+        assert_eq!(first.as_deref(), Some(MANIFEST_NAME));
+        assert_eq!(contents["My Drive/Projects/a.txt"], "alpha, edited\n");
+        assert_eq!(contents["My Drive/Archive/b.txt"], "beta\n");
+        assert_eq!(contents["My Drive/Projects/Notes.pdf"], "%PDF-notes\n");
+        assert!(!contents.contains_key("My Drive/Projects/b.txt"));
+        assert_eq!(links["My Drive/Projects/b.txt"], "My Drive/Archive/b.txt");
+    }
+
+    #[test]
+    fn refuses_to_overwrite() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut app, _account) = fixture_app(dir.path(), "mirror.json");
+
+        let out = dir.path().join("stuff");
+        fs::create_dir(&out).unwrap();
+        fs::write(out.join("precious.txt"), "keep me\n").unwrap();
+        assert!(backup(&mut app, &out).is_err());
+        assert_eq!(
+            fs::read_to_string(out.join("precious.txt")).unwrap(),
+            "keep me\n"
+        );
+        assert_eq!(fs::read_dir(&out).unwrap().count(), 1);
+
+        let out = dir.path().join("stuff.tar.zst");
+        fs::write(&out, "not a tarball\n").unwrap();
+        assert!(backup(&mut app, &out).is_err());
+        assert_eq!(fs::read_to_string(&out).unwrap(), "not a tarball\n");
+
+        // Nor will it write over a backup of another account.
+        let out = dir.path().join("other");
+        assert_eq!(backup(&mut app, &out).unwrap(), (4, 0));
+        let mut manifest = read_manifest(&out);
+        manifest.account = "other@example.com".to_owned();
+        serde_json::to_writer(
+            fs::File::create(out.join(MANIFEST_NAME)).unwrap(),
+            &manifest,
+        )
+        .unwrap();
+        assert!(backup(&mut app, &out).is_err());
+    }
+}
@@ -0,0 +1,59 @@
+// Copyright 2018 Peter Williams <peter@newton.cx>
+// Licensed under the MIT License.
+
+//! User configuration settings.
+//!
+//! Settings are read from `config.json` in the app's configuration directory,
+//! next to the `client_id.json` application secret. The file is optional,
+//! and any settings missing from it take their default values.
+
+use app_dirs;
+use serde_json;
+use std::fs;
+use std::io::ErrorKind;
+
+use app::MatchMode;
+use errors::Result;
+
+/// The user's configuration settings.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// How document names given on the command line should be matched,
+    /// "strict" or "fuzzy" (the default). The `--match` option overrides
+    /// this.
+    pub matching: Option<MatchMode>,
+
+    /// Settings relating to document sharing.
+    pub sharing: SharingConfig,
+}
+
+/// Settings relating to document sharing.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct SharingConfig {
+    /// Domains that documents may be shared with without being flagged by
+    /// `drorg audit sharing`. Subdomains of these domains are allowed too.
+    pub allowed_domains: Vec<String>,
+}
+
+impl Config {
+    /// Load the configuration, falling back to the defaults if there is no
+    /// configuration file.
+    pub fn load() -> Result<Config> {
+        let p = app_dirs::get_app_dir(
+            app_dirs::AppDataType::UserConfig,
+            &::APP_INFO,
+            "config.json",
+        )?;
+
+        let f = match fs::File::open(&p) {
+            Ok(f) => f,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(Config::default()),
+            Err(e) => return Err(e.into()),
+        };
+
+        serde_json::from_reader(f)
+            .map_err(|e| format_err!("cannot parse \"{}\": {}", p.display(), e))
+    }
+}
@@ -0,0 +1,264 @@
+// Copyright 2018 Peter Williams <peter@newton.cx>
+// Licensed under the MIT License.
+
+//! Helpers for transferring document content to and from the local disk.
+//!
+//! Files with binary content in Google Drive can be fetched directly, but
+//! "native" Google documents have to be exported into some other format. The
+//! set of formats available for each kind of document is advertised in the
+//! `exportFormats` item of the "about" information, and is expressed in terms
+//! of MIME types; the table here maps them to friendlier short names that
+//! double as filename extensions.
+
+use google_drive3::About;
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use errors::Result;
+
+/// Short format names and the MIME types that they correspond to.
+///
+/// Some formats correspond to more than one MIME type, because Google has
+/// been inconsistent about what it advertises.
+const FORMATS: &[(&str, &str)] = &[
+    ("csv", "text/csv"),
+    (
+        "docx",
+        "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+    ),
+    ("epub", "application/epub+zip"),
+    ("html", "text/html"),
+    ("jpg", "image/jpeg"),
+    ("json", "application/vnd.google-apps.script+json"),
+    ("md", "text/markdown"),
+    ("odp", "application/vnd.oasis.opendocument.presentation"),
+    ("ods", "application/vnd.oasis.opendocument.spreadsheet"),
+    ("ods", "application/x-vnd.oasis.opendocument.spreadsheet"),
+    ("odt", "application/vnd.oasis.opendocument.text"),
+    ("pdf", "application/pdf"),
+    ("png", "image/png"),
+    (
+        "pptx",
+        "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+    ),
+    ("rtf", "application/rtf"),
+    ("svg", "image/svg+xml"),
+    ("tsv", "text/tab-separated-values"),
+    ("txt", "text/plain"),
+    (
+        "xlsx",
+        "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+    ),
+    ("zip", "application/zip"),
+];
+
+/// The export formats that we try, in order, if the user doesn't ask for one.
+const DEFAULT_EXPORT_PREFERENCES: &[&str] = &["pdf", "png", "json"];
+
+/// Get the short format name (and filename extension) for a MIME type, if we
+/// know of one.
+pub fn extension_for_mime(mime_type: &str) -> Option<&'static str> {
+    FORMATS
+        .iter()
+        .find(|(_, m)| *m == mime_type)
+        .map(|(ext, _)| *ext)
+}
+
+/// Get the MIME types that might be meant by a format name.
+///
+/// Anything with a slash in it is assumed to be a MIME type already.
+fn mime_types_for_format(format: &str) -> Vec<&str> {
+    if format.contains('/') {
+        return vec![format];
+    }
+
+    FORMATS
+        .iter()
+        .filter(|(ext, _)| ext.eq_ignore_ascii_case(format))
+        .map(|(_, mime)| *mime)
+        .collect()
+}
+
+/// Guess the MIME type of a local file from its extension.
+pub fn mime_for_path<P: AsRef<Path>>(path: P) -> &'static str {
+    path.as_ref()
+        .extension()
+        .and_then(|e| e.to_str())
+        .and_then(|ext| {
+            FORMATS
+                .iter()
+                .find(|(e, _)| e.eq_ignore_ascii_case(ext))
+                .map(|(_, mime)| *mime)
+        })
+        .unwrap_or("application/octet-stream")
+}
+
+/// Figure out which native Google type, if any, content of the specified
+/// MIME type can be converted into on upload.
+///
+/// This is based on the `importFormats` item of the "about" information.
+pub fn choose_import_format(about: &About, content_mime_type: &str) -> Option<String> {
+    about
+        .import_formats
+        .as_ref()
+        .and_then(|m| m.get(content_mime_type))
+        .and_then(|targets| targets.first())
+        .cloned()
+}
+
+/// Figure out which MIME type to use when exporting a native document.
+///
+/// If *requested* is None, we choose a sensible default from among the
+/// formats that are available.
+pub fn choose_export_format(
+    about: &About,
+    doc_mime_type: &str,
+    requested: Option<&str>,
+) -> Result<String> {
+    let available = about
+        .export_formats
+        .as_ref()
+        .and_then(|m| m.get(doc_mime_type))
+        .ok_or_else(|| format_err!("documents of type \"{}\" cannot be exported", doc_mime_type))?;
+
+    if let Some(format) = requested {
+        for mime in mime_types_for_format(format) {
+            if available.iter().any(|a| a == mime) {
+                return Ok(mime.to_owned());
+            }
+        }
+
+        let names: Vec<_> = available
+            .iter()
+            .map(|m| extension_for_mime(m).unwrap_or(m))
+            .collect();
+
+        return Err(format_err!(
+            "cannot export this document as \"{}\"; available formats are: {}",
+            format,
+            names.join(", ")
+        ));
+    }
+
+    for format in DEFAULT_EXPORT_PREFERENCES {
+        for mime in mime_types_for_format(format) {
+            if available.iter().any(|a| a == mime) {
+                return Ok(mime.to_owned());
+            }
+        }
+    }
+
+    available
+        .first()
+        .cloned()
+        .ok_or_else(|| format_err!("server did not advertise any export formats"))
+}
+
+/// Choose an export format for one of many documents of mixed types.
+///
+/// When a whole folder is being exported, the requested format won't suit
+/// every kind of document (there's no such thing as a spreadsheet in DOCX
+/// format), so documents that can't be exported as requested fall back to
+/// the default.
+pub fn choose_bulk_export_format(
+    about: &About,
+    doc_mime_type: &str,
+    requested: Option<&str>,
+) -> Result<String> {
+    requested
+        .and_then(|f| choose_export_format(about, doc_mime_type, Some(f)).ok())
+        .map(Ok)
+        .unwrap_or_else(|| choose_export_format(about, doc_mime_type, None))
+}
+
+/// Come up with a local filename for a document.
+///
+/// Drive names can contain slashes, which we can't put in a filename. If
+/// the document is being exported, an appropriate extension is appended to
+/// the name if it doesn't already have one.
+pub fn local_filename(name: &str, export_mime_type: Option<&str>) -> PathBuf {
+    let mut filename = name.replace('/', "_");
+
+    if let Some(ext) = export_mime_type.and_then(extension_for_mime) {
+        let suffix = format!(".{}", ext);
+
+        if !filename.to_lowercase().ends_with(&suffix) {
+            filename.push_str(&suffix);
+        }
+    }
+
+    PathBuf::from(filename)
+}
+
+/// Make a document name safe to use as a single path component.
+pub fn safe_component(name: &str) -> String {
+    let name = local_filename(name, None).to_string_lossy().into_owned();
+
+    match name.as_str() {
+        "" | "." | ".." => format!("_{}", name),
+        _ => name,
+    }
+}
+
+/// Come up with a name that isn't in *taken*, by adding a number to *name*
+/// before its extension if needed.
+pub fn unique_name(name: String, taken: &mut HashSet<String>) -> String {
+    if taken.insert(name.clone()) {
+        return name;
+    }
+
+    let (stem, ext) = match name.rfind('.') {
+        Some(i) if i > 0 => (&name[..i], &name[i..]),
+        _ => (&name[..], ""),
+    };
+
+    let mut n = 2;
+
+    loop {
+        let candidate = format!("{} ({}){}", stem, n, ext);
+
+        if taken.insert(candidate.clone()) {
+            return candidate;
+        }
+
+        n += 1;
+    }
+}
+
+/// Join a directory and a file name into a relative path.
+pub fn join_path(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+/// Write a file by way of a temporary file, so that the destination is never
+/// left in a half-written state.
+///
+/// The temporary file is created in the same directory as the destination so
+/// that the final rename doesn't cross filesystems.
+pub fn write_atomically<P, F>(path: P, f: F) -> Result<()>
+where
+    P: AsRef<Path>,
+    F: FnOnce(&mut dyn Write) -> Result<()>,
+{
+    let path = path.as_ref();
+
+    let destdir = match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p.to_owned(),
+        _ => PathBuf::from("."),
+    };
+
+    let mut temp = tempfile::Builder::new()
+        .prefix(".drorg")
+        .suffix(".tmp")
+        .tempfile_in(destdir)?;
+
+    f(&mut temp)?;
+    temp.flush()?;
+    temp.persist(path)?;
+    Ok(())
+}
@@ -10,6 +10,7 @@ use chrono::{DateTime, NaiveDateTime, Utc};
 use diesel::prelude::*;
 use diesel::sqlite::SqliteConnection;
 use google_drive3;
+use std::path::Path;
 
 use app::Application;
 use database;
@@ -17,15 +18,20 @@ use errors::Result;
 use schema::*;
 
 /// Connect to the Sqlite database.
-pub fn get_db_connection() -> Result<SqliteConnection> {
-    let p = app_dirs::get_app_dir(
-        app_dirs::AppDataType::UserData,
-        &super::APP_INFO,
-        "db.sqlite",
-    )?;
+///
+/// If *path* is None, the database in the user's data directory is used.
+pub fn get_db_connection(path: Option<&Path>) -> Result<SqliteConnection> {
+    let p = match path {
+        Some(p) => p.to_owned(),
+        None => app_dirs::get_app_dir(
+            app_dirs::AppDataType::UserData,
+            &super::APP_INFO,
+            "db.sqlite",
+        )?,
+    };
     let as_str = p
         .to_str()
-        .ok_or_else(|| format_err!("cannot express user data path as Unicode"))?;
+        .ok_or_else(|| format_err!("cannot express the database path as Unicode"))?;
     Ok(SqliteConnection::establish(as_str)?)
 }
 
@@ -66,6 +72,12 @@ impl<'a> NewAccount<'a> {
     }
 }
 
+/// The MIME type that Google Drive uses for folders.
+pub const FOLDER_MIME_TYPE: &str = "application/vnd.google-apps.folder";
+
+/// The MIME type of Drive shortcuts, which have no content of their own.
+pub const SHORTCUT_MIME_TYPE: &str = "application/vnd.google-apps.shortcut";
+
 /// A document residing on a Google Drive.
 #[derive(Clone, Debug, Eq, Identifiable, PartialEq, Queryable)]
 #[table_name = "docs"]
@@ -101,6 +113,22 @@ pub struct Doc {
 
     /// The size of this file in bytes, if it has binary content in Google Drive.
     pub size: Option<i32>,
+
+    /// The MD5 checksum of this file's content, if it has binary content in
+    /// Google Drive.
+    pub md5_checksum: Option<String>,
+
+    /// The ID of the shared drive that this document lives in, if it isn't
+    /// in a "My Drive".
+    pub drive_id: Option<String>,
+
+    /// The email address of the user who owns this document, if it has an
+    /// owner. Documents in shared drives don't.
+    pub owner_email: Option<String>,
+
+    /// The display name of the user who owns this document, if it has an
+    /// owner.
+    pub owner_name: Option<String>,
 }
 
 impl Doc {
@@ -121,7 +149,13 @@ impl Doc {
 
     /// Return true if this document is a folder.
     pub fn is_folder(&self) -> bool {
-        self.mime_type == "application/vnd.google-apps.folder"
+        self.mime_type == FOLDER_MIME_TYPE
+    }
+
+    /// Return true if this document is a "native" Google document, which
+    /// has no binary content and must be exported to be downloaded.
+    pub fn is_native(&self) -> bool {
+        self.mime_type.starts_with("application/vnd.google-apps.")
     }
 
     /// Format the size of this document in a human-friendly way, if it is
@@ -139,16 +173,25 @@ impl Doc {
 
     /// Discover which accounts this document is associated with.
     pub fn accounts(&self, app: &mut Application) -> Result<Vec<database::Account>> {
+        let accounts: Vec<_> = self
+            .associations(app)?
+            .into_iter()
+            .map(|(_assoc, account)| account)
+            .collect();
+        Ok(accounts)
+    }
+
+    /// Get this document's associations with the accounts that it belongs
+    /// to, which carry the account-specific information about it.
+    pub fn associations(
+        &self,
+        app: &mut Application,
+    ) -> Result<Vec<(database::AccountAssociation, database::Account)>> {
         use schema::account_associations::dsl::*;
-        let associations = account_associations
+        Ok(account_associations
             .inner_join(accounts::table)
             .filter(doc_id.eq(&self.id))
-            .load::<(database::AccountAssociation, database::Account)>(&app.conn)?;
-        let accounts: Vec<_> = associations
-            .iter()
-            .map(|(_assoc, account)| account.clone())
-            .collect();
-        Ok(accounts)
+            .load::<(database::AccountAssociation, database::Account)>(&app.conn)?)
     }
 }
 
@@ -180,6 +223,19 @@ pub struct NewDoc<'a> {
 
     /// The size of this file in bytes, if it has binary content in Google Drive.
     pub size: Option<i32>,
+
+    /// The MD5 checksum of this file's content, if it has binary content in
+    /// Google Drive.
+    pub md5_checksum: Option<&'a str>,
+
+    /// The ID of the shared drive that this document lives in, if any.
+    pub drive_id: Option<&'a str>,
+
+    /// The email address of the user who owns this document, if any.
+    pub owner_email: Option<&'a str>,
+
+    /// The display name of the user who owns this document, if any.
+    pub owner_name: Option<&'a str>,
 }
 
 impl<'a> NewDoc<'a> {
@@ -203,6 +259,14 @@ impl<'a> NewDoc<'a> {
             Some(text) => Some(text.parse()?), // I don't think there's a better way to unwrap?
             None => None,
         };
+        let md5_checksum = file.md5_checksum.as_deref();
+        let drive_id = file.drive_id.as_deref();
+
+        // Only some legacy files have more than one owner. We just keep
+        // track of the first.
+        let owner = file.owners.as_ref().and_then(|o| o.first());
+        let owner_email = owner.and_then(|u| u.email_address.as_deref());
+        let owner_name = owner.and_then(|u| u.display_name.as_deref());
 
         Ok(NewDoc {
             id,
@@ -212,6 +276,10 @@ impl<'a> NewDoc<'a> {
             trashed,
             modified_time,
             size,
+            md5_checksum,
+            drive_id,
+            owner_email,
+            owner_name,
         })
     }
 }
@@ -272,6 +340,17 @@ pub struct AccountAssociation {
     /// Each document is associated with at least one, but maybe more than
     /// one, account.
     pub account_id: i32,
+
+    /// Whether the account owns the document.
+    pub owned_by_me: bool,
+
+    /// The email address of the user who shared the document with the
+    /// account, if it was shared and the API told us who did it.
+    pub sharing_user_email: Option<String>,
+
+    /// The display name of the user who shared the document with the
+    /// account, if known.
+    pub sharing_user_name: Option<String>,
 }
 
 /// Data representing a new account association row to insert into the
@@ -289,15 +368,382 @@ pub struct NewAccountAssociation<'a> {
 
     /// The ID of the associated account.
     pub account_id: i32,
+
+    /// Whether the account owns the document.
+    pub owned_by_me: bool,
+
+    /// The email address of the user who shared the document with the
+    /// account, if known.
+    pub sharing_user_email: Option<&'a str>,
+
+    /// The display name of the user who shared the document with the
+    /// account, if known.
+    pub sharing_user_name: Option<&'a str>,
 }
 
 impl<'a> NewAccountAssociation<'a> {
-    /// Create a new account association record.
-    pub fn new(doc_id: &'a str, account_id: i32) -> NewAccountAssociation<'a> {
-        NewAccountAssociation { doc_id, account_id }
+    /// Fill in an account association record from a file returned by the
+    /// drive3 API on behalf of the account.
+    pub fn from_api_object(
+        doc_id: &'a str,
+        account_id: i32,
+        file: &'a google_drive3::File,
+    ) -> NewAccountAssociation<'a> {
+        let sharer = file.sharing_user.as_ref();
+
+        NewAccountAssociation {
+            doc_id,
+            account_id,
+            owned_by_me: file.owned_by_me.unwrap_or(false),
+            sharing_user_email: sharer.and_then(|u| u.email_address.as_deref()),
+            sharing_user_name: sharer.and_then(|u| u.display_name.as_deref()),
+        }
+    }
+}
+
+/// A shared drive that an account is a member of.
+///
+/// The root folder of a shared drive is a document whose ID is the same as
+/// the drive's.
+#[derive(Debug, Eq, PartialEq, Queryable)]
+pub struct SharedDrive {
+    /// The ID of the account that is a member of the drive.
+    pub account_id: i32,
+
+    /// The unique identifier of the drive and its root folder.
+    pub id: String,
+
+    /// The current name of the drive.
+    pub name: String,
+}
+
+/// Data representing a new shared drive row to insert into the database.
+///
+/// See the documentation for `SharedDrive` for explanations of the fields.
+/// This type is different than SharedDrive in that it contains references to
+/// borrowed values for non-Copy types, rather than owned values.
+#[derive(Debug, Eq, Insertable, PartialEq)]
+#[table_name = "drives"]
+pub struct NewSharedDrive<'a> {
+    /// The ID of the account that is a member of the drive.
+    pub account_id: i32,
+
+    /// The unique identifier of the drive and its root folder.
+    pub id: &'a str,
+
+    /// The current name of the drive.
+    pub name: &'a str,
+}
+
+impl<'a> NewSharedDrive<'a> {
+    /// Fill in a database record from a drive returned by the drive3 API.
+    pub fn from_api_object(
+        account_id: i32,
+        drive: &'a google_drive3::Drive,
+    ) -> Result<NewSharedDrive<'a>> {
+        let id = drive
+            .id
+            .as_ref()
+            .ok_or_else(|| format_err!("no ID provided with shared drive object"))?;
+        let name = drive.name.as_ref().map_or("???", |s| s);
+
+        Ok(NewSharedDrive {
+            account_id,
+            id,
+            name,
+        })
+    }
+}
+
+/// A permission granted on a document.
+///
+/// These are cached from the API so that we can ask questions about sharing
+/// across all documents without issuing an API call for each one. The cache
+/// is only as good as what the API tells us: it doesn't report permissions
+/// on files that the user can't share.
+#[derive(Clone, Debug, Eq, PartialEq, Queryable)]
+pub struct Permission {
+    /// The ID of the document that the permission applies to.
+    pub doc_id: String,
+
+    /// The ID of the permission. This identifies the grantee, and is the
+    /// same for all of the documents that they have access to.
+    pub id: String,
+
+    /// The kind of grantee: "user", "group", "domain", or "anyone".
+    pub grantee_type: String,
+
+    /// The role granted, such as "owner", "writer", "commenter", or
+    /// "reader".
+    pub role: String,
+
+    /// The email address of the grantee, for users and groups.
+    pub email_address: Option<String>,
+
+    /// The domain of the grantee, for domain permissions.
+    pub domain: Option<String>,
+
+    /// The "pretty" name of the grantee, if there is one.
+    pub display_name: Option<String>,
+
+    /// For domain and anyone permissions, whether the document can be found
+    /// by searching, rather than only by people who have the link.
+    pub allow_file_discovery: bool,
+}
+
+impl Permission {
+    /// Get a short human-readable description of who the permission is
+    /// granted to.
+    pub fn grantee(&self) -> String {
+        match self.grantee_type.as_str() {
+            "anyone" => {
+                if self.allow_file_discovery {
+                    "anyone on the web".to_owned()
+                } else {
+                    "anyone with the link".to_owned()
+                }
+            }
+
+            "domain" => {
+                let domain = self.domain.as_deref().unwrap_or("???");
+
+                if self.allow_file_discovery {
+                    format!("anyone at {}", domain)
+                } else {
+                    format!("anyone at {} with the link", domain)
+                }
+            }
+
+            _ => match (self.display_name.as_ref(), self.email_address.as_ref()) {
+                (Some(n), Some(e)) => format!("{} <{}>", n, e),
+                (None, Some(e)) => e.clone(),
+                (Some(n), None) => n.clone(),
+                (None, None) => self.id.clone(),
+            },
+        }
     }
 }
 
+/// Data representing a new permission row to insert into the database.
+///
+/// See the documentation for `Permission` for explanations of the fields.
+/// This type is different than Permission in that it contains references to
+/// borrowed values for non-Copy types, rather than owned values.
+#[derive(Debug, Eq, Insertable, PartialEq)]
+#[table_name = "permissions"]
+pub struct NewPermission<'a> {
+    /// The ID of the document that the permission applies to.
+    pub doc_id: &'a str,
+
+    /// The ID of the permission.
+    pub id: &'a str,
+
+    /// The kind of grantee.
+    pub grantee_type: &'a str,
+
+    /// The role granted.
+    pub role: &'a str,
+
+    /// The email address of the grantee, for users and groups.
+    pub email_address: Option<&'a str>,
+
+    /// The domain of the grantee, for domain permissions.
+    pub domain: Option<&'a str>,
+
+    /// The "pretty" name of the grantee, if there is one.
+    pub display_name: Option<&'a str>,
+
+    /// Whether the document can be found by searching.
+    pub allow_file_discovery: bool,
+}
+
+impl<'a> NewPermission<'a> {
+    /// Fill in a database record from a permission returned by the drive3
+    /// API.
+    pub fn from_api_object(
+        doc_id: &'a str,
+        perm: &'a google_drive3::Permission,
+    ) -> Result<NewPermission<'a>> {
+        let id = perm
+            .id
+            .as_ref()
+            .ok_or_else(|| format_err!("no ID provided with permission object"))?;
+        let grantee_type = perm.type_.as_ref().map_or("", |s| s);
+        let role = perm.role.as_ref().map_or("", |s| s);
+
+        Ok(NewPermission {
+            doc_id,
+            id,
+            grantee_type,
+            role,
+            email_address: perm.email_address.as_deref(),
+            domain: perm.domain.as_deref(),
+            display_name: perm.display_name.as_deref(),
+            allow_file_discovery: perm.allow_file_discovery.unwrap_or(false),
+        })
+    }
+}
+
+/// A pairing of a Drive folder with a local directory, kept in sync by
+/// `drorg mirror`.
+#[derive(Debug, Eq, PartialEq, Queryable)]
+pub struct Mirror {
+    /// The unique identifier of this mirror.
+    pub id: i32,
+
+    /// The ID of the account through which the folder is accessed.
+    pub account_id: i32,
+
+    /// The ID of the mirrored folder.
+    pub folder_id: String,
+
+    /// The absolute path of the local directory.
+    pub local_dir: String,
+
+    /// The format that native documents are exported in, if the user chose
+    /// one.
+    pub export_format: Option<String>,
+}
+
+/// Data representing a new mirror row to insert into the database.
+///
+/// See the documentation for `Mirror` for explanations of the fields. The
+/// ID is assigned by the database.
+#[derive(Debug, Eq, Insertable, PartialEq)]
+#[table_name = "mirrors"]
+pub struct NewMirror<'a> {
+    /// The ID of the account through which the folder is accessed.
+    pub account_id: i32,
+
+    /// The ID of the mirrored folder.
+    pub folder_id: &'a str,
+
+    /// The absolute path of the local directory.
+    pub local_dir: &'a str,
+
+    /// The format that native documents are exported in, if the user chose
+    /// one.
+    pub export_format: Option<&'a str>,
+}
+
+/// The state of a file in a mirror as of the last time that it was
+/// synchronized.
+///
+/// Comparing this against the current state of each side tells us which
+/// side has changed.
+#[derive(Clone, Debug, Eq, PartialEq, Queryable)]
+pub struct MirrorEntry {
+    /// The ID of the mirror that this file belongs to.
+    pub mirror_id: i32,
+
+    /// The path of the file relative to the mirror directory, with
+    /// components separated by slashes.
+    pub path: String,
+
+    /// The ID of the document corresponding to the file.
+    pub doc_id: String,
+
+    /// The modification time of the document when it was last synchronized.
+    pub remote_modified_time: NaiveDateTime,
+
+    /// The modification time of the local file when it was last
+    /// synchronized, in nanoseconds since the Unix epoch.
+    pub local_modified_time: i64,
+
+    /// The size of the local file when it was last synchronized.
+    pub local_size: i64,
+}
+
+/// Data representing a new mirror entry row to insert into the database.
+///
+/// See the documentation for `MirrorEntry` for explanations of the fields.
+#[derive(Debug, Eq, Insertable, PartialEq)]
+#[table_name = "mirror_entries"]
+pub struct NewMirrorEntry<'a> {
+    /// The ID of the mirror that this file belongs to.
+    pub mirror_id: i32,
+
+    /// The path of the file relative to the mirror directory.
+    pub path: &'a str,
+
+    /// The ID of the document corresponding to the file.
+    pub doc_id: &'a str,
+
+    /// The modification time of the document when it was last synchronized.
+    pub remote_modified_time: NaiveDateTime,
+
+    /// The modification time of the local file when it was last
+    /// synchronized, in nanoseconds since the Unix epoch.
+    pub local_modified_time: i64,
+
+    /// The size of the local file when it was last synchronized.
+    pub local_size: i64,
+}
+
+/// A record of one change to a document that was applied during
+/// synchronization.
+///
+/// A single change from the API can give rise to several of these, if, say,
+/// a document was both renamed and moved.
+#[derive(Debug, Eq, PartialEq, Queryable)]
+pub struct ChangeLogEntry {
+    /// The unique identifier of this entry.
+    pub id: i32,
+
+    /// The ID of the account whose change feed reported the change.
+    pub account_id: i32,
+
+    /// The ID of the document that changed.
+    pub doc_id: String,
+
+    /// The name of the document as of the change.
+    pub doc_name: String,
+
+    /// What happened: one of "added", "modified", "renamed", "moved",
+    /// "trashed", or "removed".
+    pub kind: String,
+
+    /// The value before the change, if meaningful for this kind: the old
+    /// name, the old parent folder IDs (comma-separated), the old trash
+    /// status ("true" or "false"), or the old modification time.
+    pub old_value: Option<String>,
+
+    /// The value after the change, in the same terms as `old_value`.
+    pub new_value: Option<String>,
+
+    /// When the change happened, in UTC.
+    pub timestamp: NaiveDateTime,
+}
+
+/// Data representing a new change log row to insert into the database.
+///
+/// See the documentation for `ChangeLogEntry` for explanations of the
+/// fields. The ID is assigned by the database.
+#[derive(Debug, Eq, Insertable, PartialEq)]
+#[table_name = "changes_log"]
+pub struct NewChangeLogEntry<'a> {
+    /// The ID of the account whose change feed reported the change.
+    pub account_id: i32,
+
+    /// The ID of the document that changed.
+    pub doc_id: &'a str,
+
+    /// The name of the document as of the change.
+    pub doc_name: &'a str,
+
+    /// What happened.
+    pub kind: &'a str,
+
+    /// The value before the change.
+    pub old_value: Option<String>,
+
+    /// The value after the change.
+    pub new_value: Option<String>,
+
+    /// When the change happened, in UTC.
+    pub timestamp: NaiveDateTime,
+}
+
 /// An document that has been entered in some list.
 #[derive(Debug, Eq, PartialEq, Queryable)]
 pub struct ListItem {
@@ -346,3 +792,14 @@ impl<'a> NewListItem<'a> {
         }
     }
 }
+
+/// A row returned by a query of the `doc_search` full-text index.
+///
+/// The index is an SQLite FTS5 virtual table, which Diesel's schema machinery
+/// doesn't understand, so it is queried with raw SQL.
+#[derive(Debug, Eq, PartialEq, QueryableByName)]
+pub struct SearchHit {
+    /// The ID of the matching document.
+    #[sql_type = "diesel::sql_types::Text"]
+    pub doc_id: String,
+}
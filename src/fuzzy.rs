@@ -0,0 +1,448 @@
+// Copyright 2018 Peter Williams <peter@newton.cx>
+// Licensed under the MIT License.
+
+//! Fuzzy matching of document names.
+//!
+//! When the user types a bare name as a document specifier, they often
+//! remember it only approximately. The scheme here scores names against the
+//! typed pattern: exact matches beat prefix matches, which beat substring
+//! matches, which beat mere subsequence matches ("qrtrpt" for "Quarterly
+//! Report"). Matches that land on word boundaries count for more. The name
+//! score is then nudged by how recently the document was modified and
+//! whether it's starred, so that when several documents match about equally
+//! well, the one the user is most likely thinking of comes out on top of the
+//! listing. Those nudges never decide on their own which document gets
+//! picked, though: see `has_clear_winner`.
+
+use chrono::{DateTime, Utc};
+
+use database::Doc;
+use query::escape_like;
+
+/// The name score of an exact (case-insensitive) match. This is set well
+/// above the others so that, bonuses notwithstanding, a unique exact match
+/// is always a clear winner.
+const EXACT_SCORE: f64 = 150.;
+
+/// The name score of a match at the start of the name.
+const PREFIX_SCORE: f64 = 80.;
+
+/// The name score of a substring match starting at a word boundary.
+const WORD_SCORE: f64 = 60.;
+
+/// The name score of a substring match anywhere else.
+const SUBSTRING_SCORE: f64 = 40.;
+
+/// The base name score of a subsequence match.
+const SUBSEQUENCE_SCORE: f64 = 20.;
+
+/// The maximum bonus for a recently-modified document. The bonus decays over
+/// a timescale of `RECENCY_DAYS`.
+const RECENCY_BONUS: f64 = 10.;
+const RECENCY_DAYS: f64 = 30.;
+
+/// The bonus for a starred document.
+const STARRED_BONUS: f64 = 10.;
+
+/// The penalty for a document in the trash.
+const TRASHED_PENALTY: f64 = 15.;
+
+/// How far ahead of the runner-up the best name score must be in order for
+/// its document to be chosen automatically.
+const CLEAR_WINNER_MARGIN: f64 = 15.;
+
+/// Lowercase a string character-by-character, so that indices into the
+/// result line up with indices into the original.
+fn lower_chars(s: &str) -> Vec<char> {
+    s.chars()
+        .map(|c| c.to_lowercase().next().unwrap_or(c))
+        .collect()
+}
+
+/// Determine whether the character at *i* starts a word.
+///
+/// This is true at the start of the text, after any non-alphanumeric
+/// character, and at lowercase-to-uppercase transitions ("camelCase").
+fn is_word_start(chars: &[char], i: usize) -> bool {
+    if i == 0 {
+        return true;
+    }
+
+    let prev = chars[i - 1];
+    let cur = chars[i];
+    !prev.is_alphanumeric() || (prev.is_lowercase() && cur.is_uppercase())
+}
+
+/// Score how well a document name matches a pattern, ignoring case.
+///
+/// Returns None if the name doesn't match at all -- that is, if the
+/// characters of the pattern don't appear in the name in order.
+pub fn name_score(pattern: &str, name: &str) -> Option<f64> {
+    let pat = lower_chars(pattern);
+    let orig: Vec<char> = name.chars().collect();
+    let text = lower_chars(name);
+
+    if pat.is_empty() {
+        return None;
+    }
+
+    if pat == text {
+        return Some(EXACT_SCORE);
+    }
+
+    if pat.len() > text.len() {
+        return None;
+    }
+
+    // Substring matches. If there are several, the best one wins.
+
+    let mut best: Option<f64> = None;
+
+    for start in 0..=(text.len() - pat.len()) {
+        if text[start..start + pat.len()] != pat[..] {
+            continue;
+        }
+
+        let score = if start == 0 {
+            PREFIX_SCORE
+        } else if is_word_start(&orig, start) {
+            WORD_SCORE
+        } else {
+            SUBSTRING_SCORE
+        };
+
+        if best.map(|b| score > b).unwrap_or(true) {
+            best = Some(score);
+        }
+    }
+
+    if best.is_some() {
+        return best;
+    }
+
+    // Subsequence match. We match greedily from the left, but prefer to
+    // land on the start of a word if one is coming up with the right
+    // character. Each match on a word start earns a bonus, and each
+    // skipped character costs a bit.
+
+    let mut pos = 0;
+    let mut boundary_hits = 0;
+    let mut gaps = 0;
+
+    for (n, &pc) in pat.iter().enumerate() {
+        let first = (pos..text.len()).find(|&i| text[i] == pc)?;
+
+        let chosen = (first..text.len())
+            .find(|&i| text[i] == pc && is_word_start(&orig, i))
+            .filter(|&i| {
+                // Don't jump ahead if that would make the rest of the pattern
+                // impossible to match.
+                let mut p = i + 1;
+
+                pat[n + 1..]
+                    .iter()
+                    .all(|&c| match (p..text.len()).find(|&j| text[j] == c) {
+                        Some(j) => {
+                            p = j + 1;
+                            true
+                        }
+                        None => false,
+                    })
+            })
+            .unwrap_or(first);
+
+        if is_word_start(&orig, chosen) {
+            boundary_hits += 1;
+        }
+
+        if n > 0 {
+            gaps += chosen - pos;
+        }
+
+        pos = chosen + 1;
+    }
+
+    let score = SUBSEQUENCE_SCORE + 5. * f64::from(boundary_hits) - 0.5 * gaps as f64;
+    Some(score.clamp(1., SUBSTRING_SCORE - 1.))
+}
+
+/// Get a SQL LIKE pattern, with `\` as the escape character, that matches
+/// every name that `name_score` might match.
+///
+/// The pattern's characters must appear in order, which is just what
+/// "%a%b%c%" checks. SQLite's LIKE only ignores case for ASCII letters, so
+/// other characters become single-character wildcards. The pattern can
+/// therefore let through some names that don't really match, but never
+/// filters out one that does.
+pub fn like_pattern(pattern: &str) -> String {
+    let mut like = String::from("%");
+
+    for c in pattern.chars() {
+        if c.is_ascii() {
+            like.push_str(&escape_like(c.encode_utf8(&mut [0; 4])));
+        } else {
+            like.push('_');
+        }
+
+        like.push('%');
+    }
+
+    like
+}
+
+/// Score a document against a pattern, taking into account its name,
+/// modification time, and status.
+///
+/// Returns None if the name doesn't match.
+pub fn doc_score(pattern: &str, doc: &Doc, now: DateTime<Utc>) -> Option<f64> {
+    let mut score = name_score(pattern, &doc.name)?;
+
+    let age_days = now
+        .signed_duration_since(doc.utc_mod_time())
+        .num_seconds()
+        .max(0) as f64
+        / 86400.;
+    score += RECENCY_BONUS / (1. + age_days / RECENCY_DAYS);
+
+    if doc.starred {
+        score += STARRED_BONUS;
+    }
+
+    if doc.trashed {
+        score -= TRASHED_PENALTY;
+    }
+
+    Some(score)
+}
+
+/// Rank documents by how well they match a pattern, best first.
+///
+/// Documents whose names don't match the pattern at all are placed at the
+/// end, in their original order.
+pub fn rank(pattern: &str, docs: Vec<Doc>) -> Vec<(Option<f64>, Doc)> {
+    let now = Utc::now();
+
+    let mut scored: Vec<_> = docs
+        .into_iter()
+        .map(|d| (doc_score(pattern, &d, now), d))
+        .collect();
+
+    // Stable sort, so ties keep their original order.
+    scored.sort_by(|a, b| {
+        let sa = a.0.unwrap_or(f64::NEG_INFINITY);
+        let sb = b.0.unwrap_or(f64::NEG_INFINITY);
+        sb.partial_cmp(&sa).unwrap_or(std::cmp::Ordering::Equal)
+    });
+
+    scored
+}
+
+/// Decide whether the best entry in a ranked list is far enough ahead of
+/// the others that it's safe to assume it's the one the user wants.
+///
+/// Only the name scores count here. The recency and starred bonuses are
+/// fine for putting the likeliest candidates first in a listing, but two
+/// documents that match the pattern equally well are equally plausible
+/// targets no matter which one was touched last.
+pub fn has_clear_winner(pattern: &str, ranked: &[(Option<f64>, Doc)]) -> bool {
+    let (best, rest) = match ranked.split_first() {
+        Some((first, rest)) => (name_score(pattern, &first.1.name), rest),
+        None => return false,
+    };
+
+    let best = match best {
+        Some(b) => b,
+        None => return false,
+    };
+
+    rest.iter()
+        .filter_map(|(_, doc)| name_score(pattern, &doc.name))
+        .all(|s| best - s >= CLEAR_WINNER_MARGIN)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    /// Make a document with the given name, modified the given number of
+    /// days ago.
+    fn doc(id: &str, name: &str, days_old: i64) -> Doc {
+        Doc {
+            id: id.to_owned(),
+            name: name.to_owned(),
+            mime_type: "application/pdf".to_owned(),
+            modified_time: (Utc::now() - Duration::days(days_old)).naive_utc(),
+            starred: false,
+            trashed: false,
+            size: None,
+            md5_checksum: None,
+            drive_id: None,
+            owner_email: None,
+            owner_name: None,
+        }
+    }
+
+    fn ids(ranked: &[(Option<f64>, Doc)]) -> Vec<&str> {
+        ranked.iter().map(|(_, d)| d.id.as_str()).collect()
+    }
+
+    #[test]
+    fn exact_match() {
+        assert_eq!(name_score("report", "Report"), Some(EXACT_SCORE));
+        assert_eq!(name_score("REPORT", "report"), Some(EXACT_SCORE));
+    }
+
+    #[test]
+    fn prefix_match() {
+        assert_eq!(name_score("rep", "Report"), Some(PREFIX_SCORE));
+    }
+
+    #[test]
+    fn word_boundary_match() {
+        assert_eq!(name_score("rep", "Quarterly Report"), Some(WORD_SCORE));
+        assert_eq!(name_score("rep", "quarterly_report"), Some(WORD_SCORE));
+        assert_eq!(name_score("rep", "quarterlyReport"), Some(WORD_SCORE));
+    }
+
+    #[test]
+    fn substring_match() {
+        assert_eq!(name_score("port", "Report"), Some(SUBSTRING_SCORE));
+
+        // The best of several occurrences counts.
+        assert_eq!(name_score("port", "Report portfolio"), Some(WORD_SCORE));
+    }
+
+    #[test]
+    fn subsequence_match() {
+        let s = name_score("qrtrpt", "Quarterly Report").unwrap();
+        assert!((1. ..SUBSTRING_SCORE).contains(&s));
+
+        // Landing on word starts beats landing in the middle of words.
+        let on_words = name_score("qr", "Quarterly Report").unwrap();
+        let in_words = name_score("qr", "Quarterlyreport").unwrap();
+        assert!(on_words > in_words);
+    }
+
+    #[test]
+    fn no_match() {
+        assert_eq!(name_score("xyz", "Report"), None);
+        assert_eq!(name_score("tropre", "Report"), None);
+        assert_eq!(name_score("reports", "Report"), None);
+        assert_eq!(name_score("", "Report"), None);
+    }
+
+    #[test]
+    fn like_patterns() {
+        assert_eq!(like_pattern("qr"), "%q%r%");
+        assert_eq!(like_pattern("50%_"), "%5%0%\\%%\\_%");
+        assert_eq!(like_pattern("Ærø"), "%_%r%_%");
+    }
+
+    #[test]
+    fn doc_score_bonuses() {
+        let now = Utc::now();
+        let plain = doc("a", "Report", 1000);
+
+        let mut starred = plain.clone();
+        starred.starred = true;
+
+        let mut trashed = plain.clone();
+        trashed.trashed = true;
+
+        let recent = doc("b", "Report", 0);
+
+        let base = doc_score("report", &plain, now).unwrap();
+        assert!(doc_score("report", &starred, now).unwrap() > base);
+        assert!(doc_score("report", &trashed, now).unwrap() < base);
+        assert!(doc_score("report", &recent, now).unwrap() > base);
+        assert!(doc_score("report", &recent, now).unwrap() <= EXACT_SCORE + RECENCY_BONUS);
+        assert_eq!(doc_score("xyz", &plain, now), None);
+    }
+
+    #[test]
+    fn rank_order() {
+        let ranked = rank(
+            "rep",
+            vec![
+                doc("none", "Budget", 0),
+                doc("sub", "Quarterly rpt", 0),
+                doc("word", "Quarterly Report", 0),
+                doc("exact", "Rep", 0),
+                doc("prefix", "Report", 0),
+            ],
+        );
+
+        assert_eq!(ids(&ranked), vec!["exact", "prefix", "word", "sub", "none"]);
+        assert_eq!(ranked[4].0, None);
+    }
+
+    #[test]
+    fn rank_ties_keep_order() {
+        let ranked = rank(
+            "report",
+            vec![
+                doc("a", "Report", 5),
+                doc("b", "Report", 5),
+                doc("c", "Report", 5),
+            ],
+        );
+
+        assert_eq!(ids(&ranked), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn clear_winner_by_name() {
+        let ranked = rank(
+            "report",
+            vec![doc("a", "Report", 1000), doc("b", "Report draft", 0)],
+        );
+        assert_eq!(ids(&ranked), vec!["a", "b"]);
+        assert!(has_clear_winner("report", &ranked));
+
+        let ranked = rank(
+            "rep",
+            vec![doc("a", "Report", 0), doc("b", "Quarterly Report", 0)],
+        );
+        assert!(has_clear_winner("rep", &ranked));
+
+        let ranked = rank("rep", vec![doc("a", "Report", 0)]);
+        assert!(has_clear_winner("rep", &ranked));
+
+        assert!(!has_clear_winner("rep", &[]));
+    }
+
+    #[test]
+    fn no_clear_winner_on_name_ties() {
+        // The bonuses put one document well ahead, but the names match
+        // equally well, so we mustn't guess.
+        let mut favorite = doc("a", "Report", 0);
+        favorite.starred = true;
+
+        let ranked = rank("report", vec![doc("b", "Report", 1000), favorite]);
+        assert_eq!(ids(&ranked), vec!["a", "b"]);
+        assert!(ranked[0].0.unwrap() - ranked[1].0.unwrap() >= CLEAR_WINNER_MARGIN);
+        assert!(!has_clear_winner("report", &ranked));
+    }
+
+    #[test]
+    fn no_clear_winner_within_margin() {
+        // Two subsequence matches of similar quality.
+        let ranked = rank(
+            "qr",
+            vec![
+                doc("a", "Quarterly Report", 0),
+                doc("b", "Quarterlyreport", 0),
+            ],
+        );
+        assert_eq!(ids(&ranked), vec!["a", "b"]);
+        assert!(!has_clear_winner("qr", &ranked));
+
+        // A name that doesn't match at all doesn't count as competition.
+        let ranked = rank(
+            "qr",
+            vec![doc("a", "Quarterly Report", 0), doc("b", "Budget", 0)],
+        );
+        assert!(has_clear_winner("qr", &ranked));
+    }
+}
@@ -8,9 +8,11 @@
 //! figure out a more convenient way to access the API server's error
 //! explanations.
 
+use google_drive3::ReadSeek;
 use hyper::Client;
 use std::cell::RefCell;
 use std::fs;
+use std::io::{self, SeekFrom, Write};
 use std::rc::Rc;
 use yup_oauth2::{
     ApplicationSecret, Authenticator as YupAuthenticator, ConsoleApplicationSecret,
@@ -106,8 +108,20 @@ macro_rules! impl_call_builder_ext {
 impl_call_builder_ext!(google_drive3::AboutGetCall<'a, C, A>);
 impl_call_builder_ext!(google_drive3::ChangeGetStartPageTokenCall<'a, C, A>);
 impl_call_builder_ext!(google_drive3::ChangeListCall<'a, C, A>);
+impl_call_builder_ext!(google_drive3::CommentListCall<'a, C, A>);
+impl_call_builder_ext!(google_drive3::DriveListCall<'a, C, A>);
+impl_call_builder_ext!(google_drive3::FileCreateCall<'a, C, A>);
+impl_call_builder_ext!(google_drive3::FileExportCall<'a, C, A>);
 impl_call_builder_ext!(google_drive3::FileGetCall<'a, C, A>);
 impl_call_builder_ext!(google_drive3::FileListCall<'a, C, A>);
+impl_call_builder_ext!(google_drive3::FileUpdateCall<'a, C, A>);
+impl_call_builder_ext!(google_drive3::PermissionCreateCall<'a, C, A>);
+impl_call_builder_ext!(google_drive3::PermissionDeleteCall<'a, C, A>);
+impl_call_builder_ext!(google_drive3::PermissionListCall<'a, C, A>);
+impl_call_builder_ext!(google_drive3::ReplyCreateCall<'a, C, A>);
+impl_call_builder_ext!(google_drive3::RevisionGetCall<'a, C, A>);
+impl_call_builder_ext!(google_drive3::RevisionListCall<'a, C, A>);
+impl_call_builder_ext!(google_drive3::RevisionUpdateCall<'a, C, A>);
 
 /// Ask the user to authorize our app to use an account, interactively.
 ///
@@ -153,6 +167,62 @@ where
     Ok(about)
 }
 
+/// Get a token that can be used to ask about changes that happen after this
+/// call.
+///
+/// If *drive_id* is specified, the token applies to that shared drive;
+/// otherwise it applies to the user's own files.
+pub fn get_start_page_token<'a, 'b>(hub: &'b Drive<'a>, drive_id: Option<&str>) -> Result<String>
+where
+    'b: 'a,
+{
+    let call = hub.changes().get_start_page_token().default_scope();
+
+    let call = match drive_id {
+        Some(d) => call.drive_id(d).supports_all_drives(true),
+        None => call,
+    };
+
+    let (_resp, info) = call.doit().adapt()?;
+    info.start_page_token
+        .ok_or_else(|| format_err!("server response did not include token"))
+}
+
+/// An app-specific type for the FileCreateCall type from `google_drive3`.
+///
+/// The main reason for providing this is to make it easier to write the
+/// signature of the `create_file` call.
+pub type FileCreateCall<'a, 'b> = google_drive3::FileCreateCall<'a, Client, Authenticator<'b>>;
+
+/// Create a new file that has no content, such as a folder.
+///
+/// The `google_drive3` crate only knows how to issue this call as an upload,
+/// so we upload an empty body. The metadata in *file* determine what kind of
+/// file is created.
+pub fn create_file<'a, 'b, F>(
+    hub: &'b Drive<'a>,
+    file: google_drive3::File,
+    mut f: F,
+) -> Result<google_drive3::File>
+where
+    'b: 'a,
+    F: 'a + FnMut(FileCreateCall<'a, 'b>) -> FileCreateCall<'a, 'b>,
+{
+    use hyper::mime::{Mime, SubLevel, TopLevel};
+
+    let call = hub.files().create(file);
+    let call = f(call);
+    let call = call.default_scope();
+    let empty = std::io::Cursor::new(Vec::new());
+    let mime = Mime(
+        TopLevel::Application,
+        SubLevel::Ext("octet-stream".to_owned()),
+        vec![],
+    );
+    let (_resp, file) = call.upload(empty, mime).adapt()?;
+    Ok(file)
+}
+
 /// An app-specific type for the FileListCall type from `google_drive3`.
 ///
 /// The main reason for providing this is to make it easier to write the
@@ -175,6 +245,359 @@ where
     Ok(file)
 }
 
+/// Get information about all of the shared drives that the user is a member
+/// of.
+///
+/// Unlike files, people don't have enough shared drives to make it worth
+/// streaming the results, so the pages are gathered into a vector.
+pub fn list_drives<'a, 'b>(hub: &'b Drive<'a>) -> Result<Vec<google_drive3::Drive>>
+where
+    'b: 'a,
+{
+    let mut drives = Vec::new();
+    let mut page_token: Option<String> = None;
+
+    loop {
+        let call = hub.drives().list().page_size(100).default_scope();
+
+        let call = match page_token.as_ref() {
+            Some(t) => call.page_token(t),
+            None => call,
+        };
+
+        let (_resp, listing) = call.doit().adapt()?;
+        drives.extend(listing.drives.unwrap_or_default());
+
+        page_token = listing.next_page_token;
+
+        if page_token.is_none() {
+            break;
+        }
+    }
+
+    Ok(drives)
+}
+
+/// The permission fields that we request from the API.
+pub const PERMISSION_FIELDS: &str =
+    "allowFileDiscovery,displayName,domain,emailAddress,id,role,type";
+
+/// Get all of the permissions that have been granted on a file.
+///
+/// As with `list_drives`, the pages of results are gathered into a vector.
+pub fn list_permissions<'a, 'b>(
+    hub: &'b Drive<'a>,
+    file_id: &str,
+) -> Result<Vec<google_drive3::Permission>>
+where
+    'b: 'a,
+{
+    let mut permissions = Vec::new();
+    let mut page_token: Option<String> = None;
+    let fields = format!("permissions({}),nextPageToken", PERMISSION_FIELDS);
+
+    loop {
+        let call = hub
+            .permissions()
+            .list(file_id)
+            .supports_all_drives(true)
+            .param("fields", &fields)
+            .default_scope();
+
+        let call = match page_token.as_ref() {
+            Some(t) => call.page_token(t),
+            None => call,
+        };
+
+        let (_resp, listing) = call.doit().adapt()?;
+        permissions.extend(listing.permissions.unwrap_or_default());
+
+        page_token = listing.next_page_token;
+
+        if page_token.is_none() {
+            break;
+        }
+    }
+
+    Ok(permissions)
+}
+
+/// An app-specific type for the PermissionCreateCall type from
+/// `google_drive3`.
+///
+/// The main reason for providing this is to make it easier to write the
+/// signature of the `create_permission` call.
+pub type PermissionCreateCall<'a, 'b> =
+    google_drive3::PermissionCreateCall<'a, Client, Authenticator<'b>>;
+
+/// Grant a new permission on a file.
+///
+/// The function *f* can customize the call, e.g. to control whether the
+/// grantee is sent a notification email.
+pub fn create_permission<'a, 'b, F>(
+    hub: &'b Drive<'a>,
+    file_id: &str,
+    permission: google_drive3::Permission,
+    mut f: F,
+) -> Result<google_drive3::Permission>
+where
+    'b: 'a,
+    F: 'a + FnMut(PermissionCreateCall<'a, 'b>) -> PermissionCreateCall<'a, 'b>,
+{
+    let call = hub
+        .permissions()
+        .create(permission, file_id)
+        .supports_all_drives(true)
+        .param("fields", PERMISSION_FIELDS);
+    let call = f(call);
+    let call = call.default_scope();
+    let (_resp, permission) = call.doit().adapt()?;
+    Ok(permission)
+}
+
+/// Revoke a permission on a file.
+pub fn delete_permission<'a, 'b>(
+    hub: &'b Drive<'a>,
+    file_id: &str,
+    permission_id: &str,
+) -> Result<()>
+where
+    'b: 'a,
+{
+    hub.permissions()
+        .delete(file_id, permission_id)
+        .supports_all_drives(true)
+        .default_scope()
+        .doit()
+        .adapt()?;
+    Ok(())
+}
+
+/// The reply fields that we request from the API.
+pub const REPLY_FIELDS: &str = "action,author(displayName),content,createdTime,deleted,id";
+
+/// The comment fields that we request from the API.
+///
+/// Comments come with all of their replies attached.
+pub const COMMENT_FIELDS: &str = "author(displayName),content,createdTime,deleted,id,\
+                                  quotedFileContent(value),resolved,\
+                                  replies(action,author(displayName),content,createdTime,deleted,id)";
+
+/// Get all of the comments on a file, along with their replies.
+///
+/// As with `list_drives`, the pages of results are gathered into a vector.
+/// Deleted comments are not included.
+pub fn list_comments<'a, 'b>(
+    hub: &'b Drive<'a>,
+    file_id: &str,
+) -> Result<Vec<google_drive3::Comment>>
+where
+    'b: 'a,
+{
+    let mut comments = Vec::new();
+    let mut page_token: Option<String> = None;
+    let fields = format!("comments({}),nextPageToken", COMMENT_FIELDS);
+
+    loop {
+        let call = hub
+            .comments()
+            .list(file_id)
+            .include_deleted(false)
+            .param("fields", &fields)
+            .default_scope();
+
+        let call = match page_token.as_ref() {
+            Some(t) => call.page_token(t),
+            None => call,
+        };
+
+        let (_resp, listing) = call.doit().adapt()?;
+        comments.extend(listing.comments.unwrap_or_default());
+
+        page_token = listing.next_page_token;
+
+        if page_token.is_none() {
+            break;
+        }
+    }
+
+    Ok(comments)
+}
+
+/// Add a reply to a comment on a file.
+///
+/// A reply can also resolve or reopen the comment through its `action`.
+pub fn create_reply<'a, 'b>(
+    hub: &'b Drive<'a>,
+    file_id: &str,
+    comment_id: &str,
+    reply: google_drive3::Reply,
+) -> Result<google_drive3::Reply>
+where
+    'b: 'a,
+{
+    let (_resp, reply) = hub
+        .replies()
+        .create(reply, file_id, comment_id)
+        .param("fields", REPLY_FIELDS)
+        .default_scope()
+        .doit()
+        .adapt()?;
+    Ok(reply)
+}
+
+/// Files larger than this many bytes are uploaded with the resumable
+/// protocol, which transfers the data in chunks.
+const RESUMABLE_UPLOAD_THRESHOLD: u64 = 5 * 1024 * 1024;
+
+/// Create a new file with the specified content.
+///
+/// Large files are sent using the resumable upload protocol. If the
+/// `mimeType` set in *file* differs from *content_mime_type*, and is one of
+/// the native Google types, the server will convert the content.
+pub fn upload_file<'a, 'b, F>(
+    hub: &'b Drive<'a>,
+    file: google_drive3::File,
+    content: &mut dyn ReadSeek,
+    content_mime_type: &str,
+    mut f: F,
+) -> Result<google_drive3::File>
+where
+    'b: 'a,
+    F: 'a + FnMut(FileCreateCall<'a, 'b>) -> FileCreateCall<'a, 'b>,
+{
+    use hyper::mime::Mime;
+
+    let size = content.seek(SeekFrom::End(0))?;
+    content.seek(SeekFrom::Start(0))?;
+
+    let mime: Mime = content_mime_type
+        .parse()
+        .map_err(|_| format_err!("invalid MIME type \"{}\"", content_mime_type))?;
+
+    let call = hub.files().create(file);
+    let call = f(call);
+    let call = call.default_scope();
+
+    let (_resp, file) = if size > RESUMABLE_UPLOAD_THRESHOLD {
+        call.upload_resumable(content, mime).adapt()?
+    } else {
+        call.upload(content, mime).adapt()?
+    };
+
+    Ok(file)
+}
+
+/// Download the binary content of a file, writing it to *dest*.
+///
+/// This only works for files that have binary content in Google Drive;
+/// native Google documents must be exported with `export_file`. Returns the
+/// number of bytes written.
+pub fn download_file<'a, 'b>(hub: &'b Drive<'a>, id: &str, dest: &mut dyn Write) -> Result<u64>
+where
+    'b: 'a,
+{
+    // Setting `alt=media` makes the library hand us the raw response rather
+    // than trying to parse it as JSON.
+    let (mut resp, _file) = hub
+        .files()
+        .get(id)
+        .supports_all_drives(true)
+        .param("alt", "media")
+        .default_scope()
+        .doit()
+        .adapt()?;
+    Ok(io::copy(&mut resp, dest)?)
+}
+
+/// Export a native Google document in the specified format, writing the
+/// result to *dest*.
+///
+/// Returns the number of bytes written.
+pub fn export_file<'a, 'b>(
+    hub: &'b Drive<'a>,
+    id: &str,
+    mime_type: &str,
+    dest: &mut dyn Write,
+) -> Result<u64>
+where
+    'b: 'a,
+{
+    let mut resp = hub
+        .files()
+        .export(id, mime_type)
+        .default_scope()
+        .doit()
+        .adapt()?;
+    Ok(io::copy(&mut resp, dest)?)
+}
+
+/// An app-specific type for the FileUpdateCall type from `google_drive3`.
+///
+/// The main reason for providing this is to make it easier to write the
+/// signature of the `update_file` call.
+pub type FileUpdateCall<'a, 'b> = google_drive3::FileUpdateCall<'a, Client, Authenticator<'b>>;
+
+/// Modify the metadata of a file.
+///
+/// Only the fields that are set in *file* are changed. Moving a file between
+/// folders is done by customizing the call with `add_parents` and
+/// `remove_parents`; its `parents` field cannot be set directly.
+pub fn update_file<'a, 'b, F>(
+    hub: &'b Drive<'a>,
+    id: &str,
+    file: google_drive3::File,
+    mut f: F,
+) -> Result<google_drive3::File>
+where
+    'b: 'a,
+    F: 'a + FnMut(FileUpdateCall<'a, 'b>) -> FileUpdateCall<'a, 'b>,
+{
+    let call = hub.files().update(file, id);
+    let call = f(call);
+    let call = call.default_scope();
+    let (_resp, file) = call.doit_without_upload().adapt()?;
+    Ok(file)
+}
+
+/// Replace the content of an existing file.
+///
+/// This works like `upload_file`, but for a file that already exists; any
+/// fields set in *file* are changed as well.
+pub fn update_content<'a, 'b, F>(
+    hub: &'b Drive<'a>,
+    id: &str,
+    file: google_drive3::File,
+    content: &mut dyn ReadSeek,
+    content_mime_type: &str,
+    mut f: F,
+) -> Result<google_drive3::File>
+where
+    'b: 'a,
+    F: 'a + FnMut(FileUpdateCall<'a, 'b>) -> FileUpdateCall<'a, 'b>,
+{
+    use hyper::mime::Mime;
+
+    let size = content.seek(SeekFrom::End(0))?;
+    content.seek(SeekFrom::Start(0))?;
+
+    let mime: Mime = content_mime_type
+        .parse()
+        .map_err(|_| format_err!("invalid MIME type \"{}\"", content_mime_type))?;
+
+    let call = hub.files().update(file, id);
+    let call = f(call);
+    let call = call.default_scope();
+
+    let (_resp, file) = if size > RESUMABLE_UPLOAD_THRESHOLD {
+        call.upload_resumable(content, mime).adapt()?
+    } else {
+        call.upload(content, mime).adapt()?
+    };
+
+    Ok(file)
+}
+
 /// An app-specific type for the FileListCall type from `google_drive3`.
 ///
 /// The main reason for providing this is to make it easier to write the
@@ -361,6 +784,216 @@ where
 {
 }
 
+/// An app-specific type for the RevisionListCall type from `google_drive3`.
+///
+/// The main reason for providing this is to make it easier to write the
+/// signature of the `list_revisions` call.
+pub type RevisionListCall<'a, 'b> = google_drive3::RevisionListCall<'a, Client, Authenticator<'b>>;
+
+/// Return an iterator over all of the stored revisions of a file.
+///
+/// As with `list_files`, the function *f* can customize the RevisionListCall
+/// instances, and may be called multiple times as the results are paged.
+pub fn list_revisions<'a, 'b, F>(
+    hub: &'b Drive<'a>,
+    file_id: &str,
+    f: F,
+) -> impl Iterator<Item = Result<google_drive3::Revision>> + 'a
+where
+    'b: 'a,
+    F: 'a + FnMut(RevisionListCall<'a, 'b>) -> RevisionListCall<'a, 'b>,
+{
+    RevisionListing::new(hub, file_id, f)
+}
+
+/// Helper class for paging `revisions.list` results.
+///
+/// This works just like `FileListing`.
+struct RevisionListing<'a, 'b, C, A, F>
+where
+    'b: 'a,
+    F: FnMut(
+        google_drive3::RevisionListCall<'a, C, A>,
+    ) -> google_drive3::RevisionListCall<'a, C, A>,
+    C: 'b + std::borrow::BorrowMut<hyper::Client>,
+    A: 'b + yup_oauth2::GetToken,
+{
+    hub: &'b google_drive3::DriveHub<C, A>,
+    file_id: String,
+    customizer: F,
+    cur_page: Option<std::vec::IntoIter<google_drive3::Revision>>,
+    next_page_token: Option<String>,
+    finished: bool,
+    final_page: bool,
+    phantoma: std::marker::PhantomData<&'a A>,
+}
+
+impl<'a, 'b, C, A, F> RevisionListing<'a, 'b, C, A, F>
+where
+    'b: 'a,
+    F: FnMut(
+        google_drive3::RevisionListCall<'a, C, A>,
+    ) -> google_drive3::RevisionListCall<'a, C, A>,
+    C: 'b + std::borrow::BorrowMut<hyper::Client>,
+    A: 'b + yup_oauth2::GetToken,
+{
+    fn new(
+        hub: &'b google_drive3::DriveHub<C, A>,
+        file_id: &str,
+        f: F,
+    ) -> RevisionListing<'a, 'b, C, A, F> {
+        RevisionListing {
+            hub,
+            file_id: file_id.to_owned(),
+            customizer: f,
+            cur_page: None,
+            next_page_token: None,
+            finished: false,
+            final_page: false,
+            phantoma: std::marker::PhantomData,
+        }
+    }
+}
+
+impl<'a, 'b, C, A, F> Iterator for RevisionListing<'a, 'b, C, A, F>
+where
+    'b: 'a,
+    F: FnMut(
+        google_drive3::RevisionListCall<'a, C, A>,
+    ) -> google_drive3::RevisionListCall<'a, C, A>,
+    C: 'b + std::borrow::BorrowMut<hyper::Client>,
+    A: 'b + yup_oauth2::GetToken,
+{
+    type Item = Result<google_drive3::Revision>;
+
+    fn next(&mut self) -> Option<Result<google_drive3::Revision>> {
+        if self.finished {
+            return None;
+        }
+
+        if let Some(iter) = self.cur_page.as_mut() {
+            if let Some(rev) = iter.next() {
+                return Some(Ok(rev));
+            }
+        }
+
+        if self.final_page {
+            self.finished = true;
+            return None;
+        }
+
+        let call = self.hub.revisions().list(&self.file_id);
+        let call = (self.customizer)(call);
+        let call = call.default_scope();
+
+        let call = if let Some(page_token) = self.next_page_token.take() {
+            call.page_token(&page_token)
+        } else {
+            call
+        };
+
+        let (_resp, listing) = match call.doit().adapt() {
+            Ok(t) => t,
+            Err(e) => {
+                self.finished = true;
+                return Some(Err(e));
+            }
+        };
+
+        if let Some(page_token) = listing.next_page_token {
+            self.next_page_token = Some(page_token);
+        } else {
+            self.final_page = true;
+        }
+
+        let mut revs_iter = match listing.revisions {
+            Some(r) => r.into_iter(),
+            None => {
+                self.finished = true;
+                return Some(Err(format_err!("API call failed: no 'revisions' returned")));
+            }
+        };
+
+        let the_rev = match revs_iter.next() {
+            Some(r) => r,
+            None => {
+                // See the corresponding comment in FileListing.
+                self.finished = true;
+
+                return if self.final_page {
+                    None
+                } else {
+                    Some(Err(format_err!(
+                        "API call failed: empty page in midst of query"
+                    )))
+                };
+            }
+        };
+
+        self.cur_page = Some(revs_iter);
+        Some(Ok(the_rev))
+    }
+}
+
+impl<'a, 'b, C, A, F> std::iter::FusedIterator for RevisionListing<'a, 'b, C, A, F>
+where
+    'b: 'a,
+    F: FnMut(
+        google_drive3::RevisionListCall<'a, C, A>,
+    ) -> google_drive3::RevisionListCall<'a, C, A>,
+    C: 'b + std::borrow::BorrowMut<hyper::Client>,
+    A: 'b + yup_oauth2::GetToken,
+{
+}
+
+/// Download the content of a stored revision of a file, writing it to
+/// *dest*.
+///
+/// Like `download_file`, this only works for files with binary content.
+/// Returns the number of bytes written.
+pub fn download_revision<'a, 'b>(
+    hub: &'b Drive<'a>,
+    file_id: &str,
+    revision_id: &str,
+    dest: &mut dyn Write,
+) -> Result<u64>
+where
+    'b: 'a,
+{
+    let (mut resp, _rev) = hub
+        .revisions()
+        .get(file_id, revision_id)
+        .param("alt", "media")
+        .default_scope()
+        .doit()
+        .adapt()?;
+    Ok(io::copy(&mut resp, dest)?)
+}
+
+/// Modify the metadata of a stored revision of a file.
+///
+/// Only the fields that are set in *revision* are changed; in practice, the
+/// interesting one is `keep_forever`.
+pub fn update_revision<'a, 'b>(
+    hub: &'b Drive<'a>,
+    file_id: &str,
+    revision_id: &str,
+    revision: google_drive3::Revision,
+    fields: &str,
+) -> Result<google_drive3::Revision>
+where
+    'b: 'a,
+{
+    let (_resp, rev) = hub
+        .revisions()
+        .update(revision, file_id, revision_id)
+        .param("fields", fields)
+        .default_scope()
+        .doit()
+        .adapt()?;
+    Ok(rev)
+}
+
 /// An app-specific type for the ChangeListCall type from `google_drive3`.
 ///
 /// The main reason for providing this is to make it easier to write the
@@ -18,22 +18,26 @@ extern crate google_drive3;
 extern crate humansize;
 extern crate hyper;
 extern crate hyper_native_tls;
+extern crate md5;
 extern crate petgraph;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde_json;
 extern crate structopt;
+extern crate tar;
 #[macro_use]
 extern crate tcprint;
 extern crate tempfile;
 extern crate timeago;
 extern crate url;
 extern crate yup_oauth2;
+extern crate zstd;
 
 use diesel::prelude::*;
-use std::collections::hash_map::Entry;
+use std::collections::HashSet;
 use std::ffi::OsStr;
+use std::path::PathBuf;
 use std::process;
 use std::result::Result as StdResult;
 use structopt::StructOpt;
@@ -41,11 +45,22 @@ use tcprint::ColorPrintState;
 
 mod accounts;
 mod app;
+mod backend;
+mod backup;
 mod colors;
+mod config;
+mod content;
 mod database;
 mod errors;
+mod fuzzy;
 mod google_apis;
+mod markdown;
+mod mirror;
+mod output;
+mod query;
 mod schema;
+#[cfg(test)]
+mod test_support;
 mod token_storage;
 
 use app::Application;
@@ -78,299 +93,2527 @@ fn open_url<S: AsRef<OsStr>>(url: S) -> Result<()> {
     }
 }
 
-/// Show detailed information about one or more documents.
+/// Find the document that a command operates on, and an account that can
+/// access it.
+///
+/// Commands that change the document should pass true for *strict*; see
+/// `GetDocBuilder::strict`.
+fn doc_and_account(
+    app: &mut Application,
+    spec: &str,
+    strict: bool,
+) -> Result<(database::Doc, accounts::Account)> {
+    let doc = app.get_docs().strict(strict).process_one(spec)?;
+
+    let acct = doc
+        .accounts(app)?
+        .into_iter()
+        .next()
+        .ok_or_else(|| format_err!("\"{}\" is not associated with any account", doc.name))?;
+    let account = accounts::Account::load(&acct.email)?;
+    Ok((doc, account))
+}
+
+/// Find the item that the user means by an ID, allowing unique prefixes.
+///
+/// This is used for things like revisions and comments, whose IDs are long
+/// and only meaningful within a single document. The *kind* is used in error
+/// messages.
+fn find_by_id<'a, T, F>(items: &'a [T], spec: &str, kind: &str, id_of: F) -> Result<&'a T>
+where
+    F: Fn(&T) -> Option<&str>,
+{
+    if let Some(item) = items.iter().find(|i| id_of(i) == Some(spec)) {
+        return Ok(item);
+    }
+
+    let matches: Vec<_> = items
+        .iter()
+        .filter(|i| id_of(i).is_some_and(|id| id.starts_with(spec)))
+        .collect();
+
+    match matches.len() {
+        0 => Err(format_err!("no {} matches \"{}\"", kind, spec)),
+        1 => Ok(matches[0]),
+        n => Err(format_err!(
+            "\"{}\" matches {} {}s; use more of the ID",
+            spec,
+            n,
+            kind
+        )),
+    }
+}
+
+/// Format a timestamp from the API in the local timezone.
+fn api_time_to_local(t: Option<&String>) -> String {
+    t.and_then(|t| chrono::DateTime::parse_from_rfc3339(t).ok())
+        .map_or_else(
+            || "[unknown time]".to_owned(),
+            |t| {
+                t.with_timezone(&chrono::Local)
+                    .format("%Y-%m-%d %H:%M")
+                    .to_string()
+            },
+        )
+}
+
+/// Audit documents for policy problems.
 #[derive(Debug, StructOpt)]
-pub struct DrorgInfoOptions {
-    #[structopt(help = "A document specifier (name, ID, ...)")]
-    spec: String,
+pub struct DrorgAuditOptions {
+    #[structopt(subcommand)]
+    command: DrorgAuditSubcommand,
 }
 
-impl DrorgInfoOptions {
+/// The kinds of audit that can be run.
+#[derive(Debug, StructOpt)]
+pub enum DrorgAuditSubcommand {
+    #[structopt(name = "sharing")]
+    /// Report documents shared publicly or outside of the allowed domains
+    Sharing(DrorgAuditSharingOptions),
+}
+
+impl DrorgAuditOptions {
     fn cli(self, app: &mut Application) -> Result<i32> {
-        use std::collections::HashMap;
+        match self.command {
+            DrorgAuditSubcommand::Sharing(opts) => opts.cli(app),
+        }
+    }
+}
+
+/// Report documents whose sharing violates the policy.
+///
+/// A document is flagged if it is shared with anyone on the web, anyone with
+/// the link, or a user, group, or domain outside of the allowed domains. The
+/// allowed domains are those listed in the `sharing.allowed_domains` setting
+/// of the configuration file and those given on the command line. The report
+/// is based on the cached permissions, which are only known for documents
+/// that the accounts can share. The permissions of documents in shared
+/// drives are fetched one by one, since file listings don't include them;
+/// if some of them can't be fetched, a warning is printed, and unless there
+/// are violations, the command exits with a distinct code. Grantees whose
+/// addresses aren't known are listed too, but aren't counted as violations.
+#[derive(Debug, StructOpt)]
+pub struct DrorgAuditSharingOptions {
+    #[structopt(
+        long = "allow",
+        help = "Also allow sharing with this domain and its subdomains",
+        number_of_values = 1
+    )]
+    allow: Vec<String>,
+
+    #[structopt(long = "trashed", help = "Include documents that are in the trash")]
+    trashed: bool,
+}
+
+/// The exit code of `drorg audit sharing` when violations are found.
+const AUDIT_VIOLATIONS_EXIT_CODE: i32 = 2;
 
+/// The exit code of `drorg audit sharing` when no violations are found, but
+/// some documents couldn't be audited.
+const AUDIT_INCOMPLETE_EXIT_CODE: i32 = 3;
+
+/// The `reason` of a sharing report record whose grantee can't be checked
+/// against the policy.
+const AUDIT_UNKNOWN_REASON: &str = "unknown";
+
+impl DrorgAuditSharingOptions {
+    fn cli(self, app: &mut Application) -> Result<i32> {
         app.maybe_sync_all_accounts()?;
 
-        let mut linkages = HashMap::new();
-        let results = app.get_docs().process(&self.spec)?; // note: avoid name clash with db table
-        let mut first = true;
+        let n_unaudited = app.fetch_drive_permissions(self.trashed)?;
 
-        for doc in results {
-            if first {
-                first = false;
-            } else {
-                tcprintln!(app.ps, (""));
-            }
+        if n_unaudited > 0 {
+            tcreport!(app.ps, warning: "{} document(s) in shared drives were not audited, since \
+                                        their permissions could not be fetched",
+                      n_unaudited);
+        }
 
-            tcprintln!(app.ps, [hl: "Name:"], ("      "), [green: "{}", doc.name]);
-            tcprintln!(app.ps, [hl: "MIME-type:"], (" {}", doc.mime_type));
-            tcprintln!(app.ps, [hl: "Size:"], ("      {}", doc.human_size().unwrap_or_else(|| "N/A".to_owned())));
-            tcprintln!(app.ps, [hl: "Modified:"], ("  {}", doc.utc_mod_time().to_rfc3339()));
-            tcprintln!(app.ps, [hl: "ID:"], ("        {}", doc.id));
-            tcprintln!(app.ps, [hl: "Starred?:"], ("  {}", if doc.starred { "yes" } else { "no" }));
-            tcprintln!(app.ps, [hl: "Trashed?:"], ("  {}", if doc.trashed { "yes" } else { "no" }));
+        let records = self.audit(app)?;
 
-            let accounts = doc.accounts(app)?;
-            let mut path_reprs = Vec::new();
+        let violating_docs: HashSet<&str> = records
+            .iter()
+            .filter(|r| r.reason != AUDIT_UNKNOWN_REASON)
+            .map(|r| r.id.as_str())
+            .collect();
 
-            for acct in &accounts {
-                if let Entry::Vacant(e) = linkages.entry(acct.id) {
-                    let table = app.load_linkage_table(acct.id, true)?;
-                    e.insert(table);
-                }
+        let exit_code = if !violating_docs.is_empty() {
+            AUDIT_VIOLATIONS_EXIT_CODE
+        } else if n_unaudited > 0 {
+            AUDIT_INCOMPLETE_EXIT_CODE
+        } else {
+            0
+        };
+
+        if app.options.format != app::OutputFormat::Text {
+            let stdout = std::io::stdout();
+            output::write_records(&app.options.format, &records, stdout.lock())?;
+            return Ok(exit_code);
+        }
 
-                let link_table = linkages.get(&acct.id).unwrap();
+        let mut last_account = None;
+        let mut last_folder = None;
 
-                for p in link_table.find_parent_paths(&doc.id).iter().map(|id_path| {
-                    // This is not efficient, and it's panicky, but meh.
-                    let names: Vec<_> = id_path
-                        .iter()
-                        .map(|docid| {
-                            use schema::docs::dsl::*;
-                            let elem = docs
-                                .filter(id.eq(&docid))
-                                .first::<database::Doc>(&app.conn)
-                                .unwrap();
-                            elem.name
-                        })
-                        .collect();
-
-                    names.join(" > ")
-                }) {
-                    path_reprs.push(format!("{}: {}", acct.email, p));
+        for rec in &records {
+            if last_account != Some(&rec.account) {
+                tcprintln!(app.ps, [hl: "{}", rec.account]);
+                last_account = Some(&rec.account);
+                last_folder = None;
+            }
+
+            if last_folder != Some(&rec.folder) {
+                if rec.folder.is_empty() {
+                    tcprintln!(app.ps, ("  (not in any folder)"));
+                } else {
+                    tcprintln!(app.ps, ("  "), [folder: "{}", rec.folder]);
                 }
+
+                last_folder = Some(&rec.folder);
             }
 
-            match path_reprs.len() {
-                0 => tcprintln!(app.ps, [hl: "Path:"], ("      [none??]")),
-                1 => tcprintln!(app.ps, [hl: "Path:"], ("      {}", path_reprs[0])),
-                _n => {
-                    tcprintln!(app.ps, [hl: "Paths::"]);
-                    for p in path_reprs {
-                        tcprintln!(app.ps, ("    {}", p));
-                    }
+            tcprintln!(app.ps,
+                ("    {}: ", rec.name), [yellow: "{}", rec.grantee],
+                (" ({}, {})", rec.role, rec.reason)
+            );
+        }
+
+        if violating_docs.is_empty() {
+            tcreport!(app.ps, info: "no sharing violations found");
+        } else {
+            tcreport!(app.ps, warning: "{} document(s) have sharing violations", violating_docs.len());
+        }
+
+        if records.len()
+            > records
+                .iter()
+                .filter(|r| r.reason != AUDIT_UNKNOWN_REASON)
+                .count()
+        {
+            tcreport!(app.ps, info: "some grantees could not be identified; check them by hand");
+        }
+
+        Ok(exit_code)
+    }
+
+    /// Find the permissions that violate the policy, or can't be checked
+    /// against it, and build the report, sorted by account and folder.
+    fn audit(&self, app: &mut Application) -> Result<Vec<output::SharingViolationRecord>> {
+        use std::collections::HashMap;
+
+        let allowed: Vec<String> = app
+            .config
+            .sharing
+            .allowed_domains
+            .iter()
+            .chain(self.allow.iter())
+            .map(|d| d.to_lowercase())
+            .collect();
+
+        let is_allowed = |domain: &str| {
+            let domain = domain.to_lowercase();
+            allowed
+                .iter()
+                .any(|a| domain == *a || domain.ends_with(&format!(".{}", a)))
+        };
+
+        // Find the offending permissions, grouped by document.
+
+        let perms = {
+            use schema::permissions::dsl::*;
+            permissions
+                .filter(role.ne("owner"))
+                .load::<database::Permission>(&app.conn)?
+        };
+
+        let mut flagged: HashMap<String, Vec<(database::Permission, &str)>> = HashMap::new();
+
+        for perm in perms {
+            let grantee_domain = match perm.grantee_type.as_str() {
+                "domain" => perm.domain.clone(),
+                _ => perm
+                    .email_address
+                    .as_ref()
+                    .and_then(|e| e.rsplit_once('@'))
+                    .map(|(_, d)| d.to_owned()),
+            };
+
+            let reason = match (perm.grantee_type.as_str(), grantee_domain) {
+                ("anyone", _) if perm.allow_file_discovery => "anyone",
+                ("anyone", _) => "anyoneWithLink",
+                (_, Some(ref d)) if is_allowed(d) => continue,
+                (_, Some(_)) => "externalDomain",
+                (_, None) => AUDIT_UNKNOWN_REASON,
+            };
+
+            flagged
+                .entry(perm.doc_id.clone())
+                .or_default()
+                .push((perm, reason));
+        }
+
+        let flagged_docs = {
+            use schema::docs::dsl::*;
+            let mut q = docs.filter(id.eq_any(flagged.keys())).into_boxed();
+
+            if !self.trashed {
+                q = q.filter(trashed.eq(false));
+            }
+
+            q.load::<database::Doc>(&app.conn)?
+        };
+
+        // Now build the report, with one record for each offending
+        // permission in each of the places that the document lives.
+
+        let mut linkages = HashMap::new();
+        let mut records = Vec::new();
+
+        for doc in &flagged_docs {
+            let mut places = app.parent_path_reprs(doc, &mut linkages)?;
+
+            for acct in doc.accounts(app)? {
+                if !places.iter().any(|(email, _)| *email == acct.email) {
+                    places.push((acct.email, String::new()));
                 }
             }
 
-            tcprintln!(app.ps, [hl: "Open-URL:"], ("  {}", doc.open_url()));
+            for (account, folder) in places {
+                for (perm, reason) in &flagged[&doc.id] {
+                    records.push(output::SharingViolationRecord {
+                        account: account.clone(),
+                        folder: folder.clone(),
+                        id: doc.id.clone(),
+                        name: doc.name.clone(),
+                        role: perm.role.clone(),
+                        grantee_type: perm.grantee_type.clone(),
+                        grantee: perm.grantee(),
+                        reason: (*reason).to_owned(),
+                        url: doc.open_url(),
+                    });
+                }
+            }
         }
 
-        Ok(0)
+        records.sort_by(|a, b| {
+            (&a.account, &a.folder, &a.name, &a.id).cmp(&(&b.account, &b.folder, &b.name, &b.id))
+        });
+
+        Ok(records)
     }
 }
 
-/// List documents.
+/// Back up all of the documents in an account.
+///
+/// The backup recreates the account's folder hierarchy, exporting native
+/// documents, and includes a manifest recording the checksum and
+/// modification time of each file. If the destination already holds a
+/// backup of the account, only the documents that have changed since are
+/// fetched again.
 #[derive(Debug, StructOpt)]
-pub struct DrorgListOptions {
-    #[structopt(help = "A document specifier (name, ID, ...)", required_unless = "all")]
-    spec: Option<String>,
+pub struct DrorgBackupOptions {
+    #[structopt(help = "The email address of the account to back up")]
+    account: String,
 
     #[structopt(
-        long = "all",
-        help = "List all documents in the database",
-        conflicts_with = "spec"
+        short = "o",
+        long = "out",
+        help = "The directory, or \".tar.zst\" file, to write the backup to",
+        parse(from_os_str)
     )]
-    all: bool,
+    out: PathBuf,
+
+    #[structopt(
+        long = "format",
+        help = "The format to export native Google documents in (pdf, docx, odt, ...)"
+    )]
+    format: Option<String>,
 }
 
-impl DrorgListOptions {
+impl DrorgBackupOptions {
     fn cli(self, app: &mut Application) -> Result<i32> {
+        // As with mirrors, a stale database would make for a stale backup.
+        if app.options.sync == app::SyncOption::Auto {
+            app.options.sync = app::SyncOption::Yes;
+        }
+
         app.maybe_sync_all_accounts()?;
 
-        let results = if self.all {
-            app.get_docs().all()
-        } else {
-            app.get_docs().process(&self.spec.unwrap())
-        }?;
+        let summary =
+            backup::Backup::new(app, &self.account, self.format.as_deref())?.run(&self.out)?;
 
-        app.print_doc_list(results)?;
+        tcreport!(app.ps, info: "{} fetched, {} unchanged, {} skipped; backup written to \"{}\"",
+                  summary.fetched, summary.unchanged, summary.skipped, self.out.display());
         Ok(0)
     }
 }
 
-/// The command-line action to add a login to the credentials DB.
+/// Print the content of a document.
 ///
-/// Note that "email" doesn't really have to be an email address -- it can be
-/// any random string; the user chooses which account to login-to
-/// interactively during the login process. But I think it makes sense from a
-/// UI perspective to just call it "email" and let the user figure out for
-/// themselves that they can give it some other value if they feel like it.
+/// Native Google Docs documents are exported as HTML and converted to
+/// Markdown by default; they can also be printed as plain text or as the raw
+/// HTML. Other native documents can be printed in whichever of those
+/// formats Drive can export them in. Files with binary content are printed
+/// as they are.
 #[derive(Debug, StructOpt)]
-pub struct DrorgLoginOptions {}
+pub struct DrorgCatOptions {
+    #[structopt(help = "A document specifier (name, ID, ...)")]
+    spec: String,
 
-impl DrorgLoginOptions {
-    /// The auth flow here will print out a message on the console, asking the
-    /// user to go to a URL, following instructions, and paste a string back
-    /// into the client.
-    ///
-    /// We want to allow the user to login to multiple accounts
-    /// simultaneously. Therefore we set up the authenticator flow with a null
-    /// storage, and then add the resulting token to the disk storage.
+    #[structopt(
+        long = "as",
+        help = "The format to print a native document in",
+        raw(possible_values = r#"&["md", "txt", "html"]"#)
+    )]
+    format: Option<String>,
+}
+
+/// The MIME type of native Google Docs documents.
+const GOOGLE_DOC_MIME_TYPE: &str = "application/vnd.google-apps.document";
+
+impl DrorgCatOptions {
     fn cli(self, app: &mut Application) -> Result<i32> {
-        let mut account = accounts::Account::default();
+        use std::io::Write;
 
-        // First we need to get authorization.
-        account.authorize_interactively(&app.secret)?;
+        app.maybe_sync_all_accounts()?;
 
-        // Now, for bookkeeping, we look up the email address associated with
-        // it. We could just have the user specify an identifier, but I went
-        // to the trouble to figure out how to do this right, so ...
-        let email_addr = account.fetch_email_address(&app.secret)?;
-        tcprintln!(app.ps, ("Successfully logged in to "), [hl: "{}", email_addr], ("."));
+        let (doc, mut account) = doc_and_account(app, &self.spec, false)?;
 
-        // We might need to add this account to the database. To have sensible
-        // foreign key relations, the email address is not the primary key of
-        // the accounts table, so we need to see whether there's already an
-        // existing row for this account (which could happen if the user
-        // re-logs-in, etc.) If we add a new row, we have to do this awkward
-        // bit where we insert and then immediately query for the row we just
-        // added (cf https://github.com/diesel-rs/diesel/issues/771 ).
-        {
-            use diesel::prelude::*;
-            use schema::accounts::dsl::*;
+        if doc.is_folder() {
+            return Err(format_err!("cannot print a folder"));
+        }
 
-            let maybe_row = accounts
-                .filter(email.eq(&email_addr))
-                .first::<database::Account>(&app.conn)
-                .optional()?;
+        let format = self.format.as_deref();
+        let mut convert = false;
 
-            let row_id = if let Some(row) = maybe_row {
-                row.id
-            } else {
-                let new_account = database::NewAccount::new(&email_addr);
-                diesel::replace_into(accounts)
-                    .values(&new_account)
-                    .execute(&app.conn)?;
+        let export_mime_type = if doc.is_native() {
+            let about = app.with_backend(&mut account, |backend| backend.about())?;
 
-                let row = accounts
-                    .filter(email.eq(&email_addr))
-                    .first::<database::Account>(&app.conn)?;
-                row.id
+            let export_format = match format.unwrap_or("md") {
+                "md" => {
+                    if doc.mime_type != GOOGLE_DOC_MIME_TYPE {
+                        return Err(format_err!(
+                            "only Google Docs documents can be converted to Markdown"
+                        ));
+                    }
+
+                    convert = true;
+                    "html"
+                }
+                other => other,
             };
 
-            account.data.db_id = row_id;
-            // JSON will be rewritten in acquire_change_page_token below.
-        }
+            Some(content::choose_export_format(
+                &about,
+                &doc.mime_type,
+                Some(export_format),
+            )?)
+        } else {
+            if format.is_some() {
+                tcreport!(app.ps, warning: "\"{}\" is not a native Google document; \
+                                            ignoring the requested format", doc.name);
+            }
 
-        // Initialize our token for checking for changes to the documents. We
-        // do this *before* scanning the complete listing; there's going to be
-        // a race condition either way, but the one that happens with this
-        // ordering seems like it would be more benign.
-        account.acquire_change_page_token(&app.secret)?;
+            None
+        };
 
-        // OK, now actually slurp in the list of documents.
-        tcprintln!(app.ps, ("Scanning documents ..."));
-        app.import_documents(&mut account)?;
+        let mut data = Vec::new();
+        app.download_doc(
+            &mut account,
+            &doc.id,
+            export_mime_type.as_deref(),
+            &mut data,
+        )?;
 
-        // All done.
-        tcprintln!(app.ps, ("Done."));
+        if convert {
+            data = markdown::html_to_markdown(&String::from_utf8_lossy(&data)).into_bytes();
+        }
+
+        let stdout = std::io::stdout();
+        let mut stdout = stdout.lock();
+        stdout.write_all(&data)?;
+        stdout.flush()?;
         Ok(0)
     }
 }
 
-/// List the files in a folder.
+/// Show the discussion threads on a document, or take part in them.
 ///
-/// TODO: this name is going to be super confusing compared to `list`.
+/// Comments can be named by their IDs or by unambiguous prefixes of them.
 #[derive(Debug, StructOpt)]
-pub struct DrorgLsOptions {
-    #[structopt(help = "A folder specifier (name, ID, ...)")]
-    spec: String,
-}
+pub struct DrorgCommentsOptions {
+    #[structopt(subcommand)]
+    command: Option<DrorgCommentsSubcommand>,
 
-impl DrorgLsOptions {
-    fn cli(self, app: &mut Application) -> Result<i32> {
-        use std::collections::HashSet;
+    #[structopt(help = "A document specifier (name, ID, ...)")]
+    spec: Option<String>,
 
-        app.maybe_sync_all_accounts()?;
+    #[structopt(long = "unresolved", help = "Only show comments that are still open")]
+    unresolved: bool,
+}
 
-        let doc = app.get_docs().process_one(self.spec)?;
+/// The operations on comments other than listing them.
+#[derive(Debug, StructOpt)]
+pub enum DrorgCommentsSubcommand {
+    #[structopt(name = "reply")]
+    /// Reply to a comment
+    Reply(DrorgCommentsReplyOptions),
+
+    #[structopt(name = "resolve")]
+    /// Mark a comment as resolved
+    Resolve(DrorgCommentsResolveOptions),
+}
 
-        // We *could* just proceed and see if there's anything that Drive
-        // thinks is a child of this doc, but it seems like the more sensible
-        // UX is to make this a hard failure. You could imagine adding a CLI
-        // option to override this behavior.
+/// Print a possibly multi-line piece of text with a fixed indentation.
+fn print_indented(app: &mut Application, indent: &str, text: &str) {
+    for line in text.lines() {
+        tcprintln!(app.ps, ("{}{}", indent, line));
+    }
+}
 
-        if !doc.is_folder() {
-            return Err(format_err!("the selected document is not a folder"));
+/// Get the display name of the author of a comment or reply.
+fn author_name(author: Option<&google_drive3::User>) -> &str {
+    author
+        .and_then(|u| u.display_name.as_deref())
+        .unwrap_or("[unknown]")
+}
+
+impl DrorgCommentsOptions {
+    fn cli(self, app: &mut Application) -> Result<i32> {
+        match self.command {
+            Some(DrorgCommentsSubcommand::Reply(opts)) => return opts.cli(app),
+            Some(DrorgCommentsSubcommand::Resolve(opts)) => return opts.cli(app),
+            None => {}
         }
 
-        // This is another operation which can be surprising when you think
-        // about the behavior when a doc belongs to more than one account. We
-        // find children for each account separately and merge the results.
+        let spec = self
+            .spec
+            .ok_or_else(|| format_err!("a document specifier must be given"))?;
 
-        let accounts = doc.accounts(app)?;
-        let mut child_ids = HashSet::new();
+        app.maybe_sync_all_accounts()?;
 
-        if accounts.len() > 1 {
-            tcreport!(app.ps, warning: "folder belongs to multiple accounts; \
-                                        their listings will be merged");
-        }
+        let (doc, mut account) = doc_and_account(app, &spec, false)?;
+        let comments = app.with_backend(&mut account, |backend| backend.list_comments(&doc.id))?;
+        let mut first = true;
 
-        for acct in &accounts {
-            let table = app.load_linkage_table(acct.id, false)?;
-            let node = match table.nodes.get(&doc.id) {
-                Some(n) => *n,
-                None => continue,
-            };
+        for comment in comments {
+            let resolved = comment.resolved == Some(true);
 
-            for child_idx in table.graph.neighbors(node) {
-                child_ids.insert(table.graph[child_idx].clone());
+            if comment.deleted == Some(true) || (self.unresolved && resolved) {
+                continue;
             }
-        }
 
-        // Is this the best ordering?
+            if first {
+                first = false;
+            } else {
+                tcprintln!(app.ps, (""));
+            }
+
+            tcprintln!(app.ps,
+                       [hl: "{}", comment.id.as_deref().unwrap_or("?")],
+                       ("  "),
+                       [green: "{}", author_name(comment.author.as_ref())],
+                       ("  {}", api_time_to_local(comment.created_time.as_ref())),
+                       [yellow: "{}", if resolved { "  (resolved)" } else { "" }]
+            );
+
+            if let Some(quote) = comment
+                .quoted_file_content
+                .as_ref()
+                .and_then(|q| q.value.as_ref())
+            {
+                print_indented(app, "    > ", quote);
+            }
+
+            print_indented(app, "    ", comment.content.as_deref().unwrap_or(""));
+
+            for reply in comment.replies.unwrap_or_default() {
+                if reply.deleted == Some(true) {
+                    continue;
+                }
+
+                let action = match reply.action.as_deref() {
+                    Some("resolve") => "  (resolved the comment)",
+                    Some("reopen") => "  (reopened the comment)",
+                    _ => "",
+                };
+
+                tcprintln!(app.ps,
+                           ("      "),
+                           [green: "{}", author_name(reply.author.as_ref())],
+                           ("  {}", api_time_to_local(reply.created_time.as_ref())),
+                           [yellow: "{}", action]
+                );
+
+                print_indented(app, "        ", reply.content.as_deref().unwrap_or(""));
+            }
+        }
+
+        if first {
+            tcreport!(app.ps, info: "no {}comments on \"{}\"",
+                      if self.unresolved { "open " } else { "" }, doc.name);
+        }
+
+        Ok(0)
+    }
+}
+
+/// Reply to a comment on a document.
+#[derive(Debug, StructOpt)]
+pub struct DrorgCommentsReplyOptions {
+    #[structopt(help = "A document specifier (name, ID, ...)")]
+    spec: String,
+
+    #[structopt(help = "The ID of the comment, or an unambiguous prefix of it")]
+    comment: String,
+
+    #[structopt(help = "The text of the reply")]
+    text: String,
+}
+
+impl DrorgCommentsReplyOptions {
+    fn cli(self, app: &mut Application) -> Result<i32> {
+        app.maybe_sync_all_accounts()?;
+
+        let (doc, mut account) = doc_and_account(app, &self.spec, true)?;
+
+        let comment_id = app.with_backend(&mut account, |backend| {
+            let reply = google_drive3::Reply {
+                content: Some(self.text.clone()),
+                ..Default::default()
+            };
+
+            let comments = backend.list_comments(&doc.id)?;
+            let comment_id = find_by_id(&comments, &self.comment, "comment", |c| c.id.as_deref())?
+                .id
+                .clone()
+                .unwrap_or_default();
+            backend.create_reply(&doc.id, &comment_id, reply)?;
+            Ok(comment_id)
+        })?;
+
+        tcreport!(app.ps, info: "replied to comment {} on \"{}\"", comment_id, doc.name);
+        Ok(0)
+    }
+}
+
+/// Mark a comment on a document as resolved.
+#[derive(Debug, StructOpt)]
+pub struct DrorgCommentsResolveOptions {
+    #[structopt(help = "A document specifier (name, ID, ...)")]
+    spec: String,
+
+    #[structopt(help = "The ID of the comment, or an unambiguous prefix of it")]
+    comment: String,
+
+    #[structopt(
+        short = "m",
+        long = "message",
+        help = "A reply to post along with the resolution"
+    )]
+    message: Option<String>,
+}
+
+impl DrorgCommentsResolveOptions {
+    fn cli(self, app: &mut Application) -> Result<i32> {
+        app.maybe_sync_all_accounts()?;
+
+        let (doc, mut account) = doc_and_account(app, &self.spec, true)?;
+
+        let (comment_id, already) = app.with_backend(&mut account, |backend| {
+            let comments = backend.list_comments(&doc.id)?;
+            let comment = find_by_id(&comments, &self.comment, "comment", |c| c.id.as_deref())?;
+            let comment_id = comment.id.clone().unwrap_or_default();
+
+            if comment.resolved == Some(true) {
+                return Ok((comment_id, true));
+            }
+
+            // Comments are resolved by posting a reply with the appropriate
+            // action; the reply may or may not have any text.
+
+            let reply = google_drive3::Reply {
+                action: Some("resolve".to_owned()),
+                content: self.message.clone(),
+                ..Default::default()
+            };
+
+            backend.create_reply(&doc.id, &comment_id, reply)?;
+            Ok((comment_id, false))
+        })?;
+
+        if already {
+            tcreport!(app.ps, warning: "comment {} on \"{}\" was already resolved", comment_id, doc.name);
+        } else {
+            tcreport!(app.ps, info: "resolved comment {} on \"{}\"", comment_id, doc.name);
+        }
+
+        Ok(0)
+    }
+}
+
+/// List the shared drives that the logged-in accounts are members of.
+#[derive(Debug, StructOpt)]
+pub struct DrorgDrivesOptions {}
+
+impl DrorgDrivesOptions {
+    fn cli(self, app: &mut Application) -> Result<i32> {
+        app.maybe_sync_all_accounts()?;
+
+        // The root folder of each drive has the same ID as the drive.
+        let roots = {
+            use schema::docs::dsl::*;
+            let drive_ids = schema::drives::table.select(schema::drives::id);
+            docs.filter(id.eq_any(drive_ids))
+                .order(name.asc())
+                .load::<database::Doc>(&app.conn)?
+        };
+
+        app.print_doc_list(roots)?;
+        Ok(0)
+    }
+}
+
+/// Summarize how much storage is used by the contents of a folder.
+#[derive(Debug, StructOpt)]
+pub struct DrorgDuOptions {
+    #[structopt(help = "A folder specifier (name, ID, ...)", default_value = ".")]
+    spec: String,
+
+    #[structopt(short = "a", long = "all", help = "List files as well as folders")]
+    all: bool,
+
+    #[structopt(
+        long = "multi-parent",
+        help = "How to count documents that are in more than one folder",
+        parse(try_from_str),
+        default_value = "once",
+        raw(possible_values = r#"&["once", "apportion"]"#)
+    )]
+    multi_parent: app::MultiParentPolicy,
+
+    #[structopt(long = "trashed", help = "Count documents that are in the trash")]
+    trashed: bool,
+
+    #[structopt(long = "quota", help = "Compare with the account's storage quota")]
+    quota: bool,
+}
+
+/// Format a possibly-fractional number of bytes in a human-friendly way.
+fn human_bytes(n: f64) -> String {
+    use humansize::{file_size_opts, FileSize};
+    (n.round() as u64)
+        .file_size(file_size_opts::BINARY)
+        .unwrap_or_else(|_e| "[invalid]".to_owned())
+}
+
+impl DrorgDuOptions {
+    fn cli(self, app: &mut Application) -> Result<i32> {
+        app.maybe_sync_all_accounts()?;
+
+        let doc = app.get_docs().process_one(&self.spec)?;
+
+        if !doc.is_folder() {
+            return Err(format_err!("the selected document is not a folder"));
+        }
+
+        // Storage is accounted separately for each account, so if the folder
+        // belongs to several, we give each its own report.
+
+        let accounts = doc.accounts(app)?;
+
+        for (i, acct) in accounts.iter().enumerate() {
+            if accounts.len() > 1 {
+                if i > 0 {
+                    tcprintln!(app.ps, (""));
+                }
+
+                tcprintln!(app.ps, [hl: "{}:", acct.email]);
+            }
+
+            self.report_account(app, &doc, acct)?;
+        }
+
+        Ok(0)
+    }
+
+    fn report_account(
+        &self,
+        app: &mut Application,
+        doc: &database::Doc,
+        acct: &database::Account,
+    ) -> Result<()> {
+        use std::collections::HashMap;
+
+        let table = app.load_linkage_table(acct.id, false)?;
+
+        // Sizes and trash status of everything in the account, by ID.
+        let info: HashMap<String, (Option<i32>, bool)> = {
+            use schema::account_associations::dsl::*;
+            use schema::docs;
+
+            account_associations
+                .inner_join(docs::table)
+                .filter(account_id.eq(acct.id))
+                .select((docs::id, docs::size, docs::trashed))
+                .load::<(String, Option<i32>, bool)>(&app.conn)?
+                .into_iter()
+                .map(|(docid, sz, tr)| (docid, (sz, tr)))
+                .collect()
+        };
+
+        let counts = |docid: &str| match info.get(docid) {
+            Some((_, true)) => self.trashed,
+            Some(_) => true,
+            None => false,
+        };
+
+        let total_of = |root_id: &str| -> f64 {
+            table
+                .subtree_weights(root_id, &self.multi_parent)
+                .iter()
+                .filter(|(docid, _)| counts(docid))
+                .filter_map(|(docid, w)| info[docid.as_str()].0.map(|sz| f64::from(sz) * w))
+                .sum()
+        };
+
+        // The shares of the children that should be attributed to this folder.
+        let folder_weights = table.subtree_weights(&doc.id, &self.multi_parent);
+
+        let children = {
+            use schema::docs::dsl::*;
+            docs.filter(id.eq_any(table.children(&doc.id)))
+                .load::<database::Doc>(&app.conn)?
+        };
+
+        let mut entries = Vec::new();
+        let mut loose_total = 0.;
+        let mut n_loose = 0;
+
+        for child in children {
+            if !counts(&child.id) {
+                continue;
+            }
+
+            let share = folder_weights.get(&child.id).cloned().unwrap_or(1.);
+            let size = total_of(&child.id) * share;
+
+            if child.is_folder() || self.all {
+                entries.push((size, child));
+            } else {
+                loose_total += size;
+                n_loose += 1;
+            }
+        }
+
+        entries.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+
+        for (size, child) in &entries {
+            if child.is_folder() {
+                tcprintln!(app.ps, ("{:>10}  ", human_bytes(*size)), [folder: "{}/", child.name]);
+            } else {
+                tcprintln!(app.ps, ("{:>10}  {}", human_bytes(*size), child.name));
+            }
+        }
+
+        if n_loose > 0 {
+            tcprintln!(
+                app.ps,
+                (
+                    "{:>10}  ({} files directly in this folder)",
+                    human_bytes(loose_total),
+                    n_loose
+                )
+            );
+        }
+
+        let total = total_of(&doc.id);
+        tcprintln!(app.ps, [hl: "{:>10}  total", human_bytes(total)]);
+
+        if self.quota {
+            let mut account = accounts::Account::load(&acct.email)?;
+            let about = app.with_backend(&mut account, |backend| backend.about())?;
+
+            let quota = about
+                .storage_quota
+                .ok_or_else(|| format_err!("server did not report storage quota information"))?;
+            let parse = |v: Option<String>| v.and_then(|t| t.parse::<f64>().ok());
+            let in_drive = parse(quota.usage_in_drive);
+            let in_trash = parse(quota.usage_in_drive_trash);
+
+            tcprintln!(app.ps, (""));
+
+            if let Some(usage) = in_drive {
+                let trash = in_trash.map(human_bytes).unwrap_or_else(|| "?".to_owned());
+                tcprintln!(app.ps, [hl: "Usage in Drive:"], ("  {} ({} in trash)", human_bytes(usage), trash));
+
+                if usage > 0. {
+                    let pct = 100. * total / usage;
+                    tcprintln!(app.ps, [hl: "This folder:"], ("     {:.1}% of usage in Drive", pct));
+                }
+            }
+
+            match (parse(quota.limit), parse(quota.usage)) {
+                (Some(limit), Some(usage)) if limit > 0. => {
+                    let pct = 100. * usage / limit;
+                    tcprintln!(app.ps, [hl: "Total usage:"], ("     {} of {} ({:.1}%)",
+                                                              human_bytes(usage), human_bytes(limit), pct));
+                }
+
+                (None, Some(usage)) => {
+                    tcprintln!(app.ps, [hl: "Total usage:"], ("     {} (no limit)", human_bytes(usage)));
+                }
+
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Find documents that appear to be copies of one another.
+///
+/// With `--trash`, the older copies in each group are moved to the trash,
+/// using the account that owns each one. Copies that none of the accounts
+/// own are left alone and reported, since trashing them would most likely
+/// fail, or remove somebody else's document from view.
+#[derive(Debug, StructOpt)]
+pub struct DrorgDupesOptions {
+    #[structopt(
+        long = "trash",
+        help = "Move all but the newest copy in each group to the trash \
+                (except for groups matched only by name, and copies that you don't own)"
+    )]
+    trash: bool,
+}
+
+/// How `drorg dupes` decides that two documents are the same.
+#[derive(Debug, Eq, Hash, PartialEq)]
+enum DupeKey {
+    /// Files with binary content are compared by checksum.
+    Checksum(String),
+
+    /// Native documents don't have checksums, so we fall back to comparing
+    /// names, types, and sizes.
+    Native(String, String, Option<i32>),
+}
+
+impl DupeKey {
+    /// Get the key of a document, or None if it's a file whose checksum we
+    /// don't know, which can't be compared.
+    fn of(doc: &database::Doc) -> Option<DupeKey> {
+        if let Some(sum) = doc.md5_checksum.as_ref() {
+            Some(DupeKey::Checksum(sum.clone()))
+        } else if doc.is_native() {
+            Some(DupeKey::Native(
+                doc.name.clone(),
+                doc.mime_type.clone(),
+                doc.size,
+            ))
+        } else {
+            None
+        }
+    }
+}
+
+/// Return true if a group of supposed duplicates was matched on nothing but
+/// names and types. Most native documents don't report sizes, so
+/// unrelated documents that happen to share a name (say, "Notes") end up
+/// in the same group. Such groups are listed but never trashed.
+fn is_name_only_dupe_group(docs: &[database::Doc]) -> bool {
+    docs[0].md5_checksum.is_none() && docs[0].size.is_none()
+}
+
+/// Sort documents into groups of apparent copies.
+///
+/// Each group has the newest copy first, and the groups that waste the most
+/// space come first. Documents without copies are dropped. The second
+/// return value is the number of files that were ignored because their
+/// checksums aren't known.
+fn group_dupes(candidates: Vec<database::Doc>) -> (Vec<Vec<database::Doc>>, usize) {
+    use std::collections::HashMap;
+
+    let mut groups: HashMap<DupeKey, Vec<database::Doc>> = HashMap::new();
+    let mut n_unchecksummed = 0;
+
+    for doc in candidates {
+        match DupeKey::of(&doc) {
+            Some(key) => groups.entry(key).or_default().push(doc),
+            None => n_unchecksummed += 1,
+        }
+    }
+
+    let mut groups: Vec<_> = groups
+        .into_values()
+        .map(|mut docs| {
+            docs.sort_by_key(|d| d.utc_mod_time());
+            docs.reverse();
+            docs
+        })
+        .filter(|docs| docs.len() > 1)
+        .collect();
+
+    groups.sort_by_key(|docs| {
+        let wasted = i64::from(docs[0].size.unwrap_or(0)) * (docs.len() as i64 - 1);
+        (-wasted, docs[0].name.to_lowercase(), docs[0].id.clone())
+    });
+
+    (groups, n_unchecksummed)
+}
+
+impl DrorgDupesOptions {
+    fn cli(self, app: &mut Application) -> Result<i32> {
+        use std::collections::HashMap;
+
+        app.maybe_sync_all_accounts()?;
+
+        let candidates = {
+            use schema::docs::dsl::*;
+            docs.filter(trashed.eq(false))
+                .filter(mime_type.ne(database::FOLDER_MIME_TYPE))
+                .load::<database::Doc>(&app.conn)?
+        };
+
+        let (groups, n_unchecksummed) = group_dupes(candidates);
+
+        if n_unchecksummed > 0 {
+            tcreport!(app.ps, warning: "ignoring {} file(s) without recorded checksums; \
+                                        `drorg sync --rebuild` may fetch them", n_unchecksummed);
+        }
+
+        let text = app.options.format == app::OutputFormat::Text;
+        let mut linkages = HashMap::new();
+
+        if !text {
+            let mut records = Vec::new();
+
+            for (gnum, docs) in groups.iter().enumerate() {
+                let matched_by = if docs[0].md5_checksum.is_some() {
+                    "checksum"
+                } else if is_name_only_dupe_group(docs) {
+                    "name"
+                } else {
+                    "name+size"
+                };
+
+                for (j, doc) in docs.iter().enumerate() {
+                    records.push(output::DupeRecord {
+                        group: gnum + 1,
+                        matched_by: matched_by.to_owned(),
+                        newest: j == 0,
+                        doc: app.doc_record(doc, &mut linkages)?,
+                    });
+                }
+            }
+
+            let stdout = std::io::stdout();
+            output::write_records(&app.options.format, &records, stdout.lock())?;
+        } else if groups.is_empty() {
+            tcreport!(app.ps, info: "no duplicates found");
+            return Ok(0);
+        } else {
+            let listing: Vec<_> = groups.iter().flatten().cloned().collect();
+            app.record_listing(&listing)?;
+
+            let n_width = format!("{}", listing.len()).len();
+            let mut i = 1;
+
+            for (gnum, docs) in groups.iter().enumerate() {
+                if gnum > 0 {
+                    tcprintln!(app.ps, (""));
+                }
+
+                let size = docs[0]
+                    .human_size()
+                    .map(|s| format!(", {} each", s))
+                    .unwrap_or_default();
+                let note = if is_name_only_dupe_group(docs) {
+                    "  (matched by name only)"
+                } else {
+                    ""
+                };
+                tcprintln!(app.ps,
+                           [hl: "{} copies of \"{}\"", docs.len(), docs[0].name],
+                           ("{}", size),
+                           [yellow: "{}", note]
+                );
+
+                for (j, doc) in docs.iter().enumerate() {
+                    let note = if j == 0 { "  (newest)" } else { "" };
+
+                    tcprintln!(app.ps,
+                               [percent_tag: "%{1:<0$}", n_width, i],
+                               ("  "),
+                               [green: "{}", doc.name],
+                               ("  modified {}{}", doc.utc_mod_time().format("%Y-%m-%d"), note)
+                    );
+
+                    for (email, path) in app.parent_path_reprs(doc, &mut linkages)? {
+                        tcprintln!(app.ps, ("{:1$}    {2}: {3}", "", n_width, email, path));
+                    }
+
+                    i += 1;
+                }
+            }
+        }
+
+        if !self.trash {
+            return Ok(0);
+        }
+
+        // Work out which account will trash each copy before changing
+        // anything, so that we don't give up halfway through.
+
+        let mut plan = Vec::new();
+        let mut n_skipped = 0;
+        let mut n_unowned = 0;
+
+        for docs in &groups {
+            if is_name_only_dupe_group(docs) {
+                n_skipped += 1;
+                continue;
+            }
+
+            for doc in &docs[1..] {
+                let owner = doc
+                    .associations(app)?
+                    .into_iter()
+                    .find(|(assoc, _acct)| assoc.owned_by_me);
+
+                match owner {
+                    Some((_assoc, acct)) => plan.push((doc, acct)),
+                    None => {
+                        tcreport!(app.ps, warning: "not trashing \"{}\" ({}), since none of your \
+                                                    accounts owns it", doc.name, doc.id);
+                        n_unowned += 1;
+                    }
+                }
+            }
+        }
+
+        let trash_patch = google_drive3::File {
+            trashed: Some(true),
+            ..Default::default()
+        };
+
+        for (doc, acct) in &plan {
+            app.update_doc(acct, &doc.id, trash_patch.clone(), &[], &[])?;
+        }
+
+        if n_skipped > 0 {
+            tcreport!(app.ps, warning: "did not trash anything in {} group(s) matched by \
+                                        name only; check them by hand", n_skipped);
+        }
+
+        if text {
+            tcprintln!(app.ps, (""));
+            tcreport!(app.ps, info: "moved {} documents to the trash", plan.len());
+
+            if n_unowned > 0 {
+                tcreport!(app.ps, info: "left {} copies that you don't own", n_unowned);
+            }
+        }
+
+        Ok(0)
+    }
+}
+
+/// Change the starred or trashed status of documents.
+///
+/// This type is shared by the `star`, `unstar`, `trash`, and `untrash`
+/// commands, which differ only in the change that they make.
+#[derive(Debug, StructOpt)]
+pub struct DrorgFlagOptions {
+    #[structopt(help = "Document specifiers (name, ID, ...)", required = true)]
+    specs: Vec<String>,
+}
+
+impl DrorgFlagOptions {
+    fn cli(self, app: &mut Application, patch: google_drive3::File) -> Result<i32> {
+        app.maybe_sync_all_accounts()?;
+
+        let mut to_change = Vec::new();
+
+        for spec in &self.specs {
+            to_change.push(app.get_docs().strict(true).process_one(spec)?);
+        }
+
+        // Starring is a per-user setting, and it's not obvious what to do if a
+        // document belongs to several accounts. For now we just use the first
+        // one.
+
+        let mut changed = Vec::new();
+
+        for doc in to_change {
+            let accounts = doc.accounts(app)?;
+            let acct = accounts.first().ok_or_else(|| {
+                format_err!("\"{}\" is not associated with any account", doc.name)
+            })?;
+
+            if accounts.len() > 1 {
+                tcreport!(app.ps, warning: "\"{}\" belongs to multiple accounts; \
+                                            modifying it through {}", doc.name, acct.email);
+            }
+
+            changed.push(app.update_doc(acct, &doc.id, patch.clone(), &[], &[])?);
+        }
+
+        app.print_doc_list(changed)?;
+        Ok(0)
+    }
+}
+
+/// Download a document to a local file.
+#[derive(Debug, StructOpt)]
+pub struct DrorgGetOptions {
+    #[structopt(help = "A document specifier (name, ID, ...)")]
+    spec: String,
+
+    #[structopt(
+        short = "o",
+        long = "output",
+        help = "The path of the output file (default: based on the document name)",
+        parse(from_os_str)
+    )]
+    output: Option<PathBuf>,
+
+    #[structopt(
+        long = "format",
+        help = "The format to export a native Google document in (pdf, docx, odt, ...)"
+    )]
+    format: Option<String>,
+}
+
+impl DrorgGetOptions {
+    fn cli(self, app: &mut Application) -> Result<i32> {
+        app.maybe_sync_all_accounts()?;
+
+        let doc = app.get_docs().process_one(&self.spec)?;
+
+        if doc.is_folder() {
+            return Err(format_err!("cannot download a folder"));
+        }
+
+        let acct =
+            doc.accounts(app)?.into_iter().next().ok_or_else(|| {
+                format_err!("\"{}\" is not associated with any account", doc.name)
+            })?;
+        let mut account = accounts::Account::load(&acct.email)?;
+
+        // Native docs need to be exported, and the "about" info tells us
+        // which formats are possible.
+
+        let export_mime_type = if doc.is_native() {
+            let about = app.with_backend(&mut account, |backend| backend.about())?;
+            Some(content::choose_export_format(
+                &about,
+                &doc.mime_type,
+                self.format.as_deref(),
+            )?)
+        } else {
+            if self.format.is_some() {
+                tcreport!(app.ps, warning: "\"{}\" is not a native Google document; \
+                                            ignoring the requested format", doc.name);
+            }
+
+            None
+        };
+
+        let export_mime_type = export_mime_type.as_deref();
+        let path = self
+            .output
+            .unwrap_or_else(|| content::local_filename(&doc.name, export_mime_type));
+
+        content::write_atomically(&path, |dest| {
+            app.download_doc(&mut account, &doc.id, export_mime_type, dest)?;
+            Ok(())
+        })?;
+
+        tcreport!(app.ps, info: "wrote \"{}\"", path.display());
+        Ok(0)
+    }
+}
+
+/// Show detailed information about one or more documents.
+#[derive(Debug, StructOpt)]
+pub struct DrorgInfoOptions {
+    #[structopt(help = "A document specifier (name, ID, ...)")]
+    spec: String,
+}
+
+impl DrorgInfoOptions {
+    fn cli(self, app: &mut Application) -> Result<i32> {
+        use std::collections::HashMap;
+
+        app.maybe_sync_all_accounts()?;
+
+        let mut linkages = HashMap::new();
+        let results = app.get_docs().process(&self.spec)?; // note: avoid name clash with db table
+
+        if app.options.format != app::OutputFormat::Text {
+            app.print_doc_records(&results)?;
+            return Ok(0);
+        }
+
+        let mut first = true;
+
+        for doc in results {
+            if first {
+                first = false;
+            } else {
+                tcprintln!(app.ps, (""));
+            }
+
+            tcprintln!(app.ps, [hl: "Name:"], ("      "), [green: "{}", doc.name]);
+            tcprintln!(app.ps, [hl: "MIME-type:"], (" {}", doc.mime_type));
+            tcprintln!(app.ps, [hl: "Size:"], ("      {}", doc.human_size().unwrap_or_else(|| "N/A".to_owned())));
+            tcprintln!(app.ps, [hl: "Modified:"], ("  {}", doc.utc_mod_time().to_rfc3339()));
+            tcprintln!(app.ps, [hl: "ID:"], ("        {}", doc.id));
+
+            if let Some(ref the_drive_id) = doc.drive_id {
+                let drive_name = {
+                    use schema::drives::dsl::*;
+                    drives
+                        .filter(id.eq(the_drive_id))
+                        .first::<database::SharedDrive>(&app.conn)
+                        .optional()?
+                        .map_or_else(|| the_drive_id.clone(), |d| d.name)
+                };
+                tcprintln!(app.ps, [hl: "Drive:"], ("     {}", drive_name));
+            }
+
+            if let Some(ref email) = doc.owner_email {
+                match doc.owner_name {
+                    Some(ref n) => tcprintln!(app.ps, [hl: "Owner:"], ("     {} <{}>", n, email)),
+                    None => tcprintln!(app.ps, [hl: "Owner:"], ("     {}", email)),
+                }
+            }
+
+            for (assoc, acct) in doc.associations(app)? {
+                let sharer = match (assoc.sharing_user_name, assoc.sharing_user_email) {
+                    (Some(n), Some(e)) => format!("{} <{}>", n, e),
+                    (None, Some(e)) => e,
+                    (Some(n), None) => n,
+                    (None, None) => continue,
+                };
+
+                tcprintln!(app.ps, [hl: "Shared by:"], (" {} (with {})", sharer, acct.email));
+            }
+
+            tcprintln!(app.ps, [hl: "Starred?:"], ("  {}", if doc.starred { "yes" } else { "no" }));
+            tcprintln!(app.ps, [hl: "Trashed?:"], ("  {}", if doc.trashed { "yes" } else { "no" }));
+
+            let path_reprs: Vec<_> = app
+                .parent_path_reprs(&doc, &mut linkages)?
+                .into_iter()
+                .map(|(email, p)| format!("{}: {}", email, p))
+                .collect();
+
+            match path_reprs.len() {
+                0 => tcprintln!(app.ps, [hl: "Path:"], ("      [none??]")),
+                1 => tcprintln!(app.ps, [hl: "Path:"], ("      {}", path_reprs[0])),
+                _n => {
+                    tcprintln!(app.ps, [hl: "Paths::"]);
+                    for p in path_reprs {
+                        tcprintln!(app.ps, ("    {}", p));
+                    }
+                }
+            }
+
+            tcprintln!(app.ps, [hl: "Open-URL:"], ("  {}", doc.open_url()));
+        }
+
+        Ok(0)
+    }
+}
+
+/// List documents.
+#[derive(Debug, StructOpt)]
+pub struct DrorgListOptions {
+    #[structopt(help = "A document specifier (name, ID, ...)", required_unless = "all")]
+    spec: Option<String>,
+
+    #[structopt(
+        long = "all",
+        help = "List all documents in the database",
+        conflicts_with = "spec"
+    )]
+    all: bool,
+}
+
+impl DrorgListOptions {
+    fn cli(self, app: &mut Application) -> Result<i32> {
+        app.maybe_sync_all_accounts()?;
+
+        let results = if self.all {
+            app.get_docs().all()
+        } else {
+            app.get_docs().process(&self.spec.unwrap())
+        }?;
+
+        app.print_doc_list(results)?;
+        Ok(0)
+    }
+}
+
+/// Show the history of changes that synchronization has seen.
+///
+/// Each sync records what happened to the documents that it touched, so
+/// this can show what changed between syncs -- including documents that have
+/// since been removed, which can be selected by their IDs. Changes from all
+/// accounts are shown, oldest first.
+#[derive(Debug, StructOpt)]
+pub struct DrorgLogOptions {
+    #[structopt(
+        long = "since",
+        help = "Only show changes since this local date (YYYY-MM-DD) or RFC 3339 timestamp"
+    )]
+    since: Option<String>,
+
+    #[structopt(help = "Only show changes to this document (specifier or ID)")]
+    spec: Option<String>,
+}
+
+impl DrorgLogOptions {
+    /// Load the logged changes that match the filters, oldest first.
+    fn entries(&self, app: &mut Application) -> Result<Vec<database::ChangeLogEntry>> {
+        let since = match self.since {
+            Some(ref text) => Some(query::parse_time(text).map(|(t, _)| t).ok_or_else(|| {
+                format_err!(
+                    "cannot parse \"{}\" as a date (YYYY-MM-DD) or RFC 3339 timestamp",
+                    text
+                )
+            })?),
+            None => None,
+        };
+
+        // Removed documents can't be found by the usual means, so we also
+        // accept any ID that appears in the log.
+        let the_doc_id = match self.spec {
+            Some(ref spec) => Some(match app.get_docs().process_one(spec) {
+                Ok(doc) => doc.id,
+                Err(e) => {
+                    use schema::changes_log::dsl::*;
+
+                    let logged =
+                        diesel::select(diesel::dsl::exists(changes_log.filter(doc_id.eq(spec))))
+                            .get_result::<bool>(&app.conn)?;
+
+                    if !logged {
+                        return Err(e);
+                    }
+
+                    spec.clone()
+                }
+            }),
+            None => None,
+        };
+
+        use schema::changes_log::dsl::*;
+
+        let mut q = changes_log.into_boxed();
+
+        if let Some(t) = since {
+            q = q.filter(timestamp.ge(t));
+        }
+
+        if let Some(ref d) = the_doc_id {
+            q = q.filter(doc_id.eq(d));
+        }
+
+        Ok(q.order((timestamp.asc(), id.asc()))
+            .load::<database::ChangeLogEntry>(&app.conn)?)
+    }
+
+    fn cli(self, app: &mut Application) -> Result<i32> {
+        use std::collections::HashMap;
+
+        app.maybe_sync_all_accounts()?;
+        let entries = self.entries(app)?;
+
+        if entries.is_empty() {
+            if self.since.is_some() || self.spec.is_some() {
+                tcreport!(app.ps, info: "no logged changes match the given filters");
+            } else {
+                tcreport!(app.ps, info: "no changes have been logged");
+            }
+            return Ok(0);
+        }
+
+        let emails: HashMap<i32, String> = {
+            use schema::accounts::dsl::*;
+            accounts
+                .select((id, email))
+                .load::<(i32, String)>(&app.conn)?
+                .into_iter()
+                .collect()
+        };
+
+        let show_accounts = entries
+            .iter()
+            .map(|e| e.account_id)
+            .collect::<HashSet<_>>()
+            .len()
+            > 1;
+
+        // Moves are logged in terms of folder IDs; look up their names.
+
+        let folder_ids: HashSet<&str> = entries
+            .iter()
+            .filter(|e| e.kind == "moved")
+            .flat_map(|e| e.old_value.iter().chain(e.new_value.iter()))
+            .flat_map(|v| v.split(','))
+            .filter(|fid| !fid.is_empty())
+            .collect();
+
+        let mut folder_names: HashMap<String, String> = {
+            use schema::drives::dsl::*;
+            drives
+                .filter(id.eq_any(&folder_ids))
+                .select((id, name))
+                .load::<(String, String)>(&app.conn)?
+                .into_iter()
+                .collect()
+        };
+
+        {
+            use schema::docs::dsl::*;
+            folder_names.extend(
+                docs.filter(id.eq_any(&folder_ids))
+                    .select((id, name))
+                    .load::<(String, String)>(&app.conn)?,
+            );
+        }
+
+        let describe_folders = |value: &Option<String>| -> String {
+            let names: Vec<&str> = value
+                .iter()
+                .flat_map(|v| v.split(','))
+                .filter(|fid| !fid.is_empty())
+                .map(|fid| folder_names.get(fid).map_or(fid, |n| n.as_str()))
+                .collect();
+
+            if names.is_empty() {
+                "nowhere".to_owned()
+            } else {
+                names.join(", ")
+            }
+        };
+
+        for entry in &entries {
+            let when = chrono::DateTime::<chrono::Utc>::from_utc(entry.timestamp, chrono::Utc)
+                .with_timezone(&chrono::Local)
+                .format("%Y-%m-%d %H:%M");
+
+            let account = if show_accounts {
+                emails
+                    .get(&entry.account_id)
+                    .map_or_else(|| "[unknown account]  ".to_owned(), |e| format!("{}  ", e))
+            } else {
+                String::new()
+            };
+
+            let restored = entry.kind == "trashed" && entry.new_value.as_deref() == Some("false");
+            let label = if restored { "untrashed" } else { &entry.kind };
+
+            let detail = match entry.kind.as_str() {
+                "renamed" => format!(" (was \"{}\")", entry.old_value.as_deref().unwrap_or("")),
+                "moved" => format!(
+                    " (from {} to {})",
+                    describe_folders(&entry.old_value),
+                    describe_folders(&entry.new_value)
+                ),
+                _ => String::new(),
+            };
+
+            tcprintln!(app.ps,
+                       ("{}  ", when),
+                       [hl: "{}", account],
+                       {colors, {
+                           match entry.kind.as_str() {
+                               "added" => &colors.green,
+                               "removed" => &colors.red,
+                               _ => &colors.yellow,
+                           }
+                       }: "{:<9}", label},
+                       ("  {}{}", entry.doc_name, detail)
+            );
+        }
+
+        Ok(0)
+    }
+}
+
+/// The command-line action to add a login to the credentials DB.
+///
+/// Note that "email" doesn't really have to be an email address -- it can be
+/// any random string; the user chooses which account to login-to
+/// interactively during the login process. But I think it makes sense from a
+/// UI perspective to just call it "email" and let the user figure out for
+/// themselves that they can give it some other value if they feel like it.
+#[derive(Debug, StructOpt)]
+pub struct DrorgLoginOptions {}
+
+impl DrorgLoginOptions {
+    /// The auth flow here will print out a message on the console, asking the
+    /// user to go to a URL, following instructions, and paste a string back
+    /// into the client.
+    ///
+    /// We want to allow the user to login to multiple accounts
+    /// simultaneously. Therefore we set up the authenticator flow with a null
+    /// storage, and then add the resulting token to the disk storage.
+    fn cli(self, app: &mut Application) -> Result<i32> {
+        let mut account = accounts::Account::default();
+
+        // First we need to get authorization -- unless we're working from a
+        // fixture, in which case there's nobody to authorize against.
+        if app.fixture.is_none() {
+            account.authorize_interactively(&app.secret)?;
+        }
+
+        // Now, for bookkeeping, we look up the email address associated with
+        // it. We could just have the user specify an identifier, but I went
+        // to the trouble to figure out how to do this right, so ...
+        let email_addr = app.fetch_email_address(&mut account)?;
+        tcprintln!(app.ps, ("Successfully logged in to "), [hl: "{}", email_addr], ("."));
+
+        // We might need to add this account to the database. To have sensible
+        // foreign key relations, the email address is not the primary key of
+        // the accounts table, so we need to see whether there's already an
+        // existing row for this account (which could happen if the user
+        // re-logs-in, etc.) If we add a new row, we have to do this awkward
+        // bit where we insert and then immediately query for the row we just
+        // added (cf https://github.com/diesel-rs/diesel/issues/771 ).
+        {
+            use diesel::prelude::*;
+            use schema::accounts::dsl::*;
+
+            let maybe_row = accounts
+                .filter(email.eq(&email_addr))
+                .first::<database::Account>(&app.conn)
+                .optional()?;
+
+            let row_id = if let Some(row) = maybe_row {
+                row.id
+            } else {
+                let new_account = database::NewAccount::new(&email_addr);
+                diesel::replace_into(accounts)
+                    .values(&new_account)
+                    .execute(&app.conn)?;
+
+                let row = accounts
+                    .filter(email.eq(&email_addr))
+                    .first::<database::Account>(&app.conn)?;
+                row.id
+            };
+
+            account.data.db_id = row_id;
+            // JSON will be rewritten in acquire_change_page_token below.
+        }
+
+        // Initialize our token for checking for changes to the documents. We
+        // do this *before* scanning the complete listing; there's going to be
+        // a race condition either way, but the one that happens with this
+        // ordering seems like it would be more benign.
+        app.acquire_change_page_token(&mut account)?;
+
+        // OK, now actually slurp in the list of documents.
+        tcprintln!(app.ps, ("Scanning documents ..."));
+        app.import_documents(&mut account)?;
+
+        // All done.
+        tcprintln!(app.ps, ("Done."));
+        Ok(0)
+    }
+}
+
+/// List the files in a folder.
+///
+/// TODO: this name is going to be super confusing compared to `list`.
+#[derive(Debug, StructOpt)]
+pub struct DrorgLsOptions {
+    #[structopt(help = "A folder specifier (name, ID, ...)")]
+    spec: String,
+}
+
+impl DrorgLsOptions {
+    fn cli(self, app: &mut Application) -> Result<i32> {
+        use std::collections::HashSet;
+
+        app.maybe_sync_all_accounts()?;
+
+        let doc = app.get_docs().process_one(self.spec)?;
+
+        // We *could* just proceed and see if there's anything that Drive
+        // thinks is a child of this doc, but it seems like the more sensible
+        // UX is to make this a hard failure. You could imagine adding a CLI
+        // option to override this behavior.
+
+        if !doc.is_folder() {
+            return Err(format_err!("the selected document is not a folder"));
+        }
+
+        // This is another operation which can be surprising when you think
+        // about the behavior when a doc belongs to more than one account. We
+        // find children for each account separately and merge the results.
+
+        let accounts = doc.accounts(app)?;
+        let mut child_ids = HashSet::new();
+
+        if accounts.len() > 1 {
+            tcreport!(app.ps, warning: "folder belongs to multiple accounts; \
+                                        their listings will be merged");
+        }
+
+        for acct in &accounts {
+            let table = app.load_linkage_table(acct.id, false)?;
+            let node = match table.nodes.get(&doc.id) {
+                Some(n) => *n,
+                None => continue,
+            };
+
+            for child_idx in table.graph.neighbors(node) {
+                child_ids.insert(table.graph[child_idx].clone());
+            }
+        }
+
+        // Is this the best ordering?
+
+        let mut docs = app.ids_to_docs(&child_ids);
+        docs.sort_by_key(|d| d.utc_mod_time());
+        docs.reverse();
+        app.print_doc_list(docs)?;
+
+        app.set_cwd(&doc)?;
+
+        Ok(0)
+    }
+}
+
+/// Keep a local directory in sync with a Drive folder.
+///
+/// Files are transferred in whichever direction they have changed since the
+/// last run; see the `mirror` module for the details. The format chosen for
+/// exporting native documents is remembered for later runs.
+#[derive(Debug, StructOpt)]
+pub struct DrorgMirrorOptions {
+    #[structopt(help = "A folder specifier (name, ID, ...)")]
+    spec: String,
+
+    #[structopt(
+        help = "The local directory to mirror the folder into",
+        parse(from_os_str)
+    )]
+    local_dir: PathBuf,
+
+    #[structopt(
+        long = "format",
+        help = "The format to export native Google documents in (pdf, docx, odt, ...)"
+    )]
+    format: Option<String>,
+
+    #[structopt(short = "n", long = "dry-run", help = "Only report what would be done")]
+    dry_run: bool,
+}
+
+impl DrorgMirrorOptions {
+    fn cli(self, app: &mut Application) -> Result<i32> {
+        // The mirror needs an up-to-date picture of the folder, which the
+        // change feed gives us cheaply.
+        if app.options.sync == app::SyncOption::Auto {
+            app.options.sync = app::SyncOption::Yes;
+        }
+
+        app.maybe_sync_all_accounts()?;
+
+        let folder = app.get_docs().strict(true).process_one(&self.spec)?;
+
+        let acct =
+            folder.accounts(app)?.into_iter().next().ok_or_else(|| {
+                format_err!("\"{}\" is not associated with any account", folder.name)
+            })?;
+
+        let options = mirror::MirrorOptions {
+            export_format: self.format.as_deref(),
+            dry_run: self.dry_run,
+        };
+
+        let summary =
+            mirror::Mirrorer::new(app, acct, &folder, &self.local_dir, &options)?.run()?;
+
+        tcreport!(app.ps, info: "{}{} downloaded, {} uploaded, {} conflicts, {} deleted locally, \
+                                 {} trashed, {} removed from the folder",
+                  if self.dry_run { "(dry run) " } else { "" },
+                  summary.downloaded, summary.uploaded, summary.conflicts, summary.deleted,
+                  summary.trashed, summary.unlinked);
+        Ok(0)
+    }
+}
+
+/// Create a folder.
+#[derive(Debug, StructOpt)]
+pub struct DrorgMkdirOptions {
+    #[structopt(help = "The name of the new folder")]
+    name: String,
+
+    #[structopt(
+        long = "parent",
+        help = "A specifier for the folder in which to create the new one (default: the virtual CWD)"
+    )]
+    parent: Option<String>,
+}
+
+impl DrorgMkdirOptions {
+    fn cli(self, app: &mut Application) -> Result<i32> {
+        app.maybe_sync_all_accounts()?;
+
+        let parent_spec = self.parent.as_ref().map_or(".", |s| s.as_str());
+        let parent = app.get_docs().strict(true).process_one(parent_spec)?;
+
+        if !parent.is_folder() {
+            return Err(format_err!("the selected parent is not a folder"));
+        }
+
+        let accounts = parent.accounts(app)?;
+        let acct = accounts
+            .first()
+            .ok_or_else(|| format_err!("\"{}\" is not associated with any account", parent.name))?;
+
+        if accounts.len() > 1 {
+            tcreport!(app.ps, warning: "parent folder belongs to multiple accounts; \
+                                        creating the new folder through {}", acct.email);
+        }
+
+        let metadata = google_drive3::File {
+            name: Some(self.name),
+            mime_type: Some(database::FOLDER_MIME_TYPE.to_owned()),
+            parents: Some(vec![parent.id.clone()]),
+            ..Default::default()
+        };
+
+        let doc = app.create_doc(acct, metadata)?;
+        app.print_doc_list(vec![doc])?;
+        Ok(0)
+    }
+}
+
+/// Move documents into a folder.
+#[derive(Debug, StructOpt)]
+pub struct DrorgMvOptions {
+    #[structopt(
+        help = "Specifiers of the documents to move (name, ID, ...)",
+        required = true
+    )]
+    specs: Vec<String>,
+
+    #[structopt(help = "A folder specifier for the destination")]
+    dest: String,
+}
+
+impl DrorgMvOptions {
+    fn cli(self, app: &mut Application) -> Result<i32> {
+        use std::collections::hash_map::Entry;
+        use std::collections::HashMap;
+
+        app.maybe_sync_all_accounts()?;
+
+        let dest = app.get_docs().strict(true).process_one(&self.dest)?;
+
+        // Same UX reasoning as in `ls`.
+        if !dest.is_folder() {
+            return Err(format_err!("the destination is not a folder"));
+        }
+
+        // Resolve everything before touching anything, so that a bad
+        // specifier doesn't leave us with a half-completed move.
+
+        let mut to_move = Vec::new();
+
+        for spec in &self.specs {
+            to_move.push(app.get_docs().strict(true).process_one(spec)?);
+        }
+
+        // A document can only be moved using an account that can see both it
+        // and the destination. If there's more than one such account, we just
+        // take the first one.
+
+        let dest_accounts = dest.accounts(app)?;
+        let mut linkages = HashMap::new();
+        let mut plan = Vec::new();
+
+        for doc in to_move {
+            if doc.id == dest.id {
+                return Err(format_err!("cannot move \"{}\" into itself", doc.name));
+            }
+
+            let acct = doc
+                .accounts(app)?
+                .into_iter()
+                .find(|a| dest_accounts.contains(a))
+                .ok_or_else(|| {
+                    format_err!(
+                        "\"{}\" and the destination folder do not belong to a common account",
+                        doc.name
+                    )
+                })?;
+
+            // Moving a folder into one of its own subfolders would cut the
+            // whole subtree off from everything else.
+            if doc.is_folder() {
+                if let Entry::Vacant(e) = linkages.entry(acct.id) {
+                    e.insert(app.load_linkage_table(acct.id, false)?);
+                }
+
+                if linkages[&acct.id].is_inside(&dest.id, &doc.id) {
+                    return Err(format_err!(
+                        "cannot move \"{}\" into \"{}\", which is inside it",
+                        doc.name,
+                        dest.name
+                    ));
+                }
+            }
+
+            plan.push((doc, acct));
+        }
+
+        let mut moved = Vec::new();
+
+        for (doc, acct) in plan {
+            let old_parents = app.parent_ids(acct.id, &doc.id)?;
+            let remove: Vec<&str> = old_parents
+                .iter()
+                .map(|p| p.as_str())
+                .filter(|p| *p != dest.id)
+                .collect();
+
+            moved.push(app.update_doc(&acct, &doc.id, Default::default(), &[&dest.id], &remove)?);
+        }
+
+        app.print_doc_list(moved)?;
+        Ok(0)
+    }
+}
+
+/// Open a document.
+#[derive(Debug, StructOpt)]
+pub struct DrorgOpenOptions {
+    #[structopt(help = "A document specifier (name, ID, ...)")]
+    spec: String,
+}
+
+impl DrorgOpenOptions {
+    fn cli(self, app: &mut Application) -> Result<i32> {
+        app.maybe_sync_all_accounts()?;
+
+        let doc = app.get_docs().process_one(self.spec)?;
+        open_url(doc.open_url())?;
+        Ok(0)
+    }
+}
+
+/// Upload local files into Drive.
+#[derive(Debug, StructOpt)]
+pub struct DrorgPutOptions {
+    #[structopt(help = "Local files to upload", required = true, parse(from_os_str))]
+    paths: Vec<PathBuf>,
+
+    #[structopt(
+        long = "to",
+        help = "A specifier for the folder to upload into (default: the virtual CWD)"
+    )]
+    to: Option<String>,
+
+    #[structopt(
+        long = "convert",
+        help = "Convert the files to native Google formats where possible"
+    )]
+    convert: bool,
+}
+
+impl DrorgPutOptions {
+    fn cli(self, app: &mut Application) -> Result<i32> {
+        use std::fs;
+
+        app.maybe_sync_all_accounts()?;
+
+        let folder_spec = self.to.as_deref().unwrap_or(".");
+        let folder = app.get_docs().strict(true).process_one(folder_spec)?;
+
+        if !folder.is_folder() {
+            return Err(format_err!("the destination is not a folder"));
+        }
+
+        let accounts = folder.accounts(app)?;
+        let acct = accounts
+            .first()
+            .ok_or_else(|| format_err!("\"{}\" is not associated with any account", folder.name))?;
+
+        if accounts.len() > 1 {
+            tcreport!(app.ps, warning: "destination folder belongs to multiple accounts; \
+                                        uploading through {}", acct.email);
+        }
+
+        // The "about" info tells us which conversions are possible.
+
+        let about = if self.convert {
+            let mut account = accounts::Account::load(&acct.email)?;
+            Some(app.with_backend(&mut account, |backend| backend.about())?)
+        } else {
+            None
+        };
+
+        let mut uploaded = Vec::new();
+
+        for path in &self.paths {
+            if path.is_dir() {
+                return Err(format_err!(
+                    "\"{}\" is a directory; uploading directories is not supported",
+                    path.display()
+                ));
+            }
+
+            let mut name = path
+                .file_name()
+                .ok_or_else(|| format_err!("cannot determine the name of \"{}\"", path.display()))?
+                .to_string_lossy()
+                .into_owned();
+
+            let content_mime_type = content::mime_for_path(path);
+
+            let target_mime_type = match about.as_ref() {
+                None => None,
+                Some(about) => {
+                    let target = content::choose_import_format(about, content_mime_type);
+
+                    if target.is_none() {
+                        tcreport!(app.ps, warning: "\"{}\" cannot be converted; \
+                                                    uploading it as-is", path.display());
+                    } else if let Some(stem) = path.file_stem() {
+                        // This is what the Drive web UI does.
+                        name = stem.to_string_lossy().into_owned();
+                    }
+
+                    target
+                }
+            };
+
+            let metadata = google_drive3::File {
+                name: Some(name),
+                mime_type: target_mime_type,
+                parents: Some(vec![folder.id.clone()]),
+                ..Default::default()
+            };
+
+            let mut file = fs::File::open(path)?;
+            uploaded.push(app.upload_doc(acct, metadata, &mut file, content_mime_type)?);
+        }
+
+        app.print_doc_list(uploaded)?;
+        Ok(0)
+    }
+}
+
+/// List recently-used documents.
+#[derive(Debug, StructOpt)]
+pub struct DrorgRecentOptions {
+    #[structopt(
+        short = "n",
+        help = "Limit output to this many documents",
+        default_value = "10"
+    )]
+    limit: i64,
+
+    #[structopt(help = "Only list documents matching this query (e.g. \"owner:me\")")]
+    query: Option<String>,
+}
+
+impl DrorgRecentOptions {
+    fn cli(self, app: &mut Application) -> Result<i32> {
+        use schema::docs::dsl::*;
+
+        app.maybe_sync_all_accounts()?;
+
+        let mut q = docs.into_boxed();
+
+        if let Some(spec) = self.query.as_ref() {
+            let expr = query::parse(spec)?;
+            q = q.filter(query::compile(&expr, app)?);
+        }
+
+        let listing = q
+            .order(modified_time.desc())
+            .limit(self.limit)
+            .load::<database::Doc>(&app.conn)?;
+
+        app.print_doc_list(listing)?;
+        Ok(0)
+    }
+}
+
+/// Rename a document.
+#[derive(Debug, StructOpt)]
+pub struct DrorgRenameOptions {
+    #[structopt(help = "A document specifier (name, ID, ...)")]
+    spec: String,
+
+    #[structopt(help = "The new name of the document")]
+    new_name: String,
+}
+
+impl DrorgRenameOptions {
+    fn cli(self, app: &mut Application) -> Result<i32> {
+        app.maybe_sync_all_accounts()?;
+
+        // If the spec is ambiguous, this prints the usual disambiguation
+        // listing and bails. We never guess, even if fuzzy matching is on.
+        let doc = app.get_docs().strict(true).process_one(&self.spec)?;
+
+        let accounts = doc.accounts(app)?;
+        let acct = accounts
+            .first()
+            .ok_or_else(|| format_err!("\"{}\" is not associated with any account", doc.name))?;
+
+        let patch = google_drive3::File {
+            name: Some(self.new_name),
+            ..Default::default()
+        };
+
+        let doc = app.update_doc(acct, &doc.id, patch, &[], &[])?;
+        app.print_doc_list(vec![doc])?;
+        Ok(0)
+    }
+}
+
+/// List the stored revisions of a document, or mark them to be kept.
+///
+/// Drive automatically purges old revisions of files with binary content
+/// after a while, unless they are marked to be kept forever; `--pin` and
+/// `--unpin` change that marking. Revisions can be named by their IDs or by
+/// unambiguous prefixes of them.
+#[derive(Debug, StructOpt)]
+pub struct DrorgRevisionsOptions {
+    #[structopt(subcommand)]
+    command: Option<DrorgRevisionsSubcommand>,
+
+    #[structopt(help = "A document specifier (name, ID, ...)")]
+    spec: Option<String>,
+
+    #[structopt(
+        long = "pin",
+        help = "Mark this revision to be kept forever",
+        number_of_values = 1
+    )]
+    pin: Vec<String>,
+
+    #[structopt(
+        long = "unpin",
+        help = "Allow this revision to be purged automatically",
+        number_of_values = 1
+    )]
+    unpin: Vec<String>,
+}
+
+/// The operations on revisions other than listing them.
+#[derive(Debug, StructOpt)]
+pub enum DrorgRevisionsSubcommand {
+    #[structopt(name = "get")]
+    /// Download a specific revision of a document to a local file
+    Get(DrorgRevisionsGetOptions),
+}
+
+/// The fields of revisions that we ask for.
+const REVISION_FIELDS: &str = "id,keepForever,lastModifyingUser(displayName,emailAddress),\
+                               mimeType,modifiedTime,originalFilename,size";
+
+impl DrorgRevisionsOptions {
+    fn cli(self, app: &mut Application) -> Result<i32> {
+        if let Some(DrorgRevisionsSubcommand::Get(opts)) = self.command {
+            return opts.cli(app);
+        }
+
+        let spec = self
+            .spec
+            .ok_or_else(|| format_err!("a document specifier must be given"))?;
+
+        app.maybe_sync_all_accounts()?;
+
+        let (doc, mut account) = doc_and_account(app, &spec, false)?;
+
+        if doc.is_folder() {
+            return Err(format_err!("folders do not have revisions"));
+        }
+
+        let changes: Vec<_> = self
+            .pin
+            .iter()
+            .map(|r| (r, true))
+            .chain(self.unpin.iter().map(|r| (r, false)))
+            .collect();
+
+        let revisions = app.with_backend(&mut account, |backend| {
+            let mut revisions = backend
+                .list_revisions(
+                    &doc.id,
+                    &format!("nextPageToken,revisions({})", REVISION_FIELDS),
+                )
+                .collect::<Result<Vec<_>>>()?;
+
+            // Resolve all of the revisions before changing any of them, so
+            // that a typo doesn't leave things half-done.
+
+            let mut resolved = Vec::new();
+
+            for (rev_spec, keep) in &changes {
+                let rev_id = find_by_id(&revisions, rev_spec, "revision", |r| r.id.as_deref())?
+                    .id
+                    .clone()
+                    .unwrap_or_default();
+                resolved.push((rev_id, *keep));
+            }
+
+            for (rev_id, keep) in resolved {
+                let patch = google_drive3::Revision {
+                    keep_forever: Some(keep),
+                    ..Default::default()
+                };
+
+                let updated = backend.update_revision(&doc.id, &rev_id, patch, REVISION_FIELDS)?;
+
+                for rev in revisions.iter_mut() {
+                    if rev.id.as_deref() == Some(&rev_id) {
+                        rev.keep_forever = updated.keep_forever.or(Some(keep));
+                    }
+                }
+            }
+
+            Ok(revisions)
+        })?;
+
+        let id_width = revisions
+            .iter()
+            .map(|r| r.id.as_ref().map_or(0, |i| i.len()))
+            .max()
+            .unwrap_or(0);
+
+        for rev in &revisions {
+            let id = format!("{:<1$}", rev.id.as_deref().unwrap_or("?"), id_width);
+
+            let time = api_time_to_local(rev.modified_time.as_ref());
+
+            let size = rev
+                .size
+                .as_ref()
+                .and_then(|s| s.parse::<f64>().ok())
+                .map_or_else(|| "-".to_owned(), human_bytes);
+
+            let author = rev.last_modifying_user.as_ref().map_or_else(
+                || "[unknown]".to_owned(),
+                |u| match (u.display_name.as_ref(), u.email_address.as_ref()) {
+                    (Some(n), Some(e)) => format!("{} <{}>", n, e),
+                    (Some(n), None) => n.clone(),
+                    (None, Some(e)) => e.clone(),
+                    (None, None) => "[unknown]".to_owned(),
+                },
+            );
+
+            let keep = if rev.keep_forever == Some(true) {
+                "  (kept forever)"
+            } else {
+                ""
+            };
+
+            tcprintln!(app.ps,
+                       [hl: "{}", id],
+                       ("  {}  {:>10}  {}", time, size, author),
+                       [yellow: "{}", keep]
+            );
+        }
+
+        Ok(0)
+    }
+}
+
+/// Download a specific revision of a document to a local file.
+#[derive(Debug, StructOpt)]
+pub struct DrorgRevisionsGetOptions {
+    #[structopt(help = "A document specifier (name, ID, ...)")]
+    spec: String,
+
+    #[structopt(help = "The ID of the revision, or an unambiguous prefix of it")]
+    rev: String,
+
+    #[structopt(
+        short = "o",
+        long = "output",
+        help = "The path of the output file (default: based on the document name)",
+        parse(from_os_str)
+    )]
+    output: Option<PathBuf>,
+}
+
+impl DrorgRevisionsGetOptions {
+    fn cli(self, app: &mut Application) -> Result<i32> {
+        app.maybe_sync_all_accounts()?;
+
+        let (doc, mut account) = doc_and_account(app, &self.spec, false)?;
+
+        // Drive doesn't store the content of old revisions of native docs in
+        // a way that we can download.
 
-        let mut docs = app.ids_to_docs(&child_ids);
-        docs.sort_by_key(|d| d.utc_mod_time());
-        docs.reverse();
-        app.print_doc_list(docs)?;
+        if doc.is_folder() {
+            return Err(format_err!("folders do not have revisions"));
+        }
 
-        app.set_cwd(&doc)?;
+        if doc.is_native() {
+            return Err(format_err!(
+                "old revisions of native Google documents cannot be downloaded"
+            ));
+        }
 
+        let rev = app.with_backend(&mut account, |backend| {
+            let revisions = backend
+                .list_revisions(
+                    &doc.id,
+                    &format!("nextPageToken,revisions({})", REVISION_FIELDS),
+                )
+                .collect::<Result<Vec<_>>>()?;
+            Ok(find_by_id(&revisions, &self.rev, "revision", |r| r.id.as_deref())?.clone())
+        })?;
+
+        let rev_id = rev.id.clone().unwrap_or_default();
+        let path = self.output.unwrap_or_else(|| {
+            content::local_filename(rev.original_filename.as_deref().unwrap_or(&doc.name), None)
+        });
+
+        content::write_atomically(&path, |dest| {
+            app.with_backend(&mut account, |backend| {
+                backend.download_revision(&doc.id, &rev_id, dest)
+            })?;
+            Ok(())
+        })?;
+
+        tcreport!(app.ps, info: "wrote revision {} of \"{}\" to \"{}\"", rev_id, doc.name, path.display());
         Ok(0)
     }
 }
 
-/// Open a document.
+/// Search for documents by name and folder path.
 #[derive(Debug, StructOpt)]
-pub struct DrorgOpenOptions {
-    #[structopt(help = "A document specifier (name, ID, ...)")]
-    spec: String,
+pub struct DrorgSearchOptions {
+    #[structopt(help = "Words to search for", required = true)]
+    terms: Vec<String>,
 }
 
-impl DrorgOpenOptions {
+impl DrorgSearchOptions {
     fn cli(self, app: &mut Application) -> Result<i32> {
         app.maybe_sync_all_accounts()?;
+        app.ensure_search_index()?;
 
-        let doc = app.get_docs().process_one(self.spec)?;
-        open_url(doc.open_url())?;
+        let results = app.search_docs(&self.terms)?;
+
+        if results.is_empty() {
+            return Err(format_err!(
+                "no documents matched the search \"{}\"",
+                self.terms.join(" ")
+            ));
+        }
+
+        app.print_doc_list(results)?;
         Ok(0)
     }
 }
 
-/// List recently-used documents.
+/// Show or change who a document is shared with.
 #[derive(Debug, StructOpt)]
-pub struct DrorgRecentOptions {
+pub struct DrorgShareOptions {
+    #[structopt(help = "A document specifier (name, ID, ...)")]
+    spec: String,
+
     #[structopt(
-        short = "n",
-        help = "Limit output to this many documents",
-        default_value = "10"
+        long = "add",
+        help = "Share the document with this email address or domain",
+        number_of_values = 1
     )]
-    limit: i64,
+    add: Vec<String>,
+
+    #[structopt(
+        long = "type",
+        help = "The kind of grantee being added (default: \"user\" for email addresses, \
+                \"domain\" otherwise)",
+        raw(possible_values = r#"&["user", "group", "domain"]"#)
+    )]
+    grantee_type: Option<String>,
+
+    #[structopt(
+        long = "role",
+        help = "The role to grant to added grantees",
+        default_value = "reader",
+        raw(
+            possible_values = r#"&["reader", "commenter", "writer", "fileOrganizer", "organizer"]"#
+        )
+    )]
+    role: String,
+
+    #[structopt(
+        long = "notify",
+        help = "Send added grantees an email about the document"
+    )]
+    notify: bool,
+
+    #[structopt(
+        long = "remove",
+        help = "Stop sharing the document with this email address, domain, or permission ID",
+        number_of_values = 1
+    )]
+    remove: Vec<String>,
+
+    #[structopt(
+        long = "link",
+        help = "Set the access of anyone with the link",
+        raw(possible_values = r#"&["off", "reader", "commenter", "writer"]"#)
+    )]
+    link: Option<String>,
 }
 
-impl DrorgRecentOptions {
+impl DrorgShareOptions {
     fn cli(self, app: &mut Application) -> Result<i32> {
-        use schema::docs::dsl::*;
-
         app.maybe_sync_all_accounts()?;
 
-        let listing = docs
-            .order(modified_time.desc())
-            .limit(self.limit)
-            .load::<database::Doc>(&app.conn)?;
+        // Just showing the sharing settings is harmless, so we can guess at
+        // the document then, but not when changing them.
+        let changing = !self.add.is_empty() || !self.remove.is_empty() || self.link.is_some();
+        let doc = app.get_docs().strict(changing).process_one(&self.spec)?;
+
+        let acct =
+            doc.accounts(app)?.into_iter().next().ok_or_else(|| {
+                format_err!("\"{}\" is not associated with any account", doc.name)
+            })?;
+        let mut account = accounts::Account::load(&acct.email)?;
+
+        let perms = app.with_backend(&mut account, |backend| {
+            let mut perms = backend.list_permissions(&doc.id)?;
+
+            // Removals first, so that `--link` can replace an existing link
+            // permission with one having a different role.
+
+            let mut doomed = HashSet::new();
+
+            for who in &self.remove {
+                let matches: Vec<_> = perms
+                    .iter()
+                    .filter(|p| {
+                        p.id.as_deref() == Some(who.as_str())
+                            || p.email_address
+                                .as_ref()
+                                .is_some_and(|e| e.eq_ignore_ascii_case(who))
+                            || (p.type_.as_deref() == Some("domain")
+                                && p.domain
+                                    .as_ref()
+                                    .is_some_and(|d| d.eq_ignore_ascii_case(who)))
+                    })
+                    .filter_map(|p| p.id.clone())
+                    .collect();
+
+                if matches.is_empty() {
+                    return Err(format_err!(
+                        "\"{}\" is not shared with \"{}\"",
+                        doc.name,
+                        who
+                    ));
+                }
+
+                doomed.extend(matches);
+            }
+
+            if self.link.is_some() {
+                doomed.extend(
+                    perms
+                        .iter()
+                        .filter(|p| p.type_.as_deref() == Some("anyone"))
+                        .filter_map(|p| p.id.clone()),
+                );
+            }
+
+            for perm_id in &doomed {
+                backend.delete_permission(&doc.id, perm_id)?;
+            }
+
+            let mut changed = !doomed.is_empty();
+
+            for who in &self.add {
+                let grantee_type = match self.grantee_type.as_ref() {
+                    Some(t) => t.clone(),
+                    None if who.contains('@') => "user".to_owned(),
+                    None => "domain".to_owned(),
+                };
+
+                let mut perm = google_drive3::Permission {
+                    type_: Some(grantee_type),
+                    role: Some(self.role.clone()),
+                    ..Default::default()
+                };
+
+                if perm.type_.as_deref() == Some("domain") {
+                    perm.domain = Some(who.clone());
+                } else {
+                    perm.email_address = Some(who.clone());
+                }
+
+                backend.create_permission(&doc.id, perm, self.notify)?;
+                changed = true;
+            }
+
+            match self.link.as_deref() {
+                None | Some("off") => {}
+
+                Some(role) => {
+                    let perm = google_drive3::Permission {
+                        type_: Some("anyone".to_owned()),
+                        role: Some(role.to_owned()),
+                        allow_file_discovery: Some(false),
+                        ..Default::default()
+                    };
+
+                    backend.create_permission(&doc.id, perm, false)?;
+                    changed = true;
+                }
+            }
+
+            if changed {
+                perms = backend.list_permissions(&doc.id)?;
+            }
+
+            Ok(perms)
+        })?;
+
+        app.store_permissions(&doc.id, &perms)?;
+
+        let perms = {
+            use schema::permissions::dsl::*;
+            permissions
+                .filter(doc_id.eq(&doc.id))
+                .load::<database::Permission>(&app.conn)?
+        };
+
+        tcprintln!(app.ps, [hl: "{}", doc.name], (" is shared with:"));
+
+        if perms.is_empty() {
+            tcprintln!(app.ps, ("    nobody (or you cannot see its permissions)"));
+        }
+
+        for perm in perms {
+            let kind = format!(" {:<7} ", perm.grantee_type);
+
+            // Link sharing is the thing most worth noticing.
+            match perm.grantee_type.as_str() {
+                "anyone" => tcprintln!(app.ps,
+                    ("    "), [yellow: "{:<10}", perm.role], ("{}{}", kind, perm.grantee())
+                ),
+                _ => tcprintln!(app.ps,
+                    ("    "), [green: "{:<10}", perm.role], ("{}{}", kind, perm.grantee())
+                ),
+            }
+        }
 
-        app.print_doc_list(listing)?;
         Ok(0)
     }
 }
@@ -399,13 +2642,240 @@ impl DrorgSyncOptions {
 
                 // Redo the initialization rigamarole from the "login" command.
                 tcprintln!(app.ps, ("Rebuilding "), [hl: "{}", email], (" ..."));
-                account.acquire_change_page_token(&app.secret)?;
+                app.acquire_change_page_token(&mut account)?;
                 app.import_documents(&mut account)?;
             }
+
+            app.rebuild_search_index()?;
+        }
+
+        Ok(0)
+    }
+}
+
+/// Print a folder hierarchy as a tree.
+#[derive(Debug, StructOpt)]
+pub struct DrorgTreeOptions {
+    #[structopt(help = "A folder specifier (name, ID, ...)", default_value = ".")]
+    spec: String,
+
+    #[structopt(long = "depth", help = "The maximum number of levels to descend")]
+    depth: Option<usize>,
+
+    #[structopt(short = "d", long = "dirs-only", help = "Only show folders")]
+    dirs_only: bool,
+
+    #[structopt(short = "s", long = "sizes", help = "Show the sizes of files")]
+    sizes: bool,
+}
+
+impl DrorgTreeOptions {
+    fn cli(self, app: &mut Application) -> Result<i32> {
+        app.maybe_sync_all_accounts()?;
+
+        let doc = app.get_docs().process_one(&self.spec)?;
+
+        if !doc.is_folder() {
+            return Err(format_err!("the selected document is not a folder"));
+        }
+
+        let walker = self.walk(app, &doc)?;
+
+        tcprintln!(app.ps, [folder: "{}", doc.name]);
+
+        for line in &walker.lines {
+            let child = &line.doc;
+
+            tcprintln!(app.ps,
+                       ("{}", line.lead),
+                       {colors, {
+                           if child.trashed {
+                               &colors.red
+                           } else if child.starred {
+                               &colors.yellow
+                           } else if child.is_folder() {
+                               &colors.folder
+                           } else {
+                               &colors.plain
+                           }
+                       }: "{}", child.name},
+                       ("{}", line.notes)
+            );
+        }
+
+        tcprintln!(app.ps, (""));
+
+        if self.dirs_only {
+            tcprintln!(app.ps, ("{} folders", walker.n_folders));
+        } else {
+            tcprintln!(
+                app.ps,
+                ("{} folders, {} files", walker.n_folders, walker.n_files)
+            );
         }
 
         Ok(0)
     }
+
+    /// Gather the lines of the tree below a folder.
+    fn walk(&self, app: &mut Application, doc: &database::Doc) -> Result<TreeWalker<'_>> {
+        // As with `ls`, if the folder belongs to more than one account, we
+        // merge their views of the hierarchy.
+
+        let mut tables = Vec::new();
+
+        for acct in doc.accounts(app)? {
+            tables.push(app.load_linkage_table(acct.id, false)?);
+        }
+
+        let mut walker = TreeWalker {
+            opts: self,
+            tables,
+            ancestors: vec![doc.id.clone()],
+            expanded: HashSet::new(),
+            lines: Vec::new(),
+            n_folders: 0,
+            n_files: 0,
+        };
+
+        walker.expanded.insert(doc.id.clone());
+        walker.walk(app, &doc.id, "", 1)?;
+        Ok(walker)
+    }
+}
+
+/// One line of the output of `drorg tree`.
+struct TreeLine {
+    /// The tree-drawing characters that precede the name.
+    lead: String,
+
+    doc: database::Doc,
+
+    /// Annotations to print after the name, if any.
+    notes: String,
+}
+
+/// State for the recursive traversal done by `drorg tree`.
+struct TreeWalker<'a> {
+    opts: &'a DrorgTreeOptions,
+
+    /// The linkage tables of the accounts that we're looking at.
+    tables: Vec<app::LinkageTable>,
+
+    /// The IDs of the folders leading to the current one, for cycle
+    /// detection.
+    ancestors: Vec<String>,
+
+    /// The IDs of the folders whose contents have already been printed.
+    /// Folders with multiple parents are only expanded once.
+    expanded: HashSet<String>,
+
+    /// The lines of the tree, in order.
+    lines: Vec<TreeLine>,
+
+    n_folders: usize,
+    n_files: usize,
+}
+
+impl<'a> TreeWalker<'a> {
+    fn walk(
+        &mut self,
+        app: &mut Application,
+        folder_id: &str,
+        prefix: &str,
+        depth: usize,
+    ) -> Result<()> {
+        let mut child_ids = HashSet::new();
+
+        for table in &self.tables {
+            child_ids.extend(table.children(folder_id));
+        }
+
+        let mut children = {
+            use schema::docs::dsl::*;
+            docs.filter(id.eq_any(child_ids))
+                .load::<database::Doc>(&app.conn)?
+        };
+
+        if self.opts.dirs_only {
+            children.retain(|d| d.is_folder());
+        }
+
+        // Folders first, then alphabetical.
+        children.sort_by_key(|d| (!d.is_folder(), d.name.to_lowercase()));
+
+        let n = children.len();
+
+        for (i, child) in children.iter().enumerate() {
+            let is_last = i + 1 == n;
+            let connector = if is_last { "└── " } else { "├── " };
+
+            if child.is_folder() {
+                self.n_folders += 1;
+            } else {
+                self.n_files += 1;
+            }
+
+            let mut notes = Vec::new();
+
+            if self.opts.sizes && !child.is_folder() {
+                if let Some(s) = child.human_size() {
+                    notes.push(s);
+                }
+            }
+
+            let n_parents = {
+                let mut parents = HashSet::new();
+
+                for table in &self.tables {
+                    parents.extend(table.parents(&child.id));
+                }
+
+                parents.len()
+            };
+
+            if n_parents > 1 {
+                notes.push(format!("{} parents", n_parents));
+            }
+
+            // Cycles are possible in Drive. Folders with multiple parents
+            // might appear several times in the tree, but we only show their
+            // contents once.
+
+            let mut descend =
+                child.is_folder() && self.opts.depth.map(|d| depth < d).unwrap_or(true);
+
+            if child.is_folder() && self.ancestors.contains(&child.id) {
+                notes.push("cycle!".to_owned());
+                descend = false;
+            } else if child.is_folder() && self.expanded.contains(&child.id) {
+                notes.push("contents shown above".to_owned());
+                descend = false;
+            }
+
+            let notes = if notes.is_empty() {
+                String::new()
+            } else {
+                format!("  [{}]", notes.join("; "))
+            };
+
+            self.lines.push(TreeLine {
+                lead: format!("{}{}", prefix, connector),
+                doc: child.clone(),
+                notes,
+            });
+
+            if descend {
+                let sub_prefix = format!("{}{}", prefix, if is_last { "    " } else { "│   " });
+                self.expanded.insert(child.id.clone());
+                self.ancestors.push(child.id.clone());
+                self.walk(app, &child.id, &sub_prefix, depth + 1)?;
+                self.ancestors.pop();
+            }
+        }
+
+        Ok(())
+    }
 }
 
 /// Print the URL of a document.
@@ -428,6 +2898,38 @@ impl DrorgUrlOptions {
 /// The main StructOpt type for dispatching subcommands.
 #[derive(Debug, StructOpt)]
 pub enum DrorgSubcommand {
+    #[structopt(name = "audit")]
+    /// Audit documents for policy problems
+    Audit(DrorgAuditOptions),
+
+    #[structopt(name = "backup")]
+    /// Back up an entire account to a directory or tarball
+    Backup(DrorgBackupOptions),
+
+    #[structopt(name = "cat")]
+    /// Print the content of a document, converting Google Docs to Markdown
+    Cat(DrorgCatOptions),
+
+    #[structopt(name = "comments")]
+    /// Show, reply to, or resolve the comments on a document
+    Comments(DrorgCommentsOptions),
+
+    #[structopt(name = "drives")]
+    /// List shared drives
+    Drives(DrorgDrivesOptions),
+
+    #[structopt(name = "du")]
+    /// Summarize the storage used within a folder
+    Du(DrorgDuOptions),
+
+    #[structopt(name = "dupes")]
+    /// Find documents that appear to be duplicates
+    Dupes(DrorgDupesOptions),
+
+    #[structopt(name = "get")]
+    /// Download a document to a local file
+    Get(DrorgGetOptions),
+
     #[structopt(name = "info")]
     /// Show detailed information about one or more documents
     Info(DrorgInfoOptions),
@@ -436,6 +2938,10 @@ pub enum DrorgSubcommand {
     /// List documents in a compact format (note: `ls` is different)
     List(DrorgListOptions),
 
+    #[structopt(name = "log")]
+    /// Show the changes that have been seen by synchronization
+    Log(DrorgLogOptions),
+
     #[structopt(name = "login")]
     /// Add a Google account to be monitored
     Login(DrorgLoginOptions),
@@ -444,18 +2950,70 @@ pub enum DrorgSubcommand {
     /// List files in a folder (note: `list` is different)
     Ls(DrorgLsOptions),
 
+    #[structopt(name = "mirror")]
+    /// Keep a local directory in sync with a folder
+    Mirror(DrorgMirrorOptions),
+
+    #[structopt(name = "mkdir")]
+    /// Create a folder
+    Mkdir(DrorgMkdirOptions),
+
+    #[structopt(name = "mv")]
+    /// Move documents into a folder
+    Mv(DrorgMvOptions),
+
     #[structopt(name = "open")]
     /// Open a document in a web browser
     Open(DrorgOpenOptions),
 
+    #[structopt(name = "put")]
+    /// Upload local files into Drive
+    Put(DrorgPutOptions),
+
     #[structopt(name = "recent")]
     /// List recently-used documents
     Recent(DrorgRecentOptions),
 
+    #[structopt(name = "rename")]
+    /// Rename a document
+    Rename(DrorgRenameOptions),
+
+    #[structopt(name = "revisions")]
+    /// List, download, or pin revisions of a document
+    Revisions(DrorgRevisionsOptions),
+
+    #[structopt(name = "search")]
+    /// Search for documents by name and folder path
+    Search(DrorgSearchOptions),
+
+    #[structopt(name = "share")]
+    /// Show or change who a document is shared with
+    Share(DrorgShareOptions),
+
+    #[structopt(name = "star")]
+    /// Star documents
+    Star(DrorgFlagOptions),
+
     #[structopt(name = "sync")]
     /// Synchronize with the cloud
     Sync(DrorgSyncOptions),
 
+    #[structopt(name = "trash")]
+    /// Move documents to the trash
+    Trash(DrorgFlagOptions),
+
+    #[structopt(name = "tree")]
+    /// Show the hierarchy of documents within a folder
+    Tree(DrorgTreeOptions),
+
+    #[structopt(name = "unstar")]
+    /// Un-star documents
+    Unstar(DrorgFlagOptions),
+
+    #[structopt(name = "untrash")]
+    /// Restore documents from the trash
+    Untrash(DrorgFlagOptions),
+
     #[structopt(name = "url")]
     /// Print the URL to open a document
     Url(DrorgUrlOptions),
@@ -480,13 +3038,59 @@ impl DrorgCli {
         };
 
         let result = match self.command {
+            DrorgSubcommand::Audit(opts) => opts.cli(&mut app),
+            DrorgSubcommand::Backup(opts) => opts.cli(&mut app),
+            DrorgSubcommand::Cat(opts) => opts.cli(&mut app),
+            DrorgSubcommand::Comments(opts) => opts.cli(&mut app),
+            DrorgSubcommand::Drives(opts) => opts.cli(&mut app),
+            DrorgSubcommand::Du(opts) => opts.cli(&mut app),
+            DrorgSubcommand::Dupes(opts) => opts.cli(&mut app),
+            DrorgSubcommand::Get(opts) => opts.cli(&mut app),
             DrorgSubcommand::Info(opts) => opts.cli(&mut app),
             DrorgSubcommand::List(opts) => opts.cli(&mut app),
+            DrorgSubcommand::Log(opts) => opts.cli(&mut app),
             DrorgSubcommand::Login(opts) => opts.cli(&mut app),
             DrorgSubcommand::Ls(opts) => opts.cli(&mut app),
+            DrorgSubcommand::Mirror(opts) => opts.cli(&mut app),
+            DrorgSubcommand::Mkdir(opts) => opts.cli(&mut app),
+            DrorgSubcommand::Mv(opts) => opts.cli(&mut app),
             DrorgSubcommand::Open(opts) => opts.cli(&mut app),
+            DrorgSubcommand::Put(opts) => opts.cli(&mut app),
             DrorgSubcommand::Recent(opts) => opts.cli(&mut app),
+            DrorgSubcommand::Rename(opts) => opts.cli(&mut app),
+            DrorgSubcommand::Revisions(opts) => opts.cli(&mut app),
+            DrorgSubcommand::Search(opts) => opts.cli(&mut app),
+            DrorgSubcommand::Share(opts) => opts.cli(&mut app),
+            DrorgSubcommand::Star(opts) => opts.cli(
+                &mut app,
+                google_drive3::File {
+                    starred: Some(true),
+                    ..Default::default()
+                },
+            ),
             DrorgSubcommand::Sync(opts) => opts.cli(&mut app),
+            DrorgSubcommand::Trash(opts) => opts.cli(
+                &mut app,
+                google_drive3::File {
+                    trashed: Some(true),
+                    ..Default::default()
+                },
+            ),
+            DrorgSubcommand::Tree(opts) => opts.cli(&mut app),
+            DrorgSubcommand::Unstar(opts) => opts.cli(
+                &mut app,
+                google_drive3::File {
+                    starred: Some(false),
+                    ..Default::default()
+                },
+            ),
+            DrorgSubcommand::Untrash(opts) => opts.cli(
+                &mut app,
+                google_drive3::File {
+                    trashed: Some(false),
+                    ..Default::default()
+                },
+            ),
             DrorgSubcommand::Url(opts) => opts.cli(&mut app),
         };
 
@@ -517,3 +3121,474 @@ fn main() {
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_support::fixture_app;
+
+    fn doc_name(app: &Application, the_id: &str) -> String {
+        use schema::docs::dsl::*;
+        docs.filter(id.eq(the_id))
+            .select(name)
+            .first(&app.conn)
+            .unwrap()
+    }
+
+    #[test]
+    fn rename_refuses_ambiguous_spec() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut app, _account) = fixture_app(dir.path(), "folders.json");
+        app.options.match_mode = Some(app::MatchMode::Fuzzy);
+
+        // Make one of the two reports a much likelier guess than the other.
+        {
+            use schema::docs::dsl::*;
+            diesel::update(docs.filter(id.eq("R2")))
+                .set((
+                    starred.eq(true),
+                    modified_time.eq(chrono::Utc::now().naive_utc()),
+                ))
+                .execute(&app.conn)
+                .unwrap();
+        }
+
+        let opts = DrorgRenameOptions {
+            spec: "Report".to_owned(),
+            new_name: "Old Report".to_owned(),
+        };
+        assert!(opts.cli(&mut app).is_err());
+        assert_eq!(doc_name(&app, "R1"), "Report");
+        assert_eq!(doc_name(&app, "R2"), "Report");
+
+        let opts = DrorgRenameOptions {
+            spec: "R1".to_owned(),
+            new_name: "Old Report".to_owned(),
+        };
+        assert_eq!(opts.cli(&mut app).unwrap(), 0);
+        assert_eq!(doc_name(&app, "R1"), "Old Report");
+        assert_eq!(doc_name(&app, "R2"), "Report");
+    }
+
+    fn audit_opts(allow: &[&str]) -> DrorgAuditSharingOptions {
+        DrorgAuditSharingOptions {
+            allow: allow.iter().map(|d| (*d).to_owned()).collect(),
+            trashed: false,
+        }
+    }
+
+    fn audit_reasons(app: &mut Application, opts: &DrorgAuditSharingOptions) -> Vec<String> {
+        let mut reasons: Vec<_> = opts
+            .audit(app)
+            .unwrap()
+            .into_iter()
+            .map(|r| format!("{} in {:?}: {} {}", r.id, r.folder, r.reason, r.grantee))
+            .collect();
+        reasons.sort();
+        reasons
+    }
+
+    #[test]
+    fn audit_sharing_violations() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut app, _account) = fixture_app(dir.path(), "account.json");
+        app.config.sharing.allowed_domains = vec!["example.com".to_owned()];
+
+        // The permissions of the shared drive document have to be fetched
+        // specially.
+        assert_eq!(app.fetch_drive_permissions(false).unwrap(), 0);
+
+        assert_eq!(
+            audit_reasons(&mut app, &audit_opts(&[])),
+            vec![
+                "D1 in \"My Drive > Projects\": anyoneWithLink anyone with the link",
+                "P1 in \"My Drive > Projects\": externalDomain bob@other.org",
+                "P1 in \"My Drive\": externalDomain bob@other.org",
+                "T1 in \"Team\": externalDomain carol@partner.net",
+                "T1 in \"Team\": unknown Former Member",
+            ]
+        );
+
+        assert_eq!(
+            audit_opts(&[]).cli(&mut app).unwrap(),
+            AUDIT_VIOLATIONS_EXIT_CODE
+        );
+    }
+
+    #[test]
+    fn audit_sharing_clean() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut app, account) = fixture_app(dir.path(), "account.json");
+        app.config.sharing.allowed_domains = vec!["example.com".to_owned()];
+
+        {
+            use schema::permissions::dsl::*;
+            diesel::delete(permissions.filter(grantee_type.eq("anyone")))
+                .execute(&app.conn)
+                .unwrap();
+        }
+
+        // Having a shared drive doesn't make the audit fail, and neither does
+        // a grantee that can't be identified.
+        let opts = audit_opts(&["other.org", "partner.net"]);
+        assert_eq!(opts.cli(&mut app).unwrap(), 0);
+        assert_eq!(
+            audit_reasons(&mut app, &audit_opts(&["other.org", "partner.net"])),
+            vec!["T1 in \"Team\": unknown Former Member"]
+        );
+
+        // But a shared drive document whose permissions can't be fetched
+        // does.
+        {
+            use schema::docs::dsl::*;
+            diesel::insert_into(docs)
+                .values((
+                    id.eq("T9"),
+                    name.eq("Gone"),
+                    mime_type.eq("text/plain"),
+                    modified_time.eq(chrono::Utc::now().naive_utc()),
+                    starred.eq(false),
+                    trashed.eq(false),
+                    drive_id.eq("TD1"),
+                ))
+                .execute(&app.conn)
+                .unwrap();
+        }
+        {
+            use schema::account_associations::dsl::*;
+            diesel::insert_into(account_associations)
+                .values((
+                    doc_id.eq("T9"),
+                    account_id.eq(account.data.db_id),
+                    owned_by_me.eq(false),
+                ))
+                .execute(&app.conn)
+                .unwrap();
+        }
+
+        let opts = audit_opts(&["other.org", "partner.net"]);
+        assert_eq!(opts.cli(&mut app).unwrap(), AUDIT_INCOMPLETE_EXIT_CODE);
+    }
+
+    fn dupe_candidate(
+        the_id: &str,
+        the_name: &str,
+        the_mime_type: &str,
+        checksum: Option<&str>,
+        the_size: Option<i32>,
+        day: u32,
+    ) -> database::Doc {
+        database::Doc {
+            id: the_id.to_owned(),
+            name: the_name.to_owned(),
+            mime_type: the_mime_type.to_owned(),
+            modified_time: chrono::NaiveDate::from_ymd(2020, 1, day).and_hms(0, 0, 0),
+            starred: false,
+            trashed: false,
+            size: the_size,
+            md5_checksum: checksum.map(|c| c.to_owned()),
+            drive_id: None,
+            owner_email: None,
+            owner_name: None,
+        }
+    }
+
+    fn group_ids(groups: &[Vec<database::Doc>]) -> Vec<Vec<&str>> {
+        groups
+            .iter()
+            .map(|g| g.iter().map(|d| d.id.as_str()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn dupe_groups() {
+        const DOC: &str = "application/vnd.google-apps.document";
+        const SHEET: &str = "application/vnd.google-apps.spreadsheet";
+
+        let (groups, n_unchecksummed) = group_dupes(vec![
+            // Same checksum, different names: copies. Newest first.
+            dupe_candidate(
+                "a1",
+                "paper.pdf",
+                "application/pdf",
+                Some("abc"),
+                Some(10),
+                1,
+            ),
+            dupe_candidate(
+                "a2",
+                "paper (1).pdf",
+                "application/pdf",
+                Some("abc"),
+                Some(10),
+                3,
+            ),
+            dupe_candidate(
+                "a3",
+                "copy.pdf",
+                "application/pdf",
+                Some("abc"),
+                Some(10),
+                2,
+            ),
+            // Same name, different checksums: not copies.
+            dupe_candidate("b1", "big.zip", "application/zip", Some("x"), Some(1000), 1),
+            dupe_candidate("b2", "big.zip", "application/zip", Some("y"), Some(1000), 2),
+            // Bigger copies come first.
+            dupe_candidate(
+                "c1",
+                "big.iso",
+                "application/x-iso",
+                Some("z"),
+                Some(500),
+                1,
+            ),
+            dupe_candidate(
+                "c2",
+                "big.iso",
+                "application/x-iso",
+                Some("z"),
+                Some(500),
+                2,
+            ),
+            // Native documents are matched by name, type, and size.
+            dupe_candidate("d1", "Notes", DOC, None, None, 1),
+            dupe_candidate("d2", "Notes", DOC, None, None, 2),
+            dupe_candidate("d3", "Notes", SHEET, None, None, 3),
+            dupe_candidate("e1", "Plan", DOC, None, Some(5), 1),
+            dupe_candidate("e2", "Plan", DOC, None, Some(6), 2),
+            // Binary files without checksums can't be compared.
+            dupe_candidate("f1", "paper.pdf", "application/pdf", None, Some(10), 1),
+            dupe_candidate("f2", "paper.pdf", "application/pdf", None, Some(10), 2),
+        ]);
+
+        assert_eq!(
+            group_ids(&groups),
+            vec![vec!["c2", "c1"], vec!["a2", "a3", "a1"], vec!["d2", "d1"]]
+        );
+        assert_eq!(n_unchecksummed, 2);
+        assert!(!is_name_only_dupe_group(&groups[0]));
+        assert!(is_name_only_dupe_group(&groups[2]));
+    }
+
+    fn is_trashed(app: &Application, the_id: &str) -> bool {
+        use schema::docs::dsl::*;
+        docs.filter(id.eq(the_id))
+            .select(trashed)
+            .first(&app.conn)
+            .unwrap()
+    }
+
+    #[test]
+    fn dupes_trash_keeps_newest() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut app, _account) = fixture_app(dir.path(), "account.json");
+
+        // P1 and P2 have the same checksum, and P2 is newer.
+        let opts = DrorgDupesOptions { trash: true };
+        assert_eq!(opts.cli(&mut app).unwrap(), 0);
+        assert!(is_trashed(&app, "P1"));
+        assert!(!is_trashed(&app, "P2"));
+    }
+
+    #[test]
+    fn dupes_trash_skips_unowned() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut app, _account) = fixture_app(dir.path(), "account.json");
+
+        {
+            use schema::account_associations::dsl::*;
+            diesel::update(account_associations.filter(doc_id.eq("P1")))
+                .set(owned_by_me.eq(false))
+                .execute(&app.conn)
+                .unwrap();
+        }
+
+        let opts = DrorgDupesOptions { trash: true };
+        assert_eq!(opts.cli(&mut app).unwrap(), 0);
+        assert!(!is_trashed(&app, "P1"));
+        assert!(!is_trashed(&app, "P2"));
+    }
+
+    /// The IDs and kinds of the logged changes that `drorg log` would show.
+    fn log_entries(
+        app: &mut Application,
+        since: Option<&str>,
+        spec: Option<&str>,
+    ) -> Result<Vec<(String, String)>> {
+        let opts = DrorgLogOptions {
+            since: since.map(|s| s.to_owned()),
+            spec: spec.map(|s| s.to_owned()),
+        };
+
+        Ok(opts
+            .entries(app)?
+            .into_iter()
+            .map(|e| (e.doc_id, e.kind))
+            .collect())
+    }
+
+    fn entry(the_id: &str, the_kind: &str) -> (String, String) {
+        (the_id.to_owned(), the_kind.to_owned())
+    }
+
+    #[test]
+    fn log_filters() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut app, _account) = fixture_app(dir.path(), "account.json");
+        app.options.sync = app::SyncOption::Yes;
+        app.maybe_sync_all_accounts().unwrap();
+
+        assert_eq!(
+            log_entries(&mut app, None, None).unwrap(),
+            vec![
+                entry("D2", "added"),
+                entry("D1", "renamed"),
+                entry("P2", "removed"),
+                entry("T2", "added"),
+            ]
+        );
+
+        // Timestamps are inclusive.
+        assert_eq!(
+            log_entries(&mut app, Some("2026-10-10T12:00:00Z"), None).unwrap(),
+            vec![
+                entry("D1", "renamed"),
+                entry("P2", "removed"),
+                entry("T2", "added"),
+            ]
+        );
+        assert_eq!(
+            log_entries(&mut app, Some("2026-10-10T12:00:01Z"), None).unwrap(),
+            vec![entry("P2", "removed"), entry("T2", "added")]
+        );
+
+        // Dates are local, but these are far enough from the changes to
+        // hold in any timezone.
+        assert_eq!(
+            log_entries(&mut app, Some("2026-10-09"), Some("Final Report")).unwrap(),
+            vec![entry("D1", "renamed")]
+        );
+        assert!(log_entries(&mut app, Some("2026-10-12"), Some("D1"))
+            .unwrap()
+            .is_empty());
+
+        // Removed documents can be selected by their IDs.
+        assert_eq!(
+            log_entries(&mut app, None, Some("P2")).unwrap(),
+            vec![entry("P2", "removed")]
+        );
+        assert_eq!(
+            log_entries(&mut app, Some("2026-10-12"), Some("P2")).unwrap(),
+            vec![entry("P2", "removed")]
+        );
+        assert!(
+            log_entries(&mut app, Some("2026-10-14T00:00:01Z"), Some("P2"))
+                .unwrap()
+                .is_empty()
+        );
+
+        assert!(log_entries(&mut app, None, Some("P3")).is_err());
+        assert!(log_entries(&mut app, Some("last week"), None).is_err());
+    }
+
+    fn tree_lines(app: &mut Application, opts: &DrorgTreeOptions, the_id: &str) -> Vec<String> {
+        let doc = app.get_docs().process_one(the_id).unwrap();
+        opts.walk(app, &doc)
+            .unwrap()
+            .lines
+            .iter()
+            .map(|l| format!("{}{}{}", l.lead, l.doc.name, l.notes))
+            .collect()
+    }
+
+    #[test]
+    fn tree_cycles_and_multiple_parents() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut app, _account) = fixture_app(dir.path(), "folders.json");
+
+        let opts = DrorgTreeOptions {
+            spec: ".".to_owned(),
+            depth: None,
+            dirs_only: false,
+            sizes: false,
+        };
+
+        assert_eq!(
+            tree_lines(&mut app, &opts, "ROOT"),
+            vec![
+                "├── Archive",
+                "│   ├── Shared  [2 parents]",
+                "│   │   └── notes.txt",
+                "│   └── paper.pdf  [2 parents]",
+                "├── Loop  [2 parents]",
+                "│   └── Back",
+                "│       ├── Loop  [2 parents; cycle!]",
+                "│       └── loop.txt",
+                "└── Projects",
+                "    ├── Shared  [2 parents; contents shown above]",
+                "    ├── paper.pdf  [2 parents]",
+                "    ├── Report",
+                "    └── Report",
+            ]
+        );
+
+        // Starting inside the cycle, the folder that we started from is the
+        // one that gets flagged.
+        assert_eq!(
+            tree_lines(&mut app, &opts, "C2"),
+            vec![
+                "├── Loop  [2 parents]",
+                "│   └── Back  [cycle!]",
+                "└── loop.txt",
+            ]
+        );
+
+        let opts = DrorgTreeOptions {
+            spec: ".".to_owned(),
+            depth: Some(1),
+            dirs_only: true,
+            sizes: false,
+        };
+
+        assert_eq!(
+            tree_lines(&mut app, &opts, "F1"),
+            vec!["└── Shared  [2 parents]"]
+        );
+    }
+
+    fn n_ghosts(app: &Application) -> usize {
+        diesel::sql_query("SELECT doc_id FROM doc_search WHERE doc_search MATCH 'ghost'")
+            .load::<database::SearchHit>(&app.conn)
+            .unwrap()
+            .len()
+    }
+
+    #[test]
+    fn sync_rebuild_reindexes() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut app, _account) = fixture_app(dir.path(), "account.json");
+
+        // As if a document had been removed without the index noticing.
+        diesel::sql_query(
+            "INSERT INTO doc_search (doc_id, name, paths) VALUES ('D9', 'Ghost', 'My Drive')",
+        )
+        .execute(&app.conn)
+        .unwrap();
+
+        app.ensure_search_index().unwrap();
+        assert_eq!(n_ghosts(&app), 1);
+
+        let opts = DrorgSyncOptions { rebuild: true };
+        assert_eq!(opts.cli(&mut app).unwrap(), 0);
+        assert_eq!(n_ghosts(&app), 0);
+
+        let hits: Vec<_> = app
+            .search_docs(&["report".to_owned()])
+            .unwrap()
+            .into_iter()
+            .map(|d| d.id)
+            .collect();
+        assert_eq!(hits, vec!["D1"]);
+    }
+}
@@ -0,0 +1,1248 @@
+// Copyright 2018-2020 Peter Williams <peter@newton.cx>
+// Licensed under the MIT License.
+
+//! Conversion of HTML exported from Google Docs into Markdown.
+//!
+//! Drive can't export documents as Markdown, but its HTML exports are quite
+//! regular, so we convert them ourselves. The conversion knows about a few
+//! quirks of Google's output: text formatting is expressed through CSS
+//! classes defined in a `<style>` element rather than through `<b>` and
+//! `<i>`; nested lists are flattened into a series of sibling lists whose
+//! levels are encoded in their class names (`lst-kix_<id>-<level>`); and
+//! links go through a `google.com/url?q=...` redirector. Anything that
+//! doesn't have a reasonable Markdown equivalent is reduced to its text.
+//!
+//! The HTML parsing here is deliberately simple. It is not a general-purpose
+//! HTML parser, but it is tolerant of the things that Google emits and does
+//! not depend on anything but its input, so that it can be exercised against
+//! saved exports.
+
+use std::collections::HashMap;
+
+/// Elements that never have content or closing tags.
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "wbr",
+];
+
+/// Elements whose content is not HTML and is taken verbatim.
+const RAW_TEXT_ELEMENTS: &[&str] = &["script", "style", "title"];
+
+/// Elements that are rendered as blocks rather than inline.
+const BLOCK_ELEMENTS: &[&str] = &[
+    "blockquote",
+    "body",
+    "div",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "hr",
+    "li",
+    "ol",
+    "p",
+    "pre",
+    "table",
+    "ul",
+];
+
+/// A node of a parsed HTML document.
+#[derive(Clone, Debug)]
+enum Node {
+    Element(Element),
+    Text(String),
+}
+
+/// An HTML element and its contents.
+#[derive(Clone, Debug, Default)]
+struct Element {
+    name: String,
+    attrs: HashMap<String, String>,
+    children: Vec<Node>,
+}
+
+impl Element {
+    fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs.get(name).map(|s| s.as_str())
+    }
+
+    fn classes(&self) -> impl Iterator<Item = &str> {
+        self.attr("class").unwrap_or("").split_whitespace()
+    }
+
+    fn is_block(&self) -> bool {
+        BLOCK_ELEMENTS.contains(&self.name.as_str())
+    }
+
+    /// Find the first descendant element with the given name.
+    fn find(&self, name: &str) -> Option<&Element> {
+        for child in &self.children {
+            if let Node::Element(e) = child {
+                if e.name == name {
+                    return Some(e);
+                }
+
+                if let Some(found) = e.find(name) {
+                    return Some(found);
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Determine whether any descendant satisfies a predicate.
+    fn any_descendant<F: Fn(&Element) -> bool + Copy>(&self, pred: F) -> bool {
+        self.children.iter().any(|c| match c {
+            Node::Element(e) => pred(e) || e.any_descendant(pred),
+            Node::Text(_) => false,
+        })
+    }
+}
+
+/// Decode the character references in a piece of HTML text.
+fn decode_entities(text: &str) -> String {
+    if !text.contains('&') {
+        return text.to_owned();
+    }
+
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(amp) = rest.find('&') {
+        result.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+
+        let end = match rest.find(';') {
+            Some(i) if i < 12 => i,
+            _ => {
+                result.push('&');
+                rest = &rest[1..];
+                continue;
+            }
+        };
+
+        let name = &rest[1..end];
+
+        let decoded = if let Some(num) = name.strip_prefix('#') {
+            let code = if let Some(hex) = num.strip_prefix('x').or_else(|| num.strip_prefix('X')) {
+                u32::from_str_radix(hex, 16).ok()
+            } else {
+                num.parse::<u32>().ok()
+            };
+            code.and_then(std::char::from_u32)
+        } else {
+            match name {
+                "amp" => Some('&'),
+                "apos" => Some('\''),
+                "bull" => Some('\u{2022}'),
+                "copy" => Some('\u{a9}'),
+                "gt" => Some('>'),
+                "hellip" => Some('\u{2026}'),
+                "ldquo" => Some('\u{201c}'),
+                "lsquo" => Some('\u{2018}'),
+                "lt" => Some('<'),
+                "mdash" => Some('\u{2014}'),
+                "middot" => Some('\u{b7}'),
+                "nbsp" => Some('\u{a0}'),
+                "ndash" => Some('\u{2013}'),
+                "quot" => Some('"'),
+                "rdquo" => Some('\u{201d}'),
+                "reg" => Some('\u{ae}'),
+                "rsquo" => Some('\u{2019}'),
+                "trade" => Some('\u{2122}'),
+                _ => None,
+            }
+        };
+
+        match decoded {
+            Some(c) => {
+                result.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                result.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    result.push_str(rest);
+    result
+}
+
+/// Parse HTML into a tree of nodes, rooted in a synthetic element.
+fn parse(html: &str) -> Element {
+    let mut stack = vec![Element::default()];
+    let mut rest = html;
+
+    fn close_top(stack: &mut Vec<Element>) {
+        if stack.len() > 1 {
+            let elem = stack.pop().unwrap();
+            stack.last_mut().unwrap().children.push(Node::Element(elem));
+        }
+    }
+
+    while !rest.is_empty() {
+        let lt = match rest.find('<') {
+            Some(i) => i,
+            None => {
+                let top = stack.last_mut().unwrap();
+                top.children.push(Node::Text(decode_entities(rest)));
+                break;
+            }
+        };
+
+        if lt > 0 {
+            let top = stack.last_mut().unwrap();
+            top.children.push(Node::Text(decode_entities(&rest[..lt])));
+        }
+
+        rest = &rest[lt..];
+
+        if rest.starts_with("<!--") {
+            rest = match rest.find("-->") {
+                Some(i) => &rest[i + 3..],
+                None => "",
+            };
+            continue;
+        }
+
+        if rest.starts_with("<!") || rest.starts_with("<?") {
+            rest = match rest.find('>') {
+                Some(i) => &rest[i + 1..],
+                None => "",
+            };
+            continue;
+        }
+
+        if let Some(after) = rest.strip_prefix("</") {
+            let end = after.find('>').unwrap_or(after.len());
+            let name = after[..end].trim().to_lowercase();
+            rest = after.get(end + 1..).unwrap_or("");
+
+            // Close everything up to the matching element, if there is one;
+            // stray closing tags are ignored.
+            if let Some(pos) = stack.iter().rposition(|e| e.name == name) {
+                if pos > 0 {
+                    while stack.len() > pos {
+                        close_top(&mut stack);
+                    }
+                }
+            }
+
+            continue;
+        }
+
+        // A start tag -- or a lone "<" that's really just text.
+
+        let after = &rest[1..];
+
+        if !after.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            stack
+                .last_mut()
+                .unwrap()
+                .children
+                .push(Node::Text("<".to_owned()));
+            rest = after;
+            continue;
+        }
+
+        let name_end = after
+            .find(|c: char| !c.is_ascii_alphanumeric())
+            .unwrap_or(after.len());
+        let name = after[..name_end].to_lowercase();
+        let mut tail = &after[name_end..];
+        let mut attrs = HashMap::new();
+        let mut self_closing = false;
+
+        loop {
+            tail = tail.trim_start();
+
+            if tail.is_empty() {
+                break;
+            }
+
+            if let Some(t) = tail.strip_prefix("/>") {
+                self_closing = true;
+                tail = t;
+                break;
+            }
+
+            if let Some(t) = tail.strip_prefix('>') {
+                tail = t;
+                break;
+            }
+
+            let attr_end = tail
+                .find(|c: char| c.is_whitespace() || c == '=' || c == '>' || c == '/')
+                .unwrap_or(tail.len());
+
+            if attr_end == 0 {
+                // A stray "/" or similar.
+                tail = &tail[1..];
+                continue;
+            }
+
+            let attr_name = tail[..attr_end].to_lowercase();
+            tail = tail[attr_end..].trim_start();
+
+            let value = if let Some(t) = tail.strip_prefix('=') {
+                let t = t.trim_start();
+
+                if let Some(quote) = t.chars().next().filter(|c| *c == '"' || *c == '\'') {
+                    let t = &t[1..];
+                    let close = t.find(quote).unwrap_or(t.len());
+                    tail = t.get(close + 1..).unwrap_or("");
+                    decode_entities(&t[..close])
+                } else {
+                    let close = t
+                        .find(|c: char| c.is_whitespace() || c == '>')
+                        .unwrap_or(t.len());
+                    tail = &t[close..];
+                    decode_entities(&t[..close])
+                }
+            } else {
+                String::new()
+            };
+
+            attrs.insert(attr_name, value);
+        }
+
+        rest = tail;
+
+        // Google's HTML doesn't rely on implied end tags, but it's easy to
+        // handle the most common cases.
+        if (name == "li" || name == "p") && stack.last().map(|e| e.name == name).unwrap_or(false) {
+            close_top(&mut stack);
+        }
+
+        let mut elem = Element {
+            name,
+            attrs,
+            children: Vec::new(),
+        };
+
+        if VOID_ELEMENTS.contains(&elem.name.as_str()) || self_closing {
+            stack.last_mut().unwrap().children.push(Node::Element(elem));
+            continue;
+        }
+
+        if RAW_TEXT_ELEMENTS.contains(&elem.name.as_str()) {
+            let closer = format!("</{}", elem.name);
+            let end = rest
+                .to_ascii_lowercase()
+                .find(&closer)
+                .unwrap_or(rest.len());
+            elem.children.push(Node::Text(rest[..end].to_owned()));
+            rest = &rest[end..];
+            rest = match rest.find('>') {
+                Some(i) => &rest[i + 1..],
+                None => "",
+            };
+            stack.last_mut().unwrap().children.push(Node::Element(elem));
+            continue;
+        }
+
+        stack.push(elem);
+    }
+
+    while stack.len() > 1 {
+        close_top(&mut stack);
+    }
+
+    stack.pop().unwrap()
+}
+
+/// Text formatting that can be expressed in Markdown.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Style {
+    bold: bool,
+    italic: bool,
+    strike: bool,
+    code: bool,
+}
+
+/// The formatting declared by a piece of CSS. Properties that aren't
+/// mentioned are left alone when the declaration is applied.
+#[derive(Clone, Copy, Debug, Default)]
+struct StyleDecl {
+    bold: Option<bool>,
+    italic: Option<bool>,
+    strike: Option<bool>,
+    code: Option<bool>,
+}
+
+impl StyleDecl {
+    /// Update this declaration based on a CSS declaration block.
+    fn apply_css(&mut self, decls: &str) {
+        for decl in decls.split(';') {
+            let mut parts = decl.splitn(2, ':');
+            let prop = parts.next().unwrap_or("").trim().to_lowercase();
+            let value = parts.next().unwrap_or("").trim().to_lowercase();
+
+            match prop.as_str() {
+                "font-weight" => {
+                    self.bold = Some(
+                        value == "bold"
+                            || value == "bolder"
+                            || value.parse::<u32>().map(|w| w >= 600).unwrap_or(false),
+                    )
+                }
+                "font-style" => self.italic = Some(value == "italic" || value == "oblique"),
+                "text-decoration" | "text-decoration-line" => {
+                    self.strike = Some(value.contains("line-through"))
+                }
+                "font-family" => {
+                    self.code = Some(
+                        ["courier", "consolas", "mono"]
+                            .iter()
+                            .any(|f| value.contains(f)),
+                    )
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Apply this declaration to the style of some text.
+    fn apply_to(&self, style: &mut Style) {
+        if let Some(b) = self.bold {
+            style.bold = b;
+        }
+
+        if let Some(i) = self.italic {
+            style.italic = i;
+        }
+
+        if let Some(s) = self.strike {
+            style.strike = s;
+        }
+
+        if let Some(c) = self.code {
+            style.code = c;
+        }
+    }
+}
+
+/// Collect the formatting implied by simple class selectors in a style sheet.
+fn parse_class_styles(css: &str) -> HashMap<String, StyleDecl> {
+    let mut styles = HashMap::new();
+
+    for rule in css.split('}') {
+        let mut parts = rule.splitn(2, '{');
+        let selectors = parts.next().unwrap_or("");
+        let decls = match parts.next() {
+            Some(d) => d,
+            None => continue,
+        };
+
+        for sel in selectors.split(',') {
+            let sel = sel.trim();
+
+            if let Some(class) = sel.strip_prefix('.') {
+                if class
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+                {
+                    styles
+                        .entry(class.to_owned())
+                        .or_insert_with(StyleDecl::default)
+                        .apply_css(decls);
+                }
+            }
+        }
+    }
+
+    styles
+}
+
+/// A piece of inline content with uniform formatting.
+#[derive(Clone, Debug, PartialEq)]
+enum Run {
+    Text {
+        text: String,
+        style: Style,
+        link: Option<String>,
+    },
+    Image {
+        src: String,
+        alt: String,
+    },
+    LineBreak,
+
+    /// Markdown syntax to be emitted as-is.
+    Raw(String),
+}
+
+/// Undo Google's link redirection.
+fn clean_link(href: &str) -> String {
+    if href.starts_with("https://www.google.com/url?")
+        || href.starts_with("http://www.google.com/url?")
+    {
+        if let Ok(u) = url::Url::parse(href) {
+            if let Some((_, target)) = u.query_pairs().find(|(k, _)| k == "q") {
+                return target.into_owned();
+            }
+        }
+    }
+
+    href.to_owned()
+}
+
+/// Escape the characters that have special meanings in Markdown text.
+fn escape_text(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        if "\\`*_[]<>".contains(c) {
+            result.push('\\');
+        }
+
+        result.push(c);
+    }
+
+    result
+}
+
+/// Escape things at the start of a block that would otherwise be taken as
+/// Markdown block syntax.
+fn escape_block_start(text: String) -> String {
+    let needs_escape = text.starts_with('#')
+        || text.starts_with("- ")
+        || text.starts_with("+ ")
+        || text == "-"
+        || text.starts_with('=')
+        || {
+            let digits = text.chars().take_while(|c| c.is_ascii_digit()).count();
+            digits > 0 && text[digits..].starts_with(". ")
+        };
+
+    if !needs_escape {
+        return text;
+    }
+
+    if text.starts_with(|c: char| c.is_ascii_digit()) {
+        let digits = text.chars().take_while(|c| c.is_ascii_digit()).count();
+        format!("{}\\{}", &text[..digits], &text[digits..])
+    } else {
+        format!("\\{}", text)
+    }
+}
+
+/// The state of a conversion.
+struct Converter {
+    class_styles: HashMap<String, StyleDecl>,
+}
+
+/// A block of output, before being joined with the others.
+#[derive(Clone, Debug)]
+enum Block {
+    Text(String),
+    ListItem(String),
+}
+
+impl Converter {
+    /// Determine the style of an element's content, given the style of its
+    /// context.
+    fn element_style(&self, elem: &Element, mut style: Style) -> Style {
+        match elem.name.as_str() {
+            "b" | "strong" => style.bold = true,
+            "i" | "em" => style.italic = true,
+            "s" | "strike" | "del" => style.strike = true,
+            "code" | "tt" | "kbd" => style.code = true,
+            _ => {}
+        }
+
+        for class in elem.classes() {
+            if let Some(decl) = self.class_styles.get(class) {
+                decl.apply_to(&mut style);
+            }
+        }
+
+        if let Some(css) = elem.attr("style") {
+            let mut decl = StyleDecl::default();
+            decl.apply_css(css);
+            decl.apply_to(&mut style);
+        }
+
+        style
+    }
+
+    /// Gather the inline content of a node into runs.
+    fn inline_runs(&self, node: &Node, style: Style, link: Option<&str>, runs: &mut Vec<Run>) {
+        let elem = match node {
+            Node::Text(t) => {
+                runs.push(Run::Text {
+                    text: t.clone(),
+                    style,
+                    link: link.map(|l| l.to_owned()),
+                });
+                return;
+            }
+            Node::Element(e) => e,
+        };
+
+        match elem.name.as_str() {
+            "br" => {
+                runs.push(Run::LineBreak);
+                return;
+            }
+            "img" => {
+                runs.push(Run::Image {
+                    src: elem.attr("src").unwrap_or("").to_owned(),
+                    alt: elem
+                        .attr("alt")
+                        .or_else(|| elem.attr("title"))
+                        .unwrap_or("")
+                        .to_owned(),
+                });
+                return;
+            }
+            "script" | "style" | "title" | "head" => return,
+            _ => {}
+        }
+
+        let mut link = link.map(|l| l.to_owned());
+
+        if elem.name == "a" {
+            match elem.attr("href") {
+                // References to comments are dropped entirely.
+                Some(h) if h.starts_with("#cmnt") => return,
+
+                // Footnotes are turned into Markdown footnotes.
+                Some(h) if h.starts_with("#ftnt_ref") => {
+                    runs.push(Run::Raw(format!("[^{}]:", &h[9..])));
+                    return;
+                }
+                Some(h) if h.starts_with("#ftnt") => {
+                    runs.push(Run::Raw(format!("[^{}]", &h[5..])));
+                    return;
+                }
+
+                // Other links within the document (e.g. from a table of
+                // contents) can't be preserved.
+                Some(h) if h.starts_with('#') => {}
+                Some(h) if !h.is_empty() => link = Some(clean_link(h)),
+                _ => {}
+            }
+        }
+
+        let style = self.element_style(elem, style);
+
+        for child in &elem.children {
+            self.inline_runs(child, style, link.as_deref(), runs);
+        }
+    }
+
+    /// Render a sequence of runs as Markdown text.
+    fn render_runs(&self, runs: Vec<Run>) -> String {
+        // First, collapse whitespace the way that HTML does and merge
+        // adjacent runs with identical formatting.
+
+        let mut merged: Vec<Run> = Vec::new();
+        let mut last_was_space = true;
+
+        for run in runs {
+            let run = match run {
+                Run::Text { text, style, link } => {
+                    let mut collapsed = String::with_capacity(text.len());
+
+                    for c in text.chars() {
+                        if c.is_whitespace() && c != '\u{a0}' {
+                            if !last_was_space {
+                                collapsed.push(' ');
+                            }
+                            last_was_space = true;
+                        } else {
+                            collapsed.push(if c == '\u{a0}' { ' ' } else { c });
+                            last_was_space = false;
+                        }
+                    }
+
+                    if collapsed.is_empty() {
+                        continue;
+                    }
+
+                    Run::Text {
+                        text: collapsed,
+                        style,
+                        link,
+                    }
+                }
+                other => {
+                    last_was_space = other == Run::LineBreak;
+                    other
+                }
+            };
+
+            if let (
+                Some(Run::Text {
+                    text: prev_text,
+                    style: prev_style,
+                    link: prev_link,
+                }),
+                Run::Text { text, style, link },
+            ) = (merged.last_mut(), &run)
+            {
+                if *prev_style == *style && *prev_link == *link {
+                    prev_text.push_str(text);
+                    continue;
+                }
+            }
+
+            merged.push(run);
+        }
+
+        // Now render. Formatting markers have to hug the text, so
+        // surrounding whitespace is moved outside of them.
+
+        let mut out = String::new();
+
+        for run in merged {
+            match run {
+                Run::LineBreak => {
+                    while out.ends_with(' ') {
+                        out.pop();
+                    }
+                    out.push_str("\\\n");
+                }
+
+                Run::Image { src, alt } => {
+                    out.push_str(&format!("![{}]({})", escape_text(&alt), src));
+                }
+
+                Run::Raw(text) => out.push_str(&text),
+
+                Run::Text { text, style, link } => {
+                    let trimmed = text.trim();
+
+                    if trimmed.is_empty() {
+                        if !out.is_empty() && !out.ends_with(' ') && !out.ends_with('\n') {
+                            out.push(' ');
+                        }
+                        continue;
+                    }
+
+                    if text.starts_with(' ') && !out.is_empty() && !out.ends_with(' ') {
+                        out.push(' ');
+                    }
+
+                    let mut s = if style.code {
+                        format!("`{}`", trimmed.replace('`', "'"))
+                    } else {
+                        escape_text(trimmed)
+                    };
+
+                    if style.strike {
+                        s = format!("~~{}~~", s);
+                    }
+
+                    if style.italic {
+                        s = format!("_{}_", s);
+                    }
+
+                    if style.bold {
+                        s = format!("**{}**", s);
+                    }
+
+                    if let Some(href) = link {
+                        s = format!("[{}]({})", s, href.replace(' ', "%20").replace(')', "%29"));
+                    }
+
+                    out.push_str(&s);
+
+                    if text.ends_with(' ') {
+                        out.push(' ');
+                    }
+                }
+            }
+        }
+
+        out.trim().to_owned()
+    }
+
+    /// Gather the inline content of an element into runs.
+    fn element_runs(&self, elem: &Element) -> Vec<Run> {
+        let mut runs = Vec::new();
+        let style = self.element_style(elem, Style::default());
+
+        for child in &elem.children {
+            self.inline_runs(child, style, None, &mut runs);
+        }
+
+        runs
+    }
+
+    /// Render the inline content of an element as Markdown text.
+    fn inline_text(&self, elem: &Element) -> String {
+        self.render_runs(self.element_runs(elem))
+    }
+
+    /// Figure out the nesting level of a list.
+    ///
+    /// Google flattens nested lists and records the level in a class name;
+    /// otherwise we go by the actual nesting.
+    fn list_level(&self, list: &Element, depth: usize) -> usize {
+        for class in list.classes() {
+            if class.starts_with("lst-kix_") {
+                if let Some(level) = class.rsplit('-').next().and_then(|l| l.parse().ok()) {
+                    return level;
+                }
+            }
+        }
+
+        depth
+    }
+
+    /// Render a list and any lists nested within it.
+    fn render_list(&self, list: &Element, depth: usize, blocks: &mut Vec<Block>) {
+        let level = self.list_level(list, depth);
+        let indent = "    ".repeat(level);
+        let marker = if list.name == "ol" { "1." } else { "-" };
+
+        for child in &list.children {
+            let item = match child {
+                Node::Element(e) if e.name == "li" => e,
+                _ => continue,
+            };
+
+            // An item's text is everything but its nested lists.
+
+            let mut runs = Vec::new();
+            let style = self.element_style(item, Style::default());
+            let mut sublists = Vec::new();
+
+            for node in &item.children {
+                match node {
+                    Node::Element(e) if e.name == "ul" || e.name == "ol" => sublists.push(e),
+                    Node::Element(e) if e.is_block() => {
+                        if !runs.is_empty() {
+                            runs.push(Run::LineBreak);
+                        }
+                        self.inline_runs(node, style, None, &mut runs);
+                    }
+                    _ => self.inline_runs(node, style, None, &mut runs),
+                }
+            }
+
+            let text = self
+                .render_runs(runs)
+                .replace('\n', &format!("\n{}  ", indent));
+            blocks.push(Block::ListItem(format!("{}{} {}", indent, marker, text)));
+
+            for sub in sublists {
+                self.render_list(sub, level + 1, blocks);
+            }
+        }
+    }
+
+    /// Render a table as a GitHub-style pipe table.
+    fn render_table(&self, table: &Element) -> Option<String> {
+        fn collect_rows<'a>(elem: &'a Element, rows: &mut Vec<&'a Element>) {
+            for child in &elem.children {
+                if let Node::Element(e) = child {
+                    match e.name.as_str() {
+                        "tr" => rows.push(e),
+                        "thead" | "tbody" | "tfoot" => collect_rows(e, rows),
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        let mut rows = Vec::new();
+        collect_rows(table, &mut rows);
+
+        let mut grid: Vec<Vec<String>> = Vec::new();
+
+        for row in rows {
+            let mut cells = Vec::new();
+
+            for child in &row.children {
+                let cell = match child {
+                    Node::Element(e) if e.name == "td" || e.name == "th" => e,
+                    _ => continue,
+                };
+
+                // Markdown table cells can only hold one line, so the
+                // paragraphs in a cell are joined with HTML line breaks.
+                let mut sub = Vec::new();
+                self.render_blocks(cell, &mut sub);
+
+                let text = sub
+                    .into_iter()
+                    .map(|b| match b {
+                        Block::Text(t) | Block::ListItem(t) => t,
+                    })
+                    .collect::<Vec<_>>()
+                    .join("<br>")
+                    .replace("\\\n", "<br>")
+                    .replace('\n', " ")
+                    .replace('|', "\\|");
+
+                cells.push(text);
+
+                let span = cell
+                    .attr("colspan")
+                    .and_then(|s| s.parse::<usize>().ok())
+                    .unwrap_or(1);
+
+                for _ in 1..span {
+                    cells.push(String::new());
+                }
+            }
+
+            grid.push(cells);
+        }
+
+        let ncols = grid.iter().map(|r| r.len()).max().unwrap_or(0);
+
+        if ncols == 0 {
+            return None;
+        }
+
+        let mut lines = Vec::new();
+
+        for (i, row) in grid.iter().enumerate() {
+            let mut cells = row.clone();
+            cells.resize(ncols, String::new());
+            lines.push(format!("| {} |", cells.join(" | ")));
+
+            if i == 0 {
+                lines.push(format!("|{}", " --- |".repeat(ncols)));
+            }
+        }
+
+        Some(lines.join("\n"))
+    }
+
+    /// Render the contents of a block-level element as a series of blocks.
+    fn render_blocks(&self, elem: &Element, blocks: &mut Vec<Block>) {
+        let mut pending = Vec::new();
+        let style = self.element_style(elem, Style::default());
+
+        let flush = |pending: &mut Vec<Run>, blocks: &mut Vec<Block>| {
+            let text = self.render_runs(std::mem::take(pending));
+
+            if !text.is_empty() {
+                blocks.push(Block::Text(escape_block_start(text)));
+            }
+        };
+
+        for child in &elem.children {
+            let e = match child {
+                Node::Element(e) if e.is_block() || e.name == "head" || e.name == "html" => e,
+                _ => {
+                    self.inline_runs(child, style, None, &mut pending);
+                    continue;
+                }
+            };
+
+            flush(&mut pending, blocks);
+            self.render_block(e, blocks);
+        }
+
+        flush(&mut pending, blocks);
+    }
+
+    /// Render a single block-level element.
+    fn render_block(&self, elem: &Element, blocks: &mut Vec<Block>) {
+        match elem.name.as_str() {
+            "head" => {}
+
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                let level = elem.name[1..].parse::<usize>().unwrap_or(1);
+
+                // Headings are often styled in bold, which is redundant in
+                // Markdown.
+                let runs = self
+                    .element_runs(elem)
+                    .into_iter()
+                    .map(|r| match r {
+                        Run::Text {
+                            text,
+                            mut style,
+                            link,
+                        } => {
+                            style.bold = false;
+                            Run::Text { text, style, link }
+                        }
+                        Run::LineBreak => Run::Text {
+                            text: " ".to_owned(),
+                            style: Style::default(),
+                            link: None,
+                        },
+                        other => other,
+                    })
+                    .collect();
+                let text = self.render_runs(runs);
+
+                if !text.is_empty() {
+                    blocks.push(Block::Text(format!("{} {}", "#".repeat(level), text)));
+                }
+            }
+
+            "p" => {
+                let text = self.inline_text(elem);
+
+                if text.is_empty() {
+                    return;
+                }
+
+                if elem.classes().any(|c| c == "title") {
+                    blocks.push(Block::Text(format!("# {}", text)));
+                } else {
+                    blocks.push(Block::Text(escape_block_start(text)));
+                }
+            }
+
+            "ul" | "ol" => self.render_list(elem, 0, blocks),
+
+            "li" => {
+                let text = self.inline_text(elem);
+                blocks.push(Block::ListItem(format!("- {}", text)));
+            }
+
+            "table" => {
+                if let Some(t) = self.render_table(elem) {
+                    blocks.push(Block::Text(t));
+                }
+            }
+
+            "hr" => blocks.push(Block::Text("---".to_owned())),
+
+            "pre" => {
+                let mut text = String::new();
+                collect_raw_text(elem, &mut text);
+                blocks.push(Block::Text(format!("```\n{}\n```", text.trim_end())));
+            }
+
+            "blockquote" => {
+                let mut sub = Vec::new();
+                self.render_blocks(elem, &mut sub);
+                let text = join_blocks(sub)
+                    .lines()
+                    .map(|l| {
+                        if l.is_empty() {
+                            ">".to_owned()
+                        } else {
+                            format!("> {}", l)
+                        }
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                blocks.push(Block::Text(text));
+            }
+
+            "div" => {
+                // Google puts the bodies of comments in divs at the end of
+                // the document; we leave them out, since they're not part of
+                // the document text.
+                if elem.any_descendant(|e| {
+                    e.name == "a" && e.attr("href").is_some_and(|h| h.starts_with("#cmnt_ref"))
+                }) {
+                    return;
+                }
+
+                self.render_blocks(elem, blocks);
+            }
+
+            _ => self.render_blocks(elem, blocks),
+        }
+    }
+}
+
+/// Gather all of the text inside an element, without any processing.
+fn collect_raw_text(elem: &Element, out: &mut String) {
+    for child in &elem.children {
+        match child {
+            Node::Text(t) => out.push_str(t),
+            Node::Element(e) if e.name == "br" => out.push('\n'),
+            Node::Element(e) => collect_raw_text(e, out),
+        }
+    }
+}
+
+/// Join rendered blocks into a document.
+///
+/// Consecutive list items are kept together, since Google splits what is
+/// logically one list into many `<ul>` and `<ol>` elements.
+fn join_blocks(blocks: Vec<Block>) -> String {
+    let mut out = String::new();
+    let mut prev_was_item = false;
+
+    for block in blocks {
+        let (text, is_item) = match block {
+            Block::Text(t) => (t, false),
+            Block::ListItem(t) => (t, true),
+        };
+
+        if !out.is_empty() {
+            out.push_str(if is_item && prev_was_item {
+                "\n"
+            } else {
+                "\n\n"
+            });
+        }
+
+        out.push_str(&text);
+        prev_was_item = is_item;
+    }
+
+    out
+}
+
+/// Convert an HTML document, as exported by Google Docs, into Markdown.
+pub fn html_to_markdown(html: &str) -> String {
+    let root = parse(html);
+
+    let css = root
+        .find("style")
+        .map(|s| {
+            let mut text = String::new();
+            collect_raw_text(s, &mut text);
+            text
+        })
+        .unwrap_or_default();
+
+    let converter = Converter {
+        class_styles: parse_class_styles(&css),
+    };
+
+    let start = root.find("body").unwrap_or(&root);
+    let mut blocks = Vec::new();
+    converter.render_blocks(start, &mut blocks);
+
+    let mut md = join_blocks(blocks);
+
+    if !md.is_empty() {
+        md.push('\n');
+    }
+
+    md
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Wrap a body in the boilerplate of a Google Docs HTML export, with the
+    /// kind of class definitions that it uses for formatting.
+    fn export(body: &str) -> String {
+        format!(
+            "<html><head><meta content=\"text/html; charset=UTF-8\" \
+             http-equiv=\"content-type\"><style type=\"text/css\">\
+             ul.lst-kix_abc-0{{list-style-type:none}}\
+             .c1{{color:#000000;font-weight:400;font-style:normal}}\
+             .c2{{font-weight:700}}.c3{{font-style:italic}}\
+             .c4{{font-family:\"Courier New\"}}\
+             .c5{{background-color:#ffffff;max-width:468pt}}\
+             </style></head><body class=\"c5 doc-content\">{}</body></html>",
+            body
+        )
+    }
+
+    #[test]
+    fn headings() {
+        let md = html_to_markdown(&export(
+            "<p class=\"c0 title\" id=\"h.t\"><span class=\"c1\">The Title</span></p>\
+             <h1 class=\"c6\" id=\"h.a\"><span class=\"c2\">First</span></h1>\
+             <h3 class=\"c6\" id=\"h.b\"><span class=\"c1\">Third</span></h3>\
+             <p class=\"c0\"><span class=\"c1\"># not a heading</span></p>",
+        ));
+        assert_eq!(
+            md,
+            "# The Title\n\n# First\n\n### Third\n\n\\# not a heading\n"
+        );
+    }
+
+    #[test]
+    fn class_based_formatting() {
+        let md = html_to_markdown(&export(
+            "<p class=\"c0\"><span class=\"c1\">Plain, </span><span class=\"c2\">bold</span>\
+             <span class=\"c1\">, </span><span class=\"c3\">italic</span>\
+             <span class=\"c1\">, </span><span class=\"c2 c3\">both</span>\
+             <span class=\"c1\">, and </span><span class=\"c4\">code</span>\
+             <span class=\"c1\">&nbsp;with *stars*.</span></p>",
+        ));
+        assert_eq!(
+            md,
+            "Plain, **bold**, _italic_, **_both_**, and `code` with \\*stars\\*.\n"
+        );
+    }
+
+    #[test]
+    fn flattened_nested_lists() {
+        let md = html_to_markdown(&export(
+            "<ul class=\"c7 lst-kix_abc-0 start\"><li class=\"c0 li-bullet-0\">\
+             <span class=\"c1\">One</span></li><li class=\"c0 li-bullet-0\">\
+             <span class=\"c1\">Two</span></li></ul>\
+             <ul class=\"c7 lst-kix_abc-1 start\"><li class=\"c0 li-bullet-0\">\
+             <span class=\"c1\">Two A</span></li></ul>\
+             <ol class=\"c7 lst-kix_def-2 start\" start=\"1\"><li class=\"c0 li-bullet-0\">\
+             <span class=\"c1\">Deep</span></li></ol>\
+             <ul class=\"c7 lst-kix_abc-0\"><li class=\"c0 li-bullet-0\">\
+             <span class=\"c1\">Three</span></li></ul>\
+             <p class=\"c0\"><span class=\"c1\">1. Not a list</span></p>",
+        ));
+        assert_eq!(
+            md,
+            "- One\n- Two\n    - Two A\n        1. Deep\n- Three\n\n1\\. Not a list\n"
+        );
+    }
+
+    #[test]
+    fn redirected_links() {
+        let md = html_to_markdown(&export(
+            "<p class=\"c0\"><span class=\"c1\">See </span><span class=\"c8\">\
+             <a class=\"c8\" href=\"https://www.google.com/url?q=https://example.com/a?x%3D1&amp;\
+             sa=D&amp;source=editors&amp;ust=123&amp;usg=abc\">the site</a></span>\
+             <span class=\"c1\">&nbsp;or </span>\
+             <a href=\"https://example.org/\">this</a><span class=\"c1\">.</span></p>",
+        ));
+        assert_eq!(
+            md,
+            "See [the site](https://example.com/a?x=1) or [this](https://example.org/).\n"
+        );
+    }
+
+    #[test]
+    fn tables() {
+        let md = html_to_markdown(&export(
+            "<table class=\"c9\"><tr class=\"c10\">\
+             <td class=\"c11\" colspan=\"2\" rowspan=\"1\"><p class=\"c0\">\
+             <span class=\"c2\">Region</span></p></td>\
+             <td class=\"c11\" colspan=\"1\" rowspan=\"1\"><p class=\"c0\">\
+             <span class=\"c1\">Sales</span></p></td></tr>\
+             <tr class=\"c10\"><td class=\"c11\" colspan=\"1\" rowspan=\"1\"><p class=\"c0\">\
+             <span class=\"c1\">North | East</span></p></td>\
+             <td class=\"c11\" colspan=\"1\" rowspan=\"1\"><p class=\"c0\">\
+             <span class=\"c1\">Q1</span></p></td>\
+             <td class=\"c11\" colspan=\"1\" rowspan=\"1\"><p class=\"c0\">\
+             <span class=\"c1\">10</span></p><p class=\"c0\"><span class=\"c1\">(est.)</span>\
+             </p></td></tr></table>",
+        ));
+        assert_eq!(
+            md,
+            "| **Region** |  | Sales |\n| --- | --- | --- |\n\
+             | North \\| East | Q1 | 10<br>(est.) |\n"
+        );
+    }
+
+    #[test]
+    fn comments_dropped() {
+        let md = html_to_markdown(&export(
+            "<p class=\"c0\"><span class=\"c1\">Text</span><sup>\
+             <a href=\"#cmnt1\" id=\"cmnt_ref1\">[a]</a></sup></p>\
+             <div class=\"c12\"><p class=\"c0\"><a href=\"#cmnt_ref1\" id=\"cmnt1\">[a]</a>\
+             <span class=\"c1\">Is this right?</span></p></div>\
+             <div><p class=\"c0\"><span class=\"c1\">Kept</span></p></div>",
+        ));
+        assert!(!md.contains("Is this right?"), "{}", md);
+        assert!(md.starts_with("Text"), "{}", md);
+        assert!(md.ends_with("\n\nKept\n"), "{}", md);
+    }
+}
@@ -0,0 +1,976 @@
+// Copyright 2018-2020 Peter Williams <peter@newton.cx>
+// Licensed under the MIT License.
+
+//! Two-way synchronization of a Drive folder with a local directory.
+//!
+//! A mirror pairs a folder with a directory. For every file that has been
+//! synchronized, the `mirror_entries` table remembers the document's
+//! modification time and the local file's modification time and size as of
+//! that moment. On each run, we compare both sides against those records to
+//! figure out which side changed:
+//!
+//! - If only the Drive side changed, the file is downloaded (or exported, for
+//!   native documents).
+//! - If only the local side changed, the file is uploaded.
+//! - If both changed, the local file is renamed out of the way, marked as a
+//!   conflict, and uploaded as a new document; the Drive version is then
+//!   downloaded in its place. A file that appears on both sides before the
+//!   first run is only treated as a conflict if the two copies differ.
+//! - Deletions on one side are propagated to the other, unless the other
+//!   side has changed in the meantime. Documents deleted locally are moved to
+//!   the trash, not deleted outright -- or, if they also live in other
+//!   folders, just removed from the mirrored one.
+//!
+//! Native Google documents can only be exported, not re-imported, so the
+//! local copies of them are read-only as far as the mirror is concerned:
+//! local changes to them are never uploaded, they are overwritten when the
+//! document changes, and they are restored if they are deleted.
+//!
+//! The Drive side of the comparison comes from the database, which the
+//! regular synchronization keeps up to date incrementally using the
+//! account's change feed, so there is no need to rescan the folder on the
+//! server.
+
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use google_drive3::File;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use accounts;
+use app::Application;
+use content;
+use database::{
+    self, Doc, MirrorEntry, NewMirror, NewMirrorEntry, FOLDER_MIME_TYPE, SHORTCUT_MIME_TYPE,
+};
+use errors::Result;
+
+/// Local files whose names start with this are temporaries created by
+/// `content::write_atomically`, and are ignored.
+const TEMPFILE_PREFIX: &str = ".drorg";
+
+/// A file on the Drive side of a mirror.
+struct RemoteFile {
+    doc: Doc,
+
+    /// If the document is native, the MIME type that it is exported in.
+    export_mime_type: Option<String>,
+}
+
+/// A file on the local side of a mirror.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct LocalFile {
+    modified_time: i64,
+    size: i64,
+}
+
+impl LocalFile {
+    fn stat<P: AsRef<Path>>(path: P) -> Result<LocalFile> {
+        let meta = fs::metadata(path)?;
+        let modified_time = meta
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as i64)
+            .unwrap_or(0);
+
+        Ok(LocalFile {
+            modified_time,
+            size: meta.len() as i64,
+        })
+    }
+
+    fn matches(&self, entry: &MirrorEntry) -> bool {
+        self.modified_time == entry.local_modified_time && self.size == entry.local_size
+    }
+}
+
+/// How many files were affected by a mirror run.
+#[derive(Debug, Default)]
+pub struct MirrorSummary {
+    /// The number of files downloaded or exported.
+    pub downloaded: usize,
+
+    /// The number of files uploaded, whether new or modified.
+    pub uploaded: usize,
+
+    /// The number of conflicts that were resolved by renaming.
+    pub conflicts: usize,
+
+    /// The number of local files deleted.
+    pub deleted: usize,
+
+    /// The number of documents moved to the trash.
+    pub trashed: usize,
+
+    /// The number of documents removed from the mirrored folder but kept in
+    /// their other folders.
+    pub unlinked: usize,
+}
+
+/// The options that control a mirror run.
+#[derive(Debug)]
+pub struct MirrorOptions<'a> {
+    /// The format in which to export native documents. If None, the format
+    /// chosen for a previous run is used, or failing that, a default.
+    pub export_format: Option<&'a str>,
+
+    /// If true, report what would be done without doing it.
+    pub dry_run: bool,
+}
+
+/// Split a relative path into its directory and file name.
+fn split_path(path: &str) -> (&str, &str) {
+    match path.rfind('/') {
+        Some(i) => (&path[..i], &path[i + 1..]),
+        None => ("", path),
+    }
+}
+
+/// The path to rename a locally-modified file to when there is a conflict.
+fn conflict_path(path: &str) -> String {
+    let (dir, name) = split_path(path);
+    let stamp = chrono::Local::now().format("%Y-%m-%d %H%M%S");
+
+    let name = match name.rfind('.') {
+        Some(i) if i > 0 => format!("{} (conflict {}){}", &name[..i], stamp, &name[i..]),
+        _ => format!("{} (conflict {})", name, stamp),
+    };
+
+    content::join_path(dir, &name)
+}
+
+/// Mirror a folder to a local directory.
+pub struct Mirrorer<'a> {
+    app: &'a mut Application,
+    acct: database::Account,
+    account: accounts::Account,
+    mirror_id: Option<i32>,
+    local_dir: PathBuf,
+    export_format: Option<String>,
+    dry_run: bool,
+
+    /// The IDs of the remote folders, keyed by their relative paths.
+    folders: HashMap<String, String>,
+
+    summary: MirrorSummary,
+}
+
+impl<'a> Mirrorer<'a> {
+    /// Set up to mirror *folder* into *local_dir* through the specified
+    /// account.
+    ///
+    /// The mirror is registered in the database if it is new, unless this
+    /// is a dry run.
+    pub fn new(
+        app: &'a mut Application,
+        acct: database::Account,
+        folder: &Doc,
+        the_local_dir: &Path,
+        options: &MirrorOptions,
+    ) -> Result<Mirrorer<'a>> {
+        if !folder.is_folder() {
+            return Err(format_err!("\"{}\" is not a folder", folder.name));
+        }
+
+        if !the_local_dir.exists() && !options.dry_run {
+            fs::create_dir_all(the_local_dir)?;
+        }
+
+        let the_local_dir = if the_local_dir.exists() {
+            the_local_dir.canonicalize()?
+        } else {
+            std::env::current_dir()?.join(the_local_dir)
+        };
+
+        let dir_text = the_local_dir
+            .to_str()
+            .ok_or_else(|| format_err!("the local directory must have a UTF-8 path"))?
+            .to_owned();
+
+        let account = accounts::Account::load(&acct.email)?;
+
+        let mirror = {
+            use schema::mirrors::dsl::*;
+
+            let existing = mirrors
+                .filter(
+                    account_id
+                        .eq(acct.id)
+                        .and(folder_id.eq(&folder.id))
+                        .and(local_dir.eq(&dir_text)),
+                )
+                .first::<database::Mirror>(&app.conn)
+                .optional()?;
+
+            match existing {
+                Some(m) => {
+                    if !options.dry_run && options.export_format.is_some() {
+                        diesel::update(mirrors.filter(id.eq(m.id)))
+                            .set(export_format.eq(options.export_format))
+                            .execute(&app.conn)?;
+                    }
+
+                    Some(m)
+                }
+
+                None if options.dry_run => None,
+
+                None => {
+                    diesel::insert_into(mirrors)
+                        .values(&NewMirror {
+                            account_id: acct.id,
+                            folder_id: &folder.id,
+                            local_dir: &dir_text,
+                            export_format: options.export_format,
+                        })
+                        .execute(&app.conn)?;
+
+                    Some(
+                        mirrors
+                            .filter(
+                                account_id
+                                    .eq(acct.id)
+                                    .and(folder_id.eq(&folder.id))
+                                    .and(local_dir.eq(&dir_text)),
+                            )
+                            .first::<database::Mirror>(&app.conn)?,
+                    )
+                }
+            }
+        };
+
+        // An explicitly requested export format overrides the one that was
+        // saved.
+        let the_export_format = options
+            .export_format
+            .map(|f| f.to_owned())
+            .or_else(|| mirror.as_ref().and_then(|m| m.export_format.clone()));
+
+        let mut folders = HashMap::new();
+        folders.insert(String::new(), folder.id.clone());
+
+        Ok(Mirrorer {
+            app,
+            acct,
+            account,
+            mirror_id: mirror.as_ref().map(|m| m.id),
+            local_dir: the_local_dir,
+            export_format: the_export_format,
+            dry_run: options.dry_run,
+            folders,
+            summary: MirrorSummary::default(),
+        })
+    }
+
+    /// Gather the files in the Drive folder, keyed by their relative paths.
+    ///
+    /// As a side effect, this fills in `self.folders`.
+    fn scan_remote(&mut self, export_format: Option<&str>) -> Result<HashMap<String, RemoteFile>> {
+        let linkage = self.app.load_linkage_table(self.acct.id, false)?;
+        let root_id = self.folders[""].clone();
+
+        // First, find all of the IDs in the subtree, so that the documents
+        // can be loaded in one go. Cycles are possible!
+
+        let mut seen = HashSet::new();
+        let mut queue = vec![root_id.clone()];
+
+        while let Some(fid) = queue.pop() {
+            for child in linkage.children(&fid) {
+                if seen.insert(child.clone()) {
+                    queue.push(child);
+                }
+            }
+        }
+
+        let docs: HashMap<String, Doc> = {
+            use schema::docs::dsl::*;
+            let ids: Vec<_> = seen.into_iter().collect();
+            let mut map = HashMap::new();
+
+            // Keep the number of SQL parameters reasonable.
+            for chunk in ids.chunks(500) {
+                for doc in docs.filter(id.eq_any(chunk)).load::<Doc>(&self.app.conn)? {
+                    map.insert(doc.id.clone(), doc);
+                }
+            }
+
+            map
+        };
+
+        let needs_about = docs.values().any(|d| d.is_native() && !d.is_folder());
+        let about = if needs_about {
+            let about = self
+                .app
+                .with_backend(&mut self.account, |backend| backend.about())?;
+            Some(about)
+        } else {
+            None
+        };
+
+        // Now walk the tree, assigning paths. Within each folder, the names
+        // are made unique.
+
+        let mut files = HashMap::new();
+        let mut visited = HashSet::new();
+        let mut queue = vec![(String::new(), root_id)];
+        visited.insert(queue[0].1.clone());
+
+        while let Some((dir, fid)) = queue.pop() {
+            let mut children: Vec<&Doc> = linkage
+                .children(&fid)
+                .iter()
+                .filter_map(|c| docs.get(c))
+                .filter(|d| !d.trashed)
+                .collect();
+            children.sort_by(|a, b| (&a.name, &a.id).cmp(&(&b.name, &b.id)));
+
+            let mut taken = HashSet::new();
+
+            for doc in children {
+                if doc.is_folder() {
+                    if !visited.insert(doc.id.clone()) {
+                        continue;
+                    }
+
+                    let path = content::join_path(
+                        &dir,
+                        &content::unique_name(content::safe_component(&doc.name), &mut taken),
+                    );
+                    self.folders.insert(path.clone(), doc.id.clone());
+                    queue.push((path, doc.id.clone()));
+                    continue;
+                }
+
+                if doc.mime_type == SHORTCUT_MIME_TYPE {
+                    continue;
+                }
+
+                let export_mime_type = match (doc.is_native(), about.as_ref()) {
+                    (true, Some(about)) => {
+                        match content::choose_bulk_export_format(
+                            about,
+                            &doc.mime_type,
+                            export_format,
+                        ) {
+                            Ok(m) => Some(m),
+                            Err(e) => {
+                                tcreport!(self.app.ps, warning: "skipping \"{}\": {}", doc.name, e);
+                                continue;
+                            }
+                        }
+                    }
+                    _ => None,
+                };
+
+                let name = content::local_filename(&doc.name, export_mime_type.as_deref())
+                    .to_string_lossy()
+                    .into_owned();
+                let name = content::unique_name(content::safe_component(&name), &mut taken);
+                files.insert(
+                    content::join_path(&dir, &name),
+                    RemoteFile {
+                        doc: doc.clone(),
+                        export_mime_type,
+                    },
+                );
+            }
+        }
+
+        Ok(files)
+    }
+
+    /// Gather the files in the local directory, keyed by their relative
+    /// paths.
+    fn scan_local(&mut self) -> Result<HashMap<String, LocalFile>> {
+        let mut files = HashMap::new();
+
+        if !self.local_dir.exists() {
+            return Ok(files);
+        }
+
+        let mut queue = vec![(String::new(), self.local_dir.clone())];
+
+        while let Some((rel, abs)) = queue.pop() {
+            for entry in fs::read_dir(&abs)? {
+                let entry = entry?;
+                let name = match entry.file_name().into_string() {
+                    Ok(n) => n,
+                    Err(n) => {
+                        tcreport!(self.app.ps, warning: "skipping local file with non-UTF-8 name {:?}", n);
+                        continue;
+                    }
+                };
+
+                if name.starts_with(TEMPFILE_PREFIX) {
+                    continue;
+                }
+
+                let path = content::join_path(&rel, &name);
+                let file_type = entry.file_type()?;
+
+                if file_type.is_dir() {
+                    queue.push((path, entry.path()));
+                } else if file_type.is_file() {
+                    files.insert(path, LocalFile::stat(entry.path())?);
+                }
+            }
+        }
+
+        Ok(files)
+    }
+
+    /// Load the records of the last synchronization.
+    fn load_entries(&self) -> Result<HashMap<String, MirrorEntry>> {
+        let the_mirror_id = match self.mirror_id {
+            Some(i) => i,
+            None => return Ok(HashMap::new()),
+        };
+
+        use schema::mirror_entries::dsl::*;
+
+        Ok(mirror_entries
+            .filter(mirror_id.eq(the_mirror_id))
+            .load::<MirrorEntry>(&self.app.conn)?
+            .into_iter()
+            .map(|e| (e.path.clone(), e))
+            .collect())
+    }
+
+    /// Record that a file has been synchronized.
+    fn record(&self, the_path: &str, the_doc_id: &str, remote_time: NaiveDateTime) -> Result<()> {
+        let local = LocalFile::stat(self.local_dir.join(the_path))?;
+
+        if let Some(the_mirror_id) = self.mirror_id {
+            use schema::mirror_entries::dsl::*;
+
+            diesel::replace_into(mirror_entries)
+                .values(&NewMirrorEntry {
+                    mirror_id: the_mirror_id,
+                    path: the_path,
+                    doc_id: the_doc_id,
+                    remote_modified_time: remote_time,
+                    local_modified_time: local.modified_time,
+                    local_size: local.size,
+                })
+                .execute(&self.app.conn)?;
+        }
+
+        Ok(())
+    }
+
+    /// Forget the record of a file.
+    fn forget(&self, the_path: &str) -> Result<()> {
+        if let Some(the_mirror_id) = self.mirror_id {
+            use schema::mirror_entries::dsl::*;
+
+            diesel::delete(
+                mirror_entries.filter(mirror_id.eq(the_mirror_id).and(path.eq(the_path))),
+            )
+            .execute(&self.app.conn)?;
+        }
+
+        Ok(())
+    }
+
+    /// Tell the user what we're doing.
+    fn report(&mut self, action: &str, path: &str) {
+        tcprintln!(self.app.ps, [green: "{:>9}", action], ("  {}", path));
+    }
+
+    /// Fetch the content of a remote file into memory.
+    fn fetch(&mut self, remote: &RemoteFile) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        self.app.download_doc(
+            &mut self.account,
+            &remote.doc.id,
+            remote.export_mime_type.as_deref(),
+            &mut data,
+        )?;
+        Ok(data)
+    }
+
+    /// Decide whether a local file that we haven't seen before has the same
+    /// content as a remote file. The sizes and, if the server reported one,
+    /// the MD5 checksum are compared; otherwise the remote file is fetched
+    /// for a direct comparison, except in a dry run, where matching sizes
+    /// have to do.
+    fn same_content(&mut self, path: &str, remote: &RemoteFile, local: &LocalFile) -> Result<bool> {
+        if remote.doc.size.map(i64::from) != Some(local.size) {
+            return Ok(false);
+        }
+
+        if self.dry_run && remote.doc.md5_checksum.is_none() {
+            return Ok(true);
+        }
+
+        let mut local_data = Vec::new();
+        fs::File::open(self.local_dir.join(path))?.read_to_end(&mut local_data)?;
+
+        if let Some(ref expected) = remote.doc.md5_checksum {
+            return Ok(format!("{:x}", md5::compute(&local_data)) == *expected);
+        }
+
+        Ok(self.fetch(remote)? == local_data)
+    }
+
+    /// Download a remote file into place.
+    fn download(&mut self, path: &str, remote: &RemoteFile) -> Result<()> {
+        self.report("download", path);
+        self.summary.downloaded += 1;
+
+        if self.dry_run {
+            return Ok(());
+        }
+
+        let dest = self.local_dir.join(path);
+
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let app = &*self.app;
+        let account = &mut self.account;
+
+        content::write_atomically(&dest, |w| {
+            app.download_doc(
+                account,
+                &remote.doc.id,
+                remote.export_mime_type.as_deref(),
+                w,
+            )?;
+            Ok(())
+        })?;
+
+        self.record(path, &remote.doc.id, remote.doc.modified_time)
+    }
+
+    /// Find the ID of the remote folder corresponding to a local directory,
+    /// creating it (and its parents) if needed.
+    fn ensure_folder(&mut self, dir: &str) -> Result<String> {
+        if let Some(fid) = self.folders.get(dir) {
+            return Ok(fid.clone());
+        }
+
+        let (parent_dir, name) = split_path(dir);
+        let parent_id = self.ensure_folder(parent_dir)?;
+
+        let folder_id = if self.dry_run {
+            format!("(new folder {})", dir)
+        } else {
+            let metadata = File {
+                name: Some(name.to_owned()),
+                mime_type: Some(FOLDER_MIME_TYPE.to_owned()),
+                parents: Some(vec![parent_id]),
+                ..Default::default()
+            };
+
+            self.app.create_doc(&self.acct, metadata)?.id
+        };
+
+        self.report("mkdir", dir);
+        self.folders.insert(dir.to_owned(), folder_id.clone());
+        Ok(folder_id)
+    }
+
+    /// Upload a local file that doesn't yet exist remotely.
+    fn upload_new(&mut self, path: &str) -> Result<()> {
+        let (dir, name) = split_path(path);
+        let parent_id = self.ensure_folder(dir)?;
+
+        self.report("upload", path);
+        self.summary.uploaded += 1;
+
+        if self.dry_run {
+            return Ok(());
+        }
+
+        let src = self.local_dir.join(path);
+        let mut f = fs::File::open(&src)?;
+
+        let metadata = File {
+            name: Some(name.to_owned()),
+            parents: Some(vec![parent_id]),
+            ..Default::default()
+        };
+
+        let doc =
+            self.app
+                .upload_doc(&self.acct, metadata, &mut f, content::mime_for_path(&src))?;
+        self.record(path, &doc.id, doc.modified_time)
+    }
+
+    /// Upload new content for an existing remote file.
+    fn upload_update(&mut self, path: &str, doc_id: &str) -> Result<()> {
+        self.report("upload", path);
+        self.summary.uploaded += 1;
+
+        if self.dry_run {
+            return Ok(());
+        }
+
+        let src = self.local_dir.join(path);
+        let mut f = fs::File::open(&src)?;
+        let doc = self.app.update_doc_content(
+            &self.acct,
+            doc_id,
+            &mut f,
+            content::mime_for_path(&src),
+        )?;
+        self.record(path, &doc.id, doc.modified_time)
+    }
+
+    /// Move the local version of a file out of the way. Returns the new
+    /// path, which needs to be uploaded.
+    fn set_aside(&mut self, path: &str) -> Result<String> {
+        let new_path = conflict_path(path);
+        self.report("conflict", &format!("{} -> {}", path, new_path));
+        self.summary.conflicts += 1;
+
+        if !self.dry_run {
+            fs::rename(self.local_dir.join(path), self.local_dir.join(&new_path))?;
+        }
+
+        Ok(new_path)
+    }
+
+    /// Delete a local file.
+    fn delete_local(&mut self, path: &str) -> Result<()> {
+        self.report("delete", path);
+        self.summary.deleted += 1;
+
+        if self.dry_run {
+            return Ok(());
+        }
+
+        fs::remove_file(self.local_dir.join(path))?;
+        self.forget(path)
+    }
+
+    /// Move a remote file to the trash. If the document has other parents
+    /// besides the folder that it was mirrored from, it is only removed from
+    /// that folder, so that it doesn't disappear from the others.
+    fn trash_remote(&mut self, path: &str, doc_id: &str) -> Result<()> {
+        let folder_id = self.folders[split_path(path).0].clone();
+        let has_other_parents = self
+            .app
+            .parent_ids(self.acct.id, doc_id)?
+            .iter()
+            .any(|pid| *pid != folder_id);
+
+        if has_other_parents {
+            self.report("unlink", path);
+            self.summary.unlinked += 1;
+        } else {
+            self.report("trash", path);
+            self.summary.trashed += 1;
+        }
+
+        if self.dry_run {
+            return Ok(());
+        }
+
+        if has_other_parents {
+            self.app
+                .update_doc(&self.acct, doc_id, File::default(), &[], &[&folder_id])?;
+        } else {
+            let patch = File {
+                trashed: Some(true),
+                ..Default::default()
+            };
+
+            self.app.update_doc(&self.acct, doc_id, patch, &[], &[])?;
+        }
+
+        self.forget(path)
+    }
+
+    /// Synchronize the two sides of the mirror.
+    pub fn run(mut self) -> Result<MirrorSummary> {
+        let export_format = self.export_format.clone();
+        let remote = self.scan_remote(export_format.as_deref())?;
+        let local = self.scan_local()?;
+        let entries = self.load_entries()?;
+
+        // Remote folders are recreated locally even if they're empty.
+        if !self.dry_run {
+            for dir in self.folders.keys() {
+                fs::create_dir_all(self.local_dir.join(dir))?;
+            }
+        }
+
+        let paths: BTreeSet<&String> = remote
+            .keys()
+            .chain(local.keys())
+            .chain(entries.keys())
+            .collect();
+
+        let mut new_local = Vec::new();
+
+        for path in paths {
+            let r = remote.get(path);
+            let l = local.get(path);
+            let e = entries.get(path);
+
+            match (r, l, e) {
+                (Some(r), Some(l), Some(e)) => {
+                    let remote_changed =
+                        r.doc.id != e.doc_id || r.doc.modified_time != e.remote_modified_time;
+                    let local_changed = !l.matches(e);
+
+                    match (remote_changed, local_changed) {
+                        (false, false) => {}
+                        (true, false) => self.download(path, r)?,
+                        (false, true) if r.export_mime_type.is_some() => {
+                            tcreport!(self.app.ps, warning: "not uploading local changes to \
+                                                             exported document \"{}\"", path);
+                        }
+                        (false, true) => self.upload_update(path, &r.doc.id)?,
+                        (true, true) if r.export_mime_type.is_some() => {
+                            tcreport!(self.app.ps, warning: "discarding local changes to \
+                                                             exported document \"{}\"", path);
+                            self.download(path, r)?;
+                        }
+                        (true, true) => {
+                            new_local.push(self.set_aside(path)?);
+                            self.download(path, r)?;
+                        }
+                    }
+                }
+
+                (Some(r), Some(_l), None) if r.export_mime_type.is_some() => {
+                    // An export never matches a file byte-for-byte, and
+                    // local copies of native documents are read-only, so
+                    // the export just replaces whatever is there.
+                    self.download(path, r)?;
+                }
+
+                (Some(r), Some(l), None) => {
+                    // Both sides have a file that we haven't seen before,
+                    // probably because this is the first run. If they're
+                    // identical, there's nothing to do.
+                    if self.same_content(path, r, l)? {
+                        if !self.dry_run {
+                            self.record(path, &r.doc.id, r.doc.modified_time)?;
+                        }
+                    } else {
+                        new_local.push(self.set_aside(path)?);
+                        self.download(path, r)?;
+                    }
+                }
+
+                (Some(r), None, Some(e)) => {
+                    let remote_changed =
+                        r.doc.id != e.doc_id || r.doc.modified_time != e.remote_modified_time;
+
+                    if remote_changed || r.export_mime_type.is_some() {
+                        self.download(path, r)?;
+                    } else {
+                        self.trash_remote(path, &r.doc.id)?;
+                    }
+                }
+
+                (Some(r), None, None) => self.download(path, r)?,
+
+                (None, Some(l), Some(e)) => {
+                    if l.matches(e) {
+                        self.delete_local(path)?;
+                    } else {
+                        // Changed locally but gone remotely: keep the local
+                        // version and upload it anew.
+                        if !self.dry_run {
+                            self.forget(path)?;
+                        }
+                        self.upload_new(path)?;
+                    }
+                }
+
+                (None, Some(_l), None) => self.upload_new(path)?,
+
+                (None, None, Some(_e)) => {
+                    if !self.dry_run {
+                        self.forget(path)?;
+                    }
+                }
+
+                (None, None, None) => unreachable!(),
+            }
+        }
+
+        for path in new_local {
+            self.upload_new(&path)?;
+        }
+
+        Ok(self.summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use test_support::fixture_app;
+
+    /// Mirror the "Projects" folder into *local*, returning the numbers of
+    /// files downloaded, uploaded, in conflict, deleted, trashed, and
+    /// unlinked.
+    fn run(app: &mut Application, local: &Path) -> (usize, usize, usize, usize, usize, usize) {
+        let folder = app.get_docs().process_one("F1").unwrap();
+        let acct = account(app);
+        let options = MirrorOptions {
+            export_format: None,
+            dry_run: false,
+        };
+
+        let s = Mirrorer::new(app, acct, &folder, local, &options)
+            .unwrap()
+            .run()
+            .unwrap();
+        (
+            s.downloaded,
+            s.uploaded,
+            s.conflicts,
+            s.deleted,
+            s.trashed,
+            s.unlinked,
+        )
+    }
+
+    fn read(local: &Path, name: &str) -> String {
+        fs::read_to_string(local.join(name)).unwrap()
+    }
+
+    fn local_names(local: &Path) -> Vec<String> {
+        let mut names: Vec<_> = fs::read_dir(local)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        names
+    }
+
+    fn doc(app: &Application, the_id: &str) -> Doc {
+        use schema::docs::dsl::*;
+        docs.filter(id.eq(the_id)).first(&app.conn).unwrap()
+    }
+
+    fn account(app: &mut Application) -> database::Account {
+        let folder = app.get_docs().process_one("F1").unwrap();
+        folder.accounts(app).unwrap().pop().unwrap()
+    }
+
+    #[test]
+    fn first_run_and_conflicts() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut app, _account) = fixture_app(dir.path(), "mirror.json");
+        let local = dir.path().join("local");
+
+        assert_eq!(run(&mut app, &local), (4, 0, 0, 0, 0, 0));
+        assert_eq!(
+            local_names(&local),
+            vec!["Notes.pdf", "a.txt", "b.txt", "c.txt"]
+        );
+        assert_eq!(read(&local, "a.txt"), "alpha\n");
+        assert_eq!(read(&local, "Notes.pdf"), "%PDF-notes\n");
+        assert_eq!(run(&mut app, &local), (0, 0, 0, 0, 0, 0));
+
+        // One-sided changes go one way or the other.
+        fs::write(local.join("c.txt"), "gamma, edited\n").unwrap();
+        let acct = account(&mut app);
+        app.update_doc_content(
+            &acct,
+            "B1",
+            &mut Cursor::new("beta, edited\n"),
+            "text/plain",
+        )
+        .unwrap();
+        assert_eq!(run(&mut app, &local), (1, 1, 0, 0, 0, 0));
+        assert_eq!(read(&local, "b.txt"), "beta, edited\n");
+        assert_eq!(doc(&app, "C1").size, Some(14));
+
+        // If both sides changed, the local version is set aside and
+        // uploaded as a new document.
+        fs::write(local.join("a.txt"), "alpha, edited locally\n").unwrap();
+        app.update_doc_content(
+            &acct,
+            "A1",
+            &mut Cursor::new("alpha, edited remotely\n"),
+            "text/plain",
+        )
+        .unwrap();
+        assert_eq!(run(&mut app, &local), (1, 1, 1, 0, 0, 0));
+        assert_eq!(read(&local, "a.txt"), "alpha, edited remotely\n");
+
+        let conflicted: Vec<_> = local_names(&local)
+            .into_iter()
+            .filter(|n| n.starts_with("a (conflict "))
+            .collect();
+        assert_eq!(conflicted.len(), 1);
+        assert_eq!(read(&local, &conflicted[0]), "alpha, edited locally\n");
+
+        let uploaded = app.get_docs().process_one(&conflicted[0]).unwrap();
+        assert_eq!(app.parent_ids(acct.id, &uploaded.id).unwrap(), vec!["F1"]);
+        assert_eq!(run(&mut app, &local), (0, 0, 0, 0, 0, 0));
+    }
+
+    #[test]
+    fn deletions() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut app, _account) = fixture_app(dir.path(), "mirror.json");
+        let local = dir.path().join("local");
+        assert_eq!(run(&mut app, &local), (4, 0, 0, 0, 0, 0));
+
+        // A file that is only in the mirrored folder is trashed, but one
+        // that is also elsewhere is just taken out of the folder.
+        fs::remove_file(local.join("b.txt")).unwrap();
+        fs::remove_file(local.join("c.txt")).unwrap();
+        assert_eq!(run(&mut app, &local), (0, 0, 0, 0, 1, 1));
+
+        let acct = account(&mut app);
+        assert!(doc(&app, "C1").trashed);
+        assert!(!doc(&app, "B1").trashed);
+        assert_eq!(app.parent_ids(acct.id, "B1").unwrap(), vec!["F2"]);
+
+        // Deletions on the Drive side are propagated too.
+        let patch = File {
+            trashed: Some(true),
+            ..Default::default()
+        };
+        app.update_doc(&acct, "A1", patch, &[], &[]).unwrap();
+        assert_eq!(run(&mut app, &local), (0, 0, 0, 1, 0, 0));
+        assert_eq!(local_names(&local), vec!["Notes.pdf"]);
+        assert_eq!(run(&mut app, &local), (0, 0, 0, 0, 0, 0));
+    }
+
+    #[test]
+    fn exports_are_read_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut app, _account) = fixture_app(dir.path(), "mirror.json");
+        let local = dir.path().join("local");
+        assert_eq!(run(&mut app, &local), (4, 0, 0, 0, 0, 0));
+
+        // Local changes to an export are not uploaded ...
+        fs::write(local.join("Notes.pdf"), "%PDF-scribbled on\n").unwrap();
+        assert_eq!(run(&mut app, &local), (0, 0, 0, 0, 0, 0));
+        assert_eq!(read(&local, "Notes.pdf"), "%PDF-scribbled on\n");
+
+        // ... and are overwritten when the document changes.
+        let acct = account(&mut app);
+        let patch = File {
+            name: Some("Notes".to_owned()),
+            ..Default::default()
+        };
+        app.update_doc(&acct, "G1", patch, &[], &[]).unwrap();
+        assert_eq!(run(&mut app, &local), (1, 0, 0, 0, 0, 0));
+        assert_eq!(read(&local, "Notes.pdf"), "%PDF-notes\n");
+
+        // Deleting an export restores it, rather than trashing the document.
+        fs::remove_file(local.join("Notes.pdf")).unwrap();
+        assert_eq!(run(&mut app, &local), (1, 0, 0, 0, 0, 0));
+        assert_eq!(read(&local, "Notes.pdf"), "%PDF-notes\n");
+        assert!(!doc(&app, "G1").trashed);
+    }
+}
@@ -0,0 +1,374 @@
+// Copyright 2018 Peter Williams <peter@newton.cx>
+// Licensed under the MIT License.
+
+//! Machine-readable output of document information.
+//!
+//! The default output of the CLI is meant for humans, but it's nice to be
+//! able to feed document listings to other programs without screen-scraping.
+//! The `--format` option selects one of the formats implemented here.
+
+use serde::Serialize;
+use std::io::Write;
+
+use app::OutputFormat;
+use database::Doc;
+use errors::Result;
+
+/// A type that can be emitted in the machine-readable output formats.
+pub trait Record: Serialize {
+    /// The column headers for the delimited-text formats.
+    const COLUMNS: &'static [&'static str];
+
+    /// Get the fields of this record as strings, in the order given by
+    /// `COLUMNS`.
+    fn to_row(&self) -> Vec<String>;
+}
+
+/// The information about a document that we emit in machine-readable output.
+#[derive(Clone, Debug, Serialize)]
+pub struct DocRecord {
+    /// The unique identifier of the document.
+    pub id: String,
+
+    /// The name of the document.
+    pub name: String,
+
+    /// The MIME type of the document.
+    pub mime_type: String,
+
+    /// The last modification time, in RFC 3339 format.
+    pub modified_time: String,
+
+    /// The size of the document in bytes, if it has binary content.
+    pub size: Option<i32>,
+
+    /// Whether the document is starred.
+    pub starred: bool,
+
+    /// Whether the document is in the trash.
+    pub trashed: bool,
+
+    /// The email addresses of the accounts that the document belongs to.
+    pub accounts: Vec<String>,
+
+    /// The folder paths leading to the document, rendered as folder names
+    /// joined by " > ".
+    pub paths: Vec<String>,
+
+    /// A URL that can be used to open the document in a browser.
+    pub url: String,
+}
+
+impl DocRecord {
+    /// Create a new record.
+    ///
+    /// The account and path information have to be computed by the caller,
+    /// since they require database access.
+    pub fn new(doc: &Doc, accounts: Vec<String>, paths: Vec<String>) -> DocRecord {
+        DocRecord {
+            id: doc.id.clone(),
+            name: doc.name.clone(),
+            mime_type: doc.mime_type.clone(),
+            modified_time: doc.utc_mod_time().to_rfc3339(),
+            size: doc.size,
+            starred: doc.starred,
+            trashed: doc.trashed,
+            accounts,
+            paths,
+            url: doc.open_url(),
+        }
+    }
+}
+
+impl Record for DocRecord {
+    const COLUMNS: &'static [&'static str] = &[
+        "id",
+        "name",
+        "mime_type",
+        "modified_time",
+        "size",
+        "starred",
+        "trashed",
+        "accounts",
+        "paths",
+        "url",
+    ];
+
+    /// Multi-valued fields are joined with semicolons.
+    fn to_row(&self) -> Vec<String> {
+        vec![
+            self.id.clone(),
+            self.name.clone(),
+            self.mime_type.clone(),
+            self.modified_time.clone(),
+            self.size.map(|s| s.to_string()).unwrap_or_default(),
+            self.starred.to_string(),
+            self.trashed.to_string(),
+            self.accounts.join(";"),
+            self.paths.join(";"),
+            self.url.clone(),
+        ]
+    }
+}
+
+/// A copy of a document, as reported by `drorg dupes`.
+#[derive(Clone, Debug, Serialize)]
+pub struct DupeRecord {
+    /// The number of the group of copies that this one belongs to, starting
+    /// at 1.
+    pub group: usize,
+
+    /// How the copies in the group were matched: "checksum", "name" (for
+    /// native documents without sizes), or "name+size".
+    pub matched_by: String,
+
+    /// Whether this is the newest copy in its group.
+    pub newest: bool,
+
+    /// Information about the document itself.
+    #[serde(flatten)]
+    pub doc: DocRecord,
+}
+
+impl Record for DupeRecord {
+    const COLUMNS: &'static [&'static str] = &[
+        "group",
+        "matched_by",
+        "newest",
+        "id",
+        "name",
+        "mime_type",
+        "modified_time",
+        "size",
+        "starred",
+        "trashed",
+        "accounts",
+        "paths",
+        "url",
+    ];
+
+    fn to_row(&self) -> Vec<String> {
+        let mut row = vec![
+            self.group.to_string(),
+            self.matched_by.clone(),
+            self.newest.to_string(),
+        ];
+        row.extend(self.doc.to_row());
+        row
+    }
+}
+
+/// A document permission that violates the sharing policy, as reported by
+/// `drorg audit sharing`.
+#[derive(Clone, Debug, Serialize)]
+pub struct SharingViolationRecord {
+    /// The email address of the account that the document belongs to.
+    pub account: String,
+
+    /// The path of the folder containing the document, rendered as folder
+    /// names joined by " > ".
+    pub folder: String,
+
+    /// The unique identifier of the document.
+    pub id: String,
+
+    /// The name of the document.
+    pub name: String,
+
+    /// The role granted by the offending permission.
+    pub role: String,
+
+    /// The kind of grantee: "user", "group", "domain", or "anyone".
+    pub grantee_type: String,
+
+    /// A description of the grantee.
+    pub grantee: String,
+
+    /// Why the permission is a violation: "anyone" (public on the web),
+    /// "anyoneWithLink", or "externalDomain". Permissions whose grantees
+    /// aren't identified well enough to check, like users without email
+    /// addresses, are reported as "unknown" and aren't violations as such.
+    pub reason: String,
+
+    /// A URL that can be used to open the document in a browser.
+    pub url: String,
+}
+
+impl Record for SharingViolationRecord {
+    const COLUMNS: &'static [&'static str] = &[
+        "account",
+        "folder",
+        "id",
+        "name",
+        "role",
+        "grantee_type",
+        "grantee",
+        "reason",
+        "url",
+    ];
+
+    fn to_row(&self) -> Vec<String> {
+        vec![
+            self.account.clone(),
+            self.folder.clone(),
+            self.id.clone(),
+            self.name.clone(),
+            self.role.clone(),
+            self.grantee_type.clone(),
+            self.grantee.clone(),
+            self.reason.clone(),
+            self.url.clone(),
+        ]
+    }
+}
+
+/// Quote a field for CSV output, if needed.
+fn csv_quote(field: &str) -> String {
+    if field.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+/// Sanitize a field for TSV output.
+///
+/// TSV has no quoting convention, so we just replace problematic characters
+/// with spaces.
+fn tsv_clean(field: &str) -> String {
+    field.replace(&['\t', '\n', '\r'][..], " ")
+}
+
+/// Write a list of records in the specified format.
+///
+/// ## Panics
+///
+/// If *format* is `OutputFormat::Text`, which is not a machine-readable
+/// format and should be handled by the caller.
+pub fn write_records<R: Record, W: Write>(
+    format: &OutputFormat,
+    records: &[R],
+    mut dest: W,
+) -> Result<()> {
+    match *format {
+        OutputFormat::Text => panic!("text output is not handled by write_records"),
+
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut dest, records)?;
+            writeln!(dest)?;
+        }
+
+        OutputFormat::Jsonl => {
+            for rec in records {
+                serde_json::to_writer(&mut dest, rec)?;
+                writeln!(dest)?;
+            }
+        }
+
+        OutputFormat::Csv => {
+            writeln!(dest, "{}", R::COLUMNS.join(","))?;
+
+            for rec in records {
+                let row: Vec<_> = rec.to_row().iter().map(|f| csv_quote(f)).collect();
+                writeln!(dest, "{}", row.join(","))?;
+            }
+        }
+
+        OutputFormat::Tsv => {
+            writeln!(dest, "{}", R::COLUMNS.join("\t"))?;
+
+            for rec in records {
+                let row: Vec<_> = rec.to_row().iter().map(|f| tsv_clean(f)).collect();
+                writeln!(dest, "{}", row.join("\t"))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn record(name: &str) -> DocRecord {
+        let doc = Doc {
+            id: "D1".to_owned(),
+            name: name.to_owned(),
+            mime_type: "application/pdf".to_owned(),
+            modified_time: NaiveDate::from_ymd(2020, 3, 1).and_hms(12, 0, 0),
+            starred: true,
+            trashed: false,
+            size: Some(1000),
+            md5_checksum: None,
+            drive_id: None,
+            owner_email: None,
+            owner_name: None,
+        };
+
+        DocRecord::new(
+            &doc,
+            vec!["a@example.com".to_owned(), "b@example.com".to_owned()],
+            vec!["My Drive > Projects".to_owned()],
+        )
+    }
+
+    fn write(format: OutputFormat, records: &[DocRecord]) -> String {
+        let mut buf = Vec::new();
+        write_records(&format, records, &mut buf).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn json() {
+        let text = write(OutputFormat::Json, &[record("Report")]);
+        let value: serde_json::Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(value[0]["id"], "D1");
+        assert_eq!(value[0]["name"], "Report");
+        assert_eq!(value[0]["modified_time"], "2020-03-01T12:00:00+00:00");
+        assert_eq!(value[0]["size"], 1000);
+        assert_eq!(value[0]["starred"], true);
+        assert_eq!(value[0]["accounts"][1], "b@example.com");
+        assert_eq!(value[0]["paths"][0], "My Drive > Projects");
+
+        let text = write(OutputFormat::Jsonl, &[record("a"), record("b")]);
+        let names: Vec<_> = text
+            .lines()
+            .map(|l| serde_json::from_str::<serde_json::Value>(l).unwrap()["name"].clone())
+            .collect();
+        assert_eq!(names, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn csv() {
+        let text = write(OutputFormat::Csv, &[record("Report, \"final\"")]);
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], DocRecord::COLUMNS.join(","));
+        assert!(lines[1].starts_with(
+            "D1,\"Report, \"\"final\"\"\",application/pdf,2020-03-01T12:00:00+00:00,1000,true,\
+             false,a@example.com;b@example.com,My Drive > Projects,"
+        ));
+
+        let text = write(OutputFormat::Tsv, &[record("Report\tdraft")]);
+        assert_eq!(
+            text.lines().nth(1).unwrap().split('\t').nth(1),
+            Some("Report draft")
+        );
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(write(OutputFormat::Json, &[]).trim(), "[]");
+        assert_eq!(write(OutputFormat::Jsonl, &[]), "");
+        assert_eq!(
+            write(OutputFormat::Csv, &[]),
+            format!("{}\n", DocRecord::COLUMNS.join(","))
+        );
+        assert_eq!(
+            write(OutputFormat::Tsv, &[]),
+            format!("{}\n", DocRecord::COLUMNS.join("\t"))
+        );
+    }
+}
@@ -0,0 +1,1057 @@
+// Copyright 2018 Peter Williams <peter@newton.cx>
+// Licensed under the MIT License.
+
+//! The query language for document specifiers.
+//!
+//! Besides the special forms handled directly by `GetDocBuilder` (document
+//! IDs, `.`, `..`, and `%N`), a specifier is a query that is parsed into an
+//! expression tree and then compiled into a Diesel filter on the `docs`
+//! table, so that the database does the heavy lifting. The grammar is:
+//!
+//! ```text
+//! query := or
+//! or    := and ("or" and)*
+//! and   := not ("and"? not)*
+//! not   := ("not" | "-") not | atom
+//! atom  := "(" or ")" | term
+//! term  := WORD | FIELD ":" VALUE | FIELD OP VALUE
+//! ```
+//!
+//! Juxtaposed terms are implicitly and-ed together. A bare word matches
+//! documents whose names contain it, except for the keywords `starred` and
+//! `trashed`. Values may be quoted with double quotes to include spaces,
+//! parentheses, or keywords. A word is only split into a field and a value
+//! if it starts with one of the supported fields, so that names like
+//! `Re: meeting` can be searched for without quoting. The fields are:
+//!
+//! - `name:TEXT` (substring match) and `name=TEXT` (exact match)
+//! - `type:KIND`, where KIND is something like `folder`, `doc`, or `pdf`
+//! - `mime:TYPE`, where `*` acts as a wildcard
+//! - `is:starred`, `is:trashed`, and `is:shared-with-me`, which matches
+//!   documents that have been shared with an account but not added to its
+//!   My Drive, so that they aren't in any folder
+//! - `modified` with `<`, `<=`, `=`, `>=`, or `>` and a date (`2024-01-01`,
+//!   in local time) or an RFC 3339 timestamp
+//! - `size` with a comparison operator and a size such as `10M`
+//! - `in:FOLDER-SPEC`, matching the immediate children of the folder(s)
+//! - `account:EMAIL`
+//! - `owner:me`, matching documents owned by one of the logged-in accounts,
+//!   or `owner:EMAIL`
+//! - `shared:WHO`, where WHO is `anyone` (any link or public sharing),
+//!   `link` (anyone with the link), `public` (anyone on the web), an email
+//!   address, or a domain; this consults the cached permissions, which the
+//!   Drive API only reports for documents that an account can share, and
+//!   which are only fetched for documents in shared drives by `drorg audit
+//!   sharing`, so those don't match until it has been run
+
+use chrono::{DateTime, Duration, Local, NaiveDate, NaiveDateTime, TimeZone};
+use diesel::expression::BoxableExpression;
+use diesel::prelude::*;
+use diesel::sql_types::Bool;
+use diesel::sqlite::Sqlite;
+use std::fmt;
+
+use app::Application;
+use database::{self, FOLDER_MIME_TYPE};
+use errors::Result;
+use schema::docs;
+
+/// A comparison operator.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Cmp {
+    /// Less than.
+    Lt,
+
+    /// Less than or equal to.
+    Le,
+
+    /// Equal to; or, for `name`, an exact match.
+    Eq,
+
+    /// Greater than or equal to.
+    Ge,
+
+    /// Greater than.
+    Gt,
+
+    /// The field-value separator `:`.
+    Colon,
+}
+
+impl fmt::Display for Cmp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Cmp::Lt => "<",
+            Cmp::Le => "<=",
+            Cmp::Eq => "=",
+            Cmp::Ge => ">=",
+            Cmp::Gt => ">",
+            Cmp::Colon => ":",
+        })
+    }
+}
+
+/// A single condition on a document.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Term {
+    /// The name contains this text.
+    NameContains(String),
+
+    /// The name is exactly this text.
+    NameIs(String),
+
+    /// The MIME type matches this pattern, in which `*` is a wildcard.
+    Mime(String),
+
+    /// The document is starred.
+    Starred,
+
+    /// The document is in the trash.
+    Trashed,
+
+    /// The document has been shared with an account, but isn't in any of
+    /// its folders.
+    SharedWithMe,
+
+    /// The modification time compares to this value as specified. If the
+    /// boolean is true, the value was given as a date, not a timestamp.
+    Modified(Cmp, NaiveDateTime, bool),
+
+    /// The size compares to this value as specified.
+    Size(Cmp, i64),
+
+    /// The document is an immediate child of the folders matching this
+    /// specifier.
+    In(String),
+
+    /// The document is associated with the account having this email
+    /// address.
+    Account(String),
+
+    /// The document is shared with this grantee, according to the cached
+    /// permissions. Documents in shared drives have none until a sharing
+    /// audit fetches them.
+    Shared(String),
+
+    /// The document is owned by one of the logged-in accounts.
+    OwnedByMe,
+
+    /// The document is owned by the user with this email address.
+    Owner(String),
+}
+
+/// A parsed query expression.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Expr {
+    /// Both subexpressions must match.
+    And(Box<Expr>, Box<Expr>),
+
+    /// Either subexpression must match.
+    Or(Box<Expr>, Box<Expr>),
+
+    /// The subexpression must not match.
+    Not(Box<Expr>),
+
+    /// A single condition.
+    Term(Term),
+}
+
+impl Expr {
+    /// If this expression is nothing more than a bare name to search for,
+    /// get that name.
+    ///
+    /// Such queries are candidates for fuzzy matching.
+    pub fn name_pattern(&self) -> Option<&str> {
+        match self {
+            Expr::Term(Term::NameContains(text)) => Some(text),
+            _ => None,
+        }
+    }
+}
+
+/// A lexical token.
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Token {
+    LParen,
+    RParen,
+    Not,
+    And,
+    Or,
+
+    /// A word, possibly of the form FIELD OP VALUE.
+    Word {
+        /// The field name and operator, if present.
+        field: Option<(String, Cmp)>,
+
+        /// The value, with quoting removed.
+        value: String,
+
+        /// Whether any part of the value was quoted.
+        quoted: bool,
+    },
+}
+
+/// The names of the fields that can appear in terms. These must match the
+/// ones handled by `word_to_term`.
+const FIELDS: &[&str] = &[
+    "account", "in", "is", "mime", "modified", "mtime", "name", "owner", "shared", "size", "type",
+];
+
+/// Generate an error about a malformed specification.
+fn syntax_error(spec: &str, pos: usize, message: &str) -> failure::Error {
+    format_err!(
+        "cannot parse the specification \"{}\": {} (at character {})",
+        spec,
+        message,
+        pos + 1
+    )
+}
+
+/// Split a specification into tokens.
+///
+/// Each token is paired with the character position where it starts, for
+/// error reporting.
+fn tokenize(spec: &str) -> Result<Vec<(usize, Token)>> {
+    let chars: Vec<char> = spec.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        if c == '(' {
+            tokens.push((i, Token::LParen));
+            i += 1;
+            continue;
+        }
+
+        if c == ')' {
+            tokens.push((i, Token::RParen));
+            i += 1;
+            continue;
+        }
+
+        // A leading "-" negates the following term, unless it's the only
+        // thing in the word.
+        if c == '-' && i + 1 < chars.len() && !chars[i + 1].is_whitespace() {
+            tokens.push((i, Token::Not));
+            i += 1;
+            continue;
+        }
+
+        // Otherwise, we have a word.
+
+        let start = i;
+        let mut buf = String::new();
+        let mut field = None;
+        let mut quoted = false;
+
+        while i < chars.len() {
+            let c = chars[i];
+
+            if c.is_whitespace() || c == '(' || c == ')' {
+                break;
+            }
+
+            if c == '"' {
+                quoted = true;
+                i += 1;
+
+                loop {
+                    if i >= chars.len() {
+                        return Err(syntax_error(spec, start, "unterminated quotation"));
+                    }
+
+                    match chars[i] {
+                        '"' => {
+                            i += 1;
+                            break;
+                        }
+
+                        '\\' if i + 1 < chars.len() => {
+                            buf.push(chars[i + 1]);
+                            i += 2;
+                        }
+
+                        c => {
+                            buf.push(c);
+                            i += 1;
+                        }
+                    }
+                }
+
+                continue;
+            }
+
+            if field.is_none()
+                && !quoted
+                && !buf.is_empty()
+                && (c == ':' || c == '<' || c == '>' || c == '=')
+                && FIELDS.contains(&buf.to_lowercase().as_str())
+            {
+                let next_is_eq = i + 1 < chars.len() && chars[i + 1] == '=';
+
+                let (op, width) = match c {
+                    ':' => (Cmp::Colon, 1),
+                    '=' => (Cmp::Eq, 1),
+                    '<' if next_is_eq => (Cmp::Le, 2),
+                    '<' => (Cmp::Lt, 1),
+                    '>' if next_is_eq => (Cmp::Ge, 2),
+                    _ => (Cmp::Gt, 1),
+                };
+
+                field = Some((buf.to_lowercase(), op));
+                buf.clear();
+                i += width;
+                continue;
+            }
+
+            buf.push(c);
+            i += 1;
+        }
+
+        let token = if field.is_none() && !quoted {
+            match buf.to_lowercase().as_str() {
+                "and" => Token::And,
+                "or" => Token::Or,
+                "not" => Token::Not,
+                _ => Token::Word {
+                    field,
+                    value: buf,
+                    quoted,
+                },
+            }
+        } else {
+            Token::Word {
+                field,
+                value: buf,
+                quoted,
+            }
+        };
+
+        tokens.push((start, token));
+    }
+
+    Ok(tokens)
+}
+
+/// Parse a size such as `10M` into a number of bytes.
+///
+/// The suffixes are binary multiples, to match the way that sizes are
+/// printed.
+fn parse_size(text: &str) -> Option<i64> {
+    let lower = text.to_lowercase();
+    let trimmed = lower.trim_end_matches("ib").trim_end_matches('b');
+    let split = trimmed
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(trimmed.len());
+    let (number, suffix) = trimmed.split_at(split);
+    let number: f64 = number.parse().ok()?;
+
+    let multiplier = match suffix {
+        "" => 1.,
+        "k" => 1024.,
+        "m" => 1024. * 1024.,
+        "g" => 1024. * 1024. * 1024.,
+        "t" => 1024. * 1024. * 1024. * 1024.,
+        _ => return None,
+    };
+
+    Some((number * multiplier) as i64)
+}
+
+/// Get the (UTC) time at which a day starts in the local time zone.
+fn local_midnight(d: NaiveDate) -> NaiveDateTime {
+    let t = d.and_hms(0, 0, 0);
+
+    Local
+        .from_local_datetime(&t)
+        .earliest()
+        .map_or(t, |l| l.naive_utc())
+}
+
+/// Get the (UTC) time at which the local day starting at *t* ends.
+fn local_day_end(t: NaiveDateTime) -> NaiveDateTime {
+    let day = Local.from_utc_datetime(&t).naive_local().date();
+    local_midnight(day + Duration::days(1))
+}
+
+/// Parse a date or timestamp.
+///
+/// Returns the (UTC) time and whether the value was just a date. Dates are
+/// taken to start at midnight local time, since that's what the user means
+/// by "2024-01-01".
+pub fn parse_time(text: &str) -> Option<(NaiveDateTime, bool)> {
+    if let Ok(d) = NaiveDate::parse_from_str(text, "%Y-%m-%d") {
+        return Some((local_midnight(d), true));
+    }
+
+    DateTime::parse_from_rfc3339(text)
+        .ok()
+        .map(|t| (t.naive_utc(), false))
+}
+
+/// Map a friendly document kind to a MIME type pattern.
+fn mime_for_kind(kind: &str) -> Option<&'static str> {
+    Some(match kind {
+        "folder" | "dir" => FOLDER_MIME_TYPE,
+        "doc" | "document" => "application/vnd.google-apps.document",
+        "sheet" | "spreadsheet" => "application/vnd.google-apps.spreadsheet",
+        "slides" | "presentation" => "application/vnd.google-apps.presentation",
+        "form" => "application/vnd.google-apps.form",
+        "drawing" => "application/vnd.google-apps.drawing",
+        "shortcut" => "application/vnd.google-apps.shortcut",
+        "native" => "application/vnd.google-apps.*",
+        "pdf" => "application/pdf",
+        "image" => "image/*",
+        "video" => "video/*",
+        "audio" => "audio/*",
+        "text" => "text/*",
+        _ => return None,
+    })
+}
+
+/// Convert a word token into a term.
+fn word_to_term(
+    spec: &str,
+    pos: usize,
+    field: Option<(String, Cmp)>,
+    value: String,
+    quoted: bool,
+) -> Result<Term> {
+    let (field, op) = match field {
+        Some(f) => f,
+
+        None => {
+            if !quoted {
+                match value.to_lowercase().as_str() {
+                    "starred" => return Ok(Term::Starred),
+                    "trashed" => return Ok(Term::Trashed),
+                    _ => {}
+                }
+            }
+
+            return Ok(Term::NameContains(value));
+        }
+    };
+
+    let need_colon = |what: &str| -> Result<()> {
+        if op == Cmp::Colon {
+            Ok(())
+        } else {
+            Err(syntax_error(
+                spec,
+                pos,
+                &format!("the \"{}\" field must be followed by \":\"", what),
+            ))
+        }
+    };
+
+    if value.is_empty() {
+        return Err(syntax_error(
+            spec,
+            pos,
+            &format!("no value given for the \"{}\" field", field),
+        ));
+    }
+
+    match field.as_str() {
+        "name" => match op {
+            Cmp::Colon => Ok(Term::NameContains(value)),
+            Cmp::Eq => Ok(Term::NameIs(value)),
+            _ => Err(syntax_error(
+                spec,
+                pos,
+                "the \"name\" field must be followed by \":\" or \"=\"",
+            )),
+        },
+
+        "type" => {
+            need_colon("type")?;
+            let mime = mime_for_kind(&value.to_lowercase()).ok_or_else(|| {
+                syntax_error(
+                    spec,
+                    pos,
+                    &format!(
+                        "unrecognized document type \"{}\"; try folder, doc, sheet, \
+                         slides, form, drawing, shortcut, native, pdf, image, video, \
+                         audio, or text",
+                        value
+                    ),
+                )
+            })?;
+            Ok(Term::Mime(mime.to_owned()))
+        }
+
+        "mime" => {
+            need_colon("mime")?;
+            Ok(Term::Mime(value))
+        }
+
+        "is" => {
+            need_colon("is")?;
+
+            match value.to_lowercase().as_str() {
+                "starred" => Ok(Term::Starred),
+                "trashed" => Ok(Term::Trashed),
+                "shared-with-me" => Ok(Term::SharedWithMe),
+                _ => Err(syntax_error(
+                    spec,
+                    pos,
+                    &format!(
+                        "expected \"is:starred\", \"is:trashed\", or \"is:shared-with-me\", \
+                         not \"is:{}\"",
+                        value
+                    ),
+                )),
+            }
+        }
+
+        "modified" | "mtime" => {
+            let op = if op == Cmp::Colon { Cmp::Eq } else { op };
+            let (t, is_date) = parse_time(&value).ok_or_else(|| {
+                syntax_error(
+                    spec,
+                    pos,
+                    &format!(
+                        "cannot parse \"{}\" as a date (YYYY-MM-DD) or RFC 3339 timestamp",
+                        value
+                    ),
+                )
+            })?;
+            Ok(Term::Modified(op, t, is_date))
+        }
+
+        "size" => {
+            let op = if op == Cmp::Colon { Cmp::Eq } else { op };
+            let n = parse_size(&value).ok_or_else(|| {
+                syntax_error(
+                    spec,
+                    pos,
+                    &format!(
+                        "cannot parse \"{}\" as a size (e.g. 100, 15K, 10M, 2G)",
+                        value
+                    ),
+                )
+            })?;
+            Ok(Term::Size(op, n))
+        }
+
+        "in" => {
+            need_colon("in")?;
+            Ok(Term::In(value))
+        }
+
+        "account" => {
+            need_colon("account")?;
+            Ok(Term::Account(value))
+        }
+
+        "shared" => {
+            need_colon("shared")?;
+            Ok(Term::Shared(value))
+        }
+
+        "owner" => {
+            need_colon("owner")?;
+
+            if value.eq_ignore_ascii_case("me") {
+                Ok(Term::OwnedByMe)
+            } else {
+                Ok(Term::Owner(value))
+            }
+        }
+
+        other => Err(syntax_error(
+            spec,
+            pos,
+            &format!("unrecognized field \"{}\"", other),
+        )),
+    }
+}
+
+/// A recursive-descent parser over a list of tokens.
+struct Parser<'a> {
+    spec: &'a str,
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, t)| t)
+    }
+
+    fn location(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .map(|(p, _)| *p)
+            .unwrap_or_else(|| self.spec.chars().count())
+    }
+
+    fn parse_or(&mut self) -> Result<Expr> {
+        let mut expr = self.parse_and()?;
+
+        while self.peek() == Some(&Token::Or) {
+            self.pos += 1;
+            let rhs = self.parse_and()?;
+            expr = Expr::Or(Box::new(expr), Box::new(rhs));
+        }
+
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr> {
+        let mut expr = self.parse_not()?;
+
+        loop {
+            match self.peek() {
+                Some(Token::And) => {
+                    self.pos += 1;
+                }
+
+                Some(Token::Or) | Some(Token::RParen) | None => break,
+
+                // Implicit "and".
+                _ => {}
+            }
+
+            let rhs = self.parse_not()?;
+            expr = Expr::And(Box::new(expr), Box::new(rhs));
+        }
+
+        Ok(expr)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr> {
+        if self.peek() == Some(&Token::Not) {
+            self.pos += 1;
+            let inner = self.parse_not()?;
+            return Ok(Expr::Not(Box::new(inner)));
+        }
+
+        self.parse_atom()
+    }
+
+    fn parse_atom(&mut self) -> Result<Expr> {
+        let loc = self.location();
+
+        match self.tokens.get(self.pos).cloned() {
+            Some((_, Token::LParen)) => {
+                self.pos += 1;
+                let expr = self.parse_or()?;
+
+                if self.peek() != Some(&Token::RParen) {
+                    return Err(syntax_error(self.spec, self.location(), "expected \")\""));
+                }
+
+                self.pos += 1;
+                Ok(expr)
+            }
+
+            Some((
+                pos,
+                Token::Word {
+                    field,
+                    value,
+                    quoted,
+                },
+            )) => {
+                self.pos += 1;
+                Ok(Expr::Term(word_to_term(
+                    self.spec, pos, field, value, quoted,
+                )?))
+            }
+
+            Some((_, Token::RParen)) => Err(syntax_error(self.spec, loc, "unexpected \")\"")),
+            Some((_, Token::And)) => Err(syntax_error(self.spec, loc, "unexpected \"and\"")),
+            Some((_, Token::Or)) => Err(syntax_error(self.spec, loc, "unexpected \"or\"")),
+            Some((_, Token::Not)) => Err(syntax_error(self.spec, loc, "unexpected \"not\"")),
+            None => Err(syntax_error(
+                self.spec,
+                loc,
+                "unexpected end of specification",
+            )),
+        }
+    }
+}
+
+/// Parse a specification into a query expression.
+pub fn parse(spec: &str) -> Result<Expr> {
+    let tokens = tokenize(spec)?;
+
+    if tokens.is_empty() {
+        return Err(format_err!("empty document specification"));
+    }
+
+    let mut parser = Parser {
+        spec,
+        tokens,
+        pos: 0,
+    };
+
+    let expr = parser.parse_or()?;
+
+    if parser.pos < parser.tokens.len() {
+        return Err(syntax_error(spec, parser.location(), "unexpected \")\""));
+    }
+
+    Ok(expr)
+}
+
+/// The type of a compiled query: a boolean expression on the `docs` table.
+pub type DocFilter = Box<dyn BoxableExpression<docs::table, Sqlite, SqlType = Bool>>;
+
+/// Escape text for use in a SQL LIKE pattern with `\` as the escape
+/// character.
+pub fn escape_like(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        if c == '%' || c == '_' || c == '\\' {
+            escaped.push('\\');
+        }
+
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+sql_function! {
+    /// The SQLite function that converts text to lower case.
+    fn lower(x: diesel::sql_types::Nullable<diesel::sql_types::Text>)
+        -> diesel::sql_types::Nullable<diesel::sql_types::Text>;
+}
+
+/// A filter that never matches.
+fn never() -> DocFilter {
+    Box::new(diesel::dsl::sql::<Bool>("0"))
+}
+
+/// Compile a query expression into a Diesel filter.
+///
+/// This needs access to the application because `in:` terms contain nested
+/// specifiers, and `account:` terms have to be resolved to account IDs.
+pub fn compile(expr: &Expr, app: &mut Application) -> Result<DocFilter> {
+    Ok(match expr {
+        Expr::And(a, b) => Box::new(compile(a, app)?.and(compile(b, app)?)),
+        Expr::Or(a, b) => Box::new(compile(a, app)?.or(compile(b, app)?)),
+        Expr::Not(a) => Box::new(diesel::dsl::not(compile(a, app)?)),
+        Expr::Term(t) => compile_term(t, app)?,
+    })
+}
+
+/// Compile a comparison on the modification time.
+fn compile_modified(op: Cmp, t: NaiveDateTime, is_date: bool) -> DocFilter {
+    use schema::docs::dsl::*;
+
+    if !is_date {
+        return match op {
+            Cmp::Lt => Box::new(modified_time.lt(t)),
+            Cmp::Le => Box::new(modified_time.le(t)),
+            Cmp::Gt => Box::new(modified_time.gt(t)),
+            Cmp::Ge => Box::new(modified_time.ge(t)),
+            Cmp::Eq | Cmp::Colon => Box::new(modified_time.eq(t)),
+        };
+    }
+
+    // For dates, "=" means "sometime that day", and the other comparisons
+    // are adjusted to match. Not every day is 24 hours long.
+    let end = local_day_end(t);
+
+    match op {
+        Cmp::Lt => Box::new(modified_time.lt(t)),
+        Cmp::Le => Box::new(modified_time.lt(end)),
+        Cmp::Gt => Box::new(modified_time.ge(end)),
+        Cmp::Ge => Box::new(modified_time.ge(t)),
+        Cmp::Eq | Cmp::Colon => Box::new(modified_time.ge(t).and(modified_time.lt(end))),
+    }
+}
+
+fn compile_term(term: &Term, app: &mut Application) -> Result<DocFilter> {
+    use schema::docs::dsl::*;
+
+    Ok(match term {
+        Term::NameContains(text) => {
+            let pattern = format!("%{}%", escape_like(text));
+            Box::new(name.like(pattern).escape('\\'))
+        }
+
+        Term::NameIs(text) => Box::new(name.eq(text.clone())),
+
+        Term::Mime(pattern) => {
+            if pattern.contains('*') {
+                let pattern = escape_like(pattern).replace('*', "%");
+                Box::new(mime_type.like(pattern).escape('\\'))
+            } else {
+                Box::new(mime_type.eq(pattern.clone()))
+            }
+        }
+
+        Term::Starred => Box::new(starred.eq(true)),
+
+        Term::Trashed => Box::new(trashed.eq(true)),
+
+        Term::SharedWithMe => {
+            // Shared documents that haven't been added to an account's My
+            // Drive have no parents in that account, but neither do the root
+            // folders, which we rule out by ownership (My Drive) or ID
+            // (shared drives). Diesel can't express the correlated subquery.
+            Box::new(diesel::dsl::sql::<Bool>(
+                "docs.id IN (SELECT aa.doc_id FROM account_associations aa \
+                 WHERE NOT aa.owned_by_me AND NOT EXISTS (SELECT 1 FROM links l \
+                 WHERE l.account_id = aa.account_id AND l.child_id = aa.doc_id)) \
+                 AND docs.id NOT IN (SELECT id FROM drives)",
+            ))
+        }
+
+        Term::Modified(op, t, is_date) => compile_modified(*op, *t, *is_date),
+
+        Term::Size(op, n) => {
+            // The database column is only 32 bits wide.
+            if *n > i64::from(i32::MAX) {
+                return Ok(match op {
+                    Cmp::Lt | Cmp::Le => Box::new(size.is_not_null()),
+                    _ => never(),
+                });
+            }
+
+            let n = *n as i32;
+
+            match op {
+                Cmp::Lt => Box::new(size.lt(n)),
+                Cmp::Le => Box::new(size.le(n)),
+                Cmp::Gt => Box::new(size.gt(n)),
+                Cmp::Ge => Box::new(size.ge(n)),
+                Cmp::Eq | Cmp::Colon => Box::new(size.eq(n)),
+            }
+        }
+
+        Term::In(folder_spec) => {
+            let folder_ids: Vec<String> = app
+                .get_docs()
+                .process(folder_spec)?
+                .into_iter()
+                .filter(|d| d.is_folder())
+                .map(|d| d.id)
+                .collect();
+
+            if folder_ids.is_empty() {
+                return Err(format_err!(
+                    "the specification \"{}\" does not match any folders",
+                    folder_spec
+                ));
+            }
+
+            use schema::links;
+
+            Box::new(
+                id.eq_any(
+                    links::table
+                        .filter(links::parent_id.eq_any(folder_ids))
+                        .select(links::child_id),
+                ),
+            )
+        }
+
+        Term::Account(addr) => {
+            use schema::{account_associations, accounts};
+
+            let maybe_acct = accounts::table
+                .filter(accounts::email.eq(addr))
+                .first::<database::Account>(&app.conn)
+                .optional()?;
+
+            let acct = match maybe_acct {
+                Some(a) => a,
+                None => return Err(format_err!("no account with email \"{}\"", addr)),
+            };
+
+            Box::new(
+                id.eq_any(
+                    account_associations::table
+                        .filter(account_associations::account_id.eq(acct.id))
+                        .select(account_associations::doc_id),
+                ),
+            )
+        }
+
+        Term::Shared(who) => {
+            use schema::permissions as p;
+
+            let matching = p::table.select(p::doc_id).into_boxed();
+            let is_anyone = p::grantee_type.eq("anyone");
+
+            let matching = match who.to_lowercase().as_str() {
+                "anyone" => matching.filter(is_anyone),
+                "link" => matching.filter(is_anyone.and(p::allow_file_discovery.eq(false))),
+                "public" => matching.filter(is_anyone.and(p::allow_file_discovery.eq(true))),
+                // A document isn't "shared" with its owner. Addresses and
+                // domains are compared case-insensitively, and LIKE already
+                // is.
+                w if w.contains('@') => matching
+                    .filter(lower(p::email_address).eq(w.to_owned()))
+                    .filter(p::role.ne("owner")),
+                w => {
+                    let suffix = format!("%@{}", escape_like(w));
+                    matching
+                        .filter(
+                            lower(p::domain)
+                                .eq(w.to_owned())
+                                .or(p::email_address.like(suffix).escape('\\')),
+                        )
+                        .filter(p::role.ne("owner"))
+                }
+            };
+
+            Box::new(id.eq_any(matching))
+        }
+
+        Term::OwnedByMe => {
+            use schema::account_associations as aa;
+
+            Box::new(
+                id.eq_any(
+                    aa::table
+                        .filter(aa::owned_by_me.eq(true))
+                        .select(aa::doc_id),
+                ),
+            )
+        }
+
+        // LIKE is case-insensitive, which is what we want for emails.
+        Term::Owner(addr) => Box::new(owner_email.like(escape_like(addr)).escape('\\')),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(text: &str) -> Expr {
+        Expr::Term(Term::NameContains(text.to_owned()))
+    }
+
+    fn and(a: Expr, b: Expr) -> Expr {
+        Expr::And(Box::new(a), Box::new(b))
+    }
+
+    fn time(text: &str) -> NaiveDateTime {
+        parse_time(text).unwrap().0
+    }
+
+    /// Render a compiled time comparison as SQL, with the bound values
+    /// appended.
+    fn modified_sql(op: Cmp, text: &str) -> String {
+        let (t, is_date) = parse_time(text).unwrap();
+        let q = docs::table
+            .select(docs::id)
+            .filter(compile_modified(op, t, is_date));
+        diesel::debug_query::<Sqlite, _>(&q).to_string()
+    }
+
+    #[test]
+    fn parse_fields() {
+        assert_eq!(parse("name:foo").unwrap(), name("foo"));
+        assert_eq!(
+            parse("NAME=foo").unwrap(),
+            Expr::Term(Term::NameIs("foo".to_owned()))
+        );
+        assert_eq!(
+            parse("size>=10K").unwrap(),
+            Expr::Term(Term::Size(Cmp::Ge, 10240))
+        );
+        assert_eq!(
+            parse("modified<=2024-01-02T03:04:05Z").unwrap(),
+            Expr::Term(Term::Modified(Cmp::Le, time("2024-01-02T03:04:05Z"), false))
+        );
+        assert!(parse("type:nonesuch").is_err());
+        assert!(parse("size:").is_err());
+    }
+
+    #[test]
+    fn parse_colons_in_names() {
+        assert_eq!(
+            parse("Re: meeting").unwrap(),
+            and(name("Re:"), name("meeting"))
+        );
+        assert_eq!(parse("notes:2024").unwrap(), name("notes:2024"));
+        assert_eq!(parse("a<b").unwrap(), name("a<b"));
+        assert_eq!(
+            parse("\"name\":x").unwrap(),
+            name("name:x"),
+            "quoted words are never fields"
+        );
+    }
+
+    #[test]
+    fn parse_operators() {
+        assert_eq!(
+            parse("a or b c").unwrap(),
+            Expr::Or(Box::new(name("a")), Box::new(and(name("b"), name("c"))))
+        );
+        assert_eq!(
+            parse("-starred").unwrap(),
+            Expr::Not(Box::new(Expr::Term(Term::Starred)))
+        );
+        assert!(parse("(a").is_err());
+        assert!(parse("a)").is_err());
+    }
+
+    #[test]
+    fn compile_timestamps() {
+        let t = "2024-01-02T03:04:05Z";
+        assert!(modified_sql(Cmp::Lt, t).contains("`modified_time` < ?"));
+        assert!(modified_sql(Cmp::Le, t).contains("`modified_time` <= ?"));
+        assert!(modified_sql(Cmp::Gt, t).contains("`modified_time` > ?"));
+        assert!(modified_sql(Cmp::Ge, t).contains("`modified_time` >= ?"));
+        assert!(modified_sql(Cmp::Eq, t).contains("`modified_time` = ?"));
+        assert!(modified_sql(Cmp::Le, t).ends_with("binds: [2024-01-02T03:04:05]"));
+    }
+
+    #[test]
+    fn compile_dates() {
+        let d = "2024-01-02";
+        let start = local_midnight(NaiveDate::from_ymd(2024, 1, 2)).format("%Y-%m-%dT%H:%M:%S");
+        let end = local_midnight(NaiveDate::from_ymd(2024, 1, 3)).format("%Y-%m-%dT%H:%M:%S");
+
+        let le = modified_sql(Cmp::Le, d);
+        assert!(le.contains("`modified_time` < ?"));
+        assert!(le.ends_with(&format!("binds: [{}]", end)));
+
+        let gt = modified_sql(Cmp::Gt, d);
+        assert!(gt.contains("`modified_time` >= ?"));
+        assert!(gt.ends_with(&format!("binds: [{}]", end)));
+
+        let eq = modified_sql(Cmp::Eq, d);
+        assert!(eq.ends_with(&format!("binds: [{}, {}]", start, end)));
+    }
+
+    #[test]
+    fn dates_are_local() {
+        let (t, is_date) = parse_time("2024-07-01").unwrap();
+        assert!(is_date);
+
+        let local = Local.from_utc_datetime(&t);
+        assert_eq!(
+            local.naive_local(),
+            NaiveDate::from_ymd(2024, 7, 1).and_hms(0, 0, 0)
+        );
+        assert_eq!(
+            Local.from_utc_datetime(&local_day_end(t)).naive_local(),
+            NaiveDate::from_ymd(2024, 7, 2).and_hms(0, 0, 0)
+        );
+
+        // Timestamps carry their own offsets.
+        assert_eq!(
+            parse_time("2024-07-01T12:00:00+02:00").unwrap(),
+            (NaiveDate::from_ymd(2024, 7, 1).and_hms(10, 0, 0), false)
+        );
+    }
+}
@@ -2,6 +2,9 @@ table! {
     account_associations (doc_id, account_id) {
         doc_id -> Text,
         account_id -> Integer,
+        owned_by_me -> Bool,
+        sharing_user_email -> Nullable<Text>,
+        sharing_user_name -> Nullable<Text>,
     }
 }
 
@@ -12,6 +15,19 @@ table! {
     }
 }
 
+table! {
+    changes_log (id) {
+        id -> Integer,
+        account_id -> Integer,
+        doc_id -> Text,
+        doc_name -> Text,
+        kind -> Text,
+        old_value -> Nullable<Text>,
+        new_value -> Nullable<Text>,
+        timestamp -> Timestamp,
+    }
+}
+
 table! {
     docs (id) {
         id -> Text,
@@ -21,6 +37,18 @@ table! {
         starred -> Bool,
         trashed -> Bool,
         size -> Nullable<Integer>,
+        md5_checksum -> Nullable<Text>,
+        drive_id -> Nullable<Text>,
+        owner_email -> Nullable<Text>,
+        owner_name -> Nullable<Text>,
+    }
+}
+
+table! {
+    drives (account_id, id) {
+        account_id -> Integer,
+        id -> Text,
+        name -> Text,
     }
 }
 
@@ -40,9 +68,59 @@ table! {
     }
 }
 
+table! {
+    mirror_entries (mirror_id, path) {
+        mirror_id -> Integer,
+        path -> Text,
+        doc_id -> Text,
+        remote_modified_time -> Timestamp,
+        local_modified_time -> BigInt,
+        local_size -> BigInt,
+    }
+}
+
+table! {
+    mirrors (id) {
+        id -> Integer,
+        account_id -> Integer,
+        folder_id -> Text,
+        local_dir -> Text,
+        export_format -> Nullable<Text>,
+    }
+}
+
+table! {
+    permissions (doc_id, id) {
+        doc_id -> Text,
+        id -> Text,
+        grantee_type -> Text,
+        role -> Text,
+        email_address -> Nullable<Text>,
+        domain -> Nullable<Text>,
+        display_name -> Nullable<Text>,
+        allow_file_discovery -> Bool,
+    }
+}
+
 joinable!(account_associations -> accounts (account_id));
 joinable!(account_associations -> docs (doc_id));
+joinable!(changes_log -> accounts (account_id));
+joinable!(drives -> accounts (account_id));
 joinable!(links -> accounts (account_id));
 joinable!(listitems -> docs (doc_id));
+joinable!(mirror_entries -> mirrors (mirror_id));
+joinable!(mirrors -> accounts (account_id));
+joinable!(permissions -> docs (doc_id));
 
-allow_tables_to_appear_in_same_query!(account_associations, accounts, docs, links, listitems,);
+allow_tables_to_appear_in_same_query!(
+    account_associations,
+    accounts,
+    changes_log,
+    docs,
+    drives,
+    links,
+    listitems,
+    mirror_entries,
+    mirrors,
+    permissions,
+);
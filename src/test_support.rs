@@ -0,0 +1,78 @@
+// Copyright 2018 Peter Williams <peter@newton.cx>
+// Licensed under the MIT License.
+
+//! Helpers shared by the unit tests.
+//!
+//! The tests run the application against the JSON fixtures in `testdata/`,
+//! with a fresh database and account directory for each test, so that they
+//! need neither network access nor a logged-in user.
+
+use diesel::connection::SimpleConnection;
+use diesel::prelude::*;
+use std::fs;
+use std::path::Path;
+
+use accounts::{self, Account};
+use app::{Application, ApplicationOptions, MatchMode, OutputFormat, SyncOption};
+use config::Config;
+use database;
+use schema;
+
+/// Set up an application that uses the named fixture from `testdata/` and
+/// a fresh database in *dir*, with the fixture's account logged in the way
+/// `drorg login` would do it.
+///
+/// The account file is saved in *dir* too. That location is only in effect
+/// for the calling thread, which is the one that runs the test.
+pub fn fixture_app(dir: &Path, fixture: &str) -> (Application, Account) {
+    let accounts_dir = dir.join("accounts");
+    fs::create_dir_all(&accounts_dir).unwrap();
+    accounts::TEST_ACCOUNTS_DIR.with(|d| *d.borrow_mut() = Some(accounts_dir));
+
+    let db_path = dir.join("db.sqlite");
+    let conn = database::get_db_connection(Some(&db_path)).unwrap();
+    let mut migrations: Vec<_> = fs::read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/migrations"))
+        .unwrap()
+        .map(|e| e.unwrap().path().join("up.sql"))
+        .filter(|p| p.exists())
+        .collect();
+    migrations.sort();
+
+    for up in migrations {
+        conn.batch_execute(&fs::read_to_string(up).unwrap())
+            .unwrap();
+    }
+
+    let mut app = Application::initialize(ApplicationOptions {
+        sync: SyncOption::No,
+        format: OutputFormat::Text,
+        match_mode: Some(MatchMode::Strict),
+        fixture: Some(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("testdata")
+                .join(fixture),
+        ),
+        db: Some(db_path),
+    })
+    .unwrap();
+
+    // Don't let the settings of whoever runs the tests get in the way.
+    app.config = Config::default();
+
+    let mut account = Account::default();
+    let email = app.fetch_email_address(&mut account).unwrap();
+
+    diesel::insert_into(schema::accounts::table)
+        .values(&database::NewAccount::new(&email))
+        .execute(&app.conn)
+        .unwrap();
+
+    account.data.db_id = schema::accounts::table
+        .select(schema::accounts::id)
+        .first(&app.conn)
+        .unwrap();
+
+    app.acquire_change_page_token(&mut account).unwrap();
+    app.import_documents(&mut account).unwrap();
+    (app, account)
+}